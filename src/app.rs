@@ -2,36 +2,61 @@ use std::fs;
 use std::{thread, time};
 use termion::terminal_size;
 
+use crate::clipboard;
 use crate::config::Config;
 use crate::interface::Interface;
+use crate::options::Options;
 use crate::position::Position;
 
 use crate::views::command_view::CommandView;
-use crate::views::text_view::TextView;
+use crate::views::text_view::{TextCommand, TextView};
+use crate::views::undo_tree_view::UndoTreeView;
 use crate::views::View;
 
-#[derive(Debug, Copy, Clone)]
+const UNDO_TREE_WIDTH: u16 = 32;
+
+#[derive(Debug, Clone)]
 pub enum ApplicationCommand {
     Quit(bool),
+    Write(Option<String>),
+    WriteQuit(bool), // Write and quit if the write worked, false only writes a modified buffer
     FocusText,
     FocusCommand,
+    UndoTreeOpen,
+    UndoTreeMove(isize),
+    UndoTreeClose(bool), // Keep the previewed state if true, otherwise return to where we started
+    Set(String),         // Arguments of :set
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Focus {
+    Text,
+    Command,
+    UndoTree,
 }
 
 pub struct App {
+    fname: Option<String>,
+    win_sz: Position,
     txt_view: TextView,
     txt_pos: Position,
     txt_sz: Position,
     cmd_view: CommandView,
     cmd_pos: Position,
     cmd_sz: Position,
+    undo_view: UndoTreeView,
+    undo_pos: Position,
+    undo_sz: Position,
+    undo_origin: Option<usize>, // Undo state when the tree was opened, set while it is open
     interface: Interface,
+    options: Options,
     running: bool,
-    txt_focus: bool,
+    focus: Focus,
 }
 
 impl App {
     pub fn new(cfg: Config) -> Self {
-        let text = match cfg.fname {
+        let text = match &cfg.fname {
             Some(fname) => {
                 let mut txt = fs::read_to_string(fname).unwrap();
                 txt.pop();
@@ -40,7 +65,10 @@ impl App {
             None => "".to_string(),
         };
 
-        let text_view = TextView::new(&text);
+        let mut text_view = TextView::new(&text);
+        if let Some(fname) = &cfg.fname {
+            text_view.process_command(TextCommand::ReadOnlyRegister('%', fname.clone()));
+        }
 
         let win_sz = match terminal_size() {
             Ok(res) => res,
@@ -48,6 +76,11 @@ impl App {
         };
 
         Self {
+            fname: cfg.fname,
+            win_sz: Position {
+                row: win_sz.1,
+                col: win_sz.0,
+            },
             txt_view: text_view,
             txt_pos: Position { row: 0, col: 0 },
             txt_sz: Position {
@@ -63,9 +96,14 @@ impl App {
                 row: 1,
                 col: win_sz.0,
             },
+            undo_view: UndoTreeView::new(),
+            undo_pos: Position { row: 0, col: 0 },
+            undo_sz: Position { row: 0, col: 0 },
+            undo_origin: None,
             interface: Interface::new(),
+            options: Options::new(),
             running: true,
-            txt_focus: true,
+            focus: Focus::Text,
         }
     }
 
@@ -73,21 +111,39 @@ impl App {
         self.init_screen()?;
         while self.running {
             self.cmd_view.add_keystrokes(self.interface.get_keys());
-            while let Some(cmd) = self.cmd_view.get_app_command() {
-                self.process_command(cmd);
+            loop {
+                while let Some(cmd) = self.cmd_view.get_app_command() {
+                    self.process_command(cmd);
+                }
+                while let Some(cmd) = self.cmd_view.get_text_command() {
+                    self.txt_view
+                        .process_command(cmd);
+                }
+                // Keys from @x or :normal go back to the command view, which waits for them
+                match self.txt_view.take_playback() {
+                    Some(runs) => self.cmd_view.play_keys(runs),
+                    None => break,
+                }
+            }
+            if let Some(text) = self.txt_view.take_clipboard() {
+                self.copy_to_clipboard(&text)?;
             }
-            while let Some(cmd) = self.cmd_view.get_text_command() {
-                self.txt_view
-                    .process_command(cmd);
+            if let Some(msg) = self.txt_view.take_message() {
+                self.cmd_view.show_message(&msg);
+            }
+            if self.cmd_view.get_required_rows() != self.cmd_sz.row {
+                self.layout();
             }
-        
 
-            if self.txt_focus {
-                self.interface.set_cursor(self.txt_pos, &self.txt_view);
-            } else {
-                self.interface.set_cursor(self.cmd_pos, &self.cmd_view);
+            match self.focus {
+                Focus::Text => self.interface.set_cursor(self.txt_pos, &self.txt_view),
+                Focus::Command => self.interface.set_cursor(self.cmd_pos, &self.cmd_view),
+                Focus::UndoTree => self.interface.set_cursor(self.undo_pos, &self.undo_view),
             }
             self.interface.draw(self.txt_pos, &self.txt_view)?;
+            if self.undo_origin.is_some() {
+                self.interface.draw(self.undo_pos, &self.undo_view)?;
+            }
             self.interface.draw(self.cmd_pos, &self.cmd_view)?;
 
             thread::sleep(time::Duration::from_millis(30));
@@ -99,7 +155,7 @@ impl App {
     fn init_screen(&mut self) -> Result<(), Box<dyn std::error::Error>> {
         self.txt_view.set_size(self.txt_sz);
         self.cmd_view.set_size(self.cmd_sz);
-            
+
         self.interface.set_cursor(self.txt_pos, &self.txt_view);
         self.interface.draw(self.txt_pos, &self.txt_view)?;
         self.interface.draw(self.cmd_pos, &self.cmd_view)?;
@@ -107,12 +163,151 @@ impl App {
         Ok(())
     }
 
+    /// Split the window between the views, the command view grows upwards to fit long messages
+    /// and the undo tree takes a column on the right while it is open.
+    fn layout(&mut self) {
+        let cmd_rows = self.cmd_view.get_required_rows().min(self.win_sz.row - 1);
+        let undo_cols = if self.undo_origin.is_some() {
+            UNDO_TREE_WIDTH.min(self.win_sz.col / 2)
+        } else {
+            0
+        };
+
+        self.txt_sz = Position {
+            row: self.win_sz.row - cmd_rows,
+            col: self.win_sz.col - undo_cols,
+        };
+        self.undo_pos = Position {
+            row: 0,
+            col: self.win_sz.col - undo_cols,
+        };
+        self.undo_sz = Position {
+            row: self.win_sz.row - cmd_rows,
+            col: undo_cols,
+        };
+        self.cmd_pos = Position {
+            row: self.win_sz.row - cmd_rows,
+            col: 0,
+        };
+        self.cmd_sz = Position {
+            row: cmd_rows,
+            col: self.win_sz.col,
+        };
+
+        self.txt_view.set_size(self.txt_sz);
+        self.undo_view.set_size(self.undo_sz);
+        self.cmd_view.set_size(self.cmd_sz);
+    }
+
+    /// Write the text to a file, or the file being edited. Returns whether it was written.
+    fn write(&mut self, fname: Option<String>) -> bool {
+        let fname = match fname.or(self.fname.clone()) {
+            Some(fname) => fname,
+            None => {
+                self.cmd_view.show_message("E32: No file name");
+                return false;
+            }
+        };
+
+        let mut text = self.txt_view.get_text();
+        text.push('\n');
+        match fs::write(&fname, &text) {
+            Ok(()) => {
+                self.txt_view.mark_saved();
+                if self.fname.is_none() {
+                    self.txt_view.process_command(TextCommand::ReadOnlyRegister('%', fname.clone()));
+                    self.fname = Some(fname.clone());
+                }
+                let lines = text.matches('\n').count();
+                self.cmd_view.show_message(&format!("\"{fname}\" {lines}L, {}B written", text.len()));
+                true
+            }
+            Err(e) => {
+                self.cmd_view.show_message(&format!("E212: Can't open file for writing: {e}"));
+                false
+            }
+        }
+    }
+
+    /// Copy with OSC 52 and the copy command, so the text reaches the clipboard over ssh and in
+    /// terminals without OSC 52 alike.
+    fn copy_to_clipboard(&mut self, text: &str) -> Result<(), Box<dyn std::error::Error>> {
+        if self.options.osc52 {
+            self.interface.set_clipboard(text)?;
+        }
+        if !self.options.clipcopy.is_empty() {
+            if let Err(e) = clipboard::copy(&self.options.clipcopy, text) {
+                self.cmd_view.show_message(&e);
+            }
+        } else if !self.options.osc52 {
+            self.cmd_view.show_message("Clipboard: no copy command, see :set clipcopy");
+        }
+        Ok(())
+    }
+
+    /// Apply each argument of :set in turn, stopping at the first bad one. Queries are shown as a
+    /// message.
+    fn set_options(&mut self, args: &str) {
+        let mut shown = Vec::<String>::new();
+        let mut args = Options::split_args(args);
+        if args.is_empty() || args == ["all"] {
+            args = Options::NAMES.iter().map(|name| format!("{name}?")).collect();
+        }
+
+        for arg in args {
+            match self.options.set(&arg) {
+                Ok(Some(value)) => shown.push(value),
+                Ok(None) => (),
+                Err(e) => {
+                    shown.push(e);
+                    break;
+                }
+            }
+        }
+
+        self.txt_view.set_options(&self.options);
+        self.cmd_view.set_options(&self.options);
+        if !shown.is_empty() {
+            self.cmd_view.show_message(&shown.join("\n"));
+        }
+    }
+
     fn process_command(&mut self, cmd: ApplicationCommand) {
         match cmd {
             ApplicationCommand::Quit(true) => self.running = false, // Force quit
             ApplicationCommand::Quit(false) => self.running = false, // Quit if saved
-            ApplicationCommand::FocusText => self.txt_focus = true,
-            ApplicationCommand::FocusCommand => self.txt_focus = false,
+            ApplicationCommand::Write(fname) => {
+                self.write(fname);
+            }
+            ApplicationCommand::WriteQuit(always) => {
+                // A failed write keeps the editor open, so the text isn't lost
+                if (!always && !self.txt_view.is_modified()) || self.write(None) {
+                    self.running = false;
+                }
+            }
+            ApplicationCommand::FocusText => self.focus = Focus::Text,
+            ApplicationCommand::FocusCommand => self.focus = Focus::Command,
+            ApplicationCommand::UndoTreeOpen => {
+                self.undo_origin = Some(self.txt_view.undo_tree().current());
+                self.undo_view.set_tree(self.txt_view.undo_tree());
+                self.layout();
+                self.focus = Focus::UndoTree;
+            }
+            ApplicationCommand::UndoTreeMove(delta) => {
+                let change = self.undo_view.move_selection(delta);
+                self.txt_view.process_command(TextCommand::UndoJump(change));
+                self.undo_view.set_tree(self.txt_view.undo_tree());
+            }
+            ApplicationCommand::UndoTreeClose(keep) => {
+                if let Some(origin) = self.undo_origin.take() {
+                    if !keep {
+                        self.txt_view.process_command(TextCommand::UndoJump(origin));
+                    }
+                }
+                self.layout();
+                self.focus = Focus::Text;
+            }
+            ApplicationCommand::Set(args) => self.set_options(&args),
         }
     }
 }
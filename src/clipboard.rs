@@ -0,0 +1,87 @@
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+/// The escape sequence that asks the terminal to put text on the system clipboard. It works over
+/// ssh, and inside tmux it is wrapped so tmux passes it on to the outer terminal.
+pub fn osc52(text: &str, tmux: bool) -> String {
+    let sequence = format!("\x1b]52;c;{}\x07", base64(text.as_bytes()));
+    if tmux {
+        format!("\x1bPtmux;{}\x1b\\", sequence.replace('\x1b', "\x1b\x1b"))
+    } else {
+        sequence
+    }
+}
+
+/// Give text to an external copy command such as "wl-copy" on its standard input.
+pub fn copy(command: &str, text: &str) -> Result<(), String> {
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|e| format!("Clipboard: can't run {command}: {e}"))?;
+
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(text.as_bytes()).map_err(|e| format!("Clipboard: can't write to {command}: {e}"))?;
+    }
+    match child.wait() {
+        Ok(status) if status.success() => Ok(()),
+        Ok(status) => Err(format!("Clipboard: {command} failed with {status}")),
+        Err(e) => Err(format!("Clipboard: {command} failed: {e}")),
+    }
+}
+
+/// Read the clipboard from the standard output of an external paste command.
+pub fn paste(command: &str) -> Result<String, String> {
+    let output = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .map_err(|e| format!("Clipboard: can't run {command}: {e}"))?;
+
+    if !output.status.success() {
+        return Err(format!("Clipboard: {command} failed with {}", output.status));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).replace("\r\n", "\n"))
+}
+
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let n = chunk.iter().enumerate().fold(0u32, |n, (i, b)| n | (*b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * i) & 63) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn osc52_and_commands() {
+        assert_eq!(base64(b"Man"), "TWFu");
+        assert_eq!(base64(b"Ma"), "TWE=");
+        assert_eq!(base64(b"M"), "TQ==");
+        assert_eq!(osc52("hi", false), "\x1b]52;c;aGk=\x07");
+        assert_eq!(osc52("hi", true), "\x1bPtmux;\x1b\x1b]52;c;aGk=\x07\x1b\\");
+
+        let file = std::env::temp_dir().join(format!("rvim-clipboard-{}", std::process::id()));
+        let file = file.display();
+        copy(&format!("cat > {file}"), "one\ntwo").unwrap();
+        assert_eq!(paste(&format!("cat {file}")), Ok("one\ntwo".to_string()));
+        let _ = std::fs::remove_file(file.to_string());
+        assert!(paste("exit 1").is_err());
+    }
+}
@@ -5,8 +5,9 @@ use std::thread;
 use termion::input::TermRead;
 use termion::raw::{IntoRawMode, RawTerminal};
 use termion::screen::{AlternateScreen, IntoAlternateScreen};
-//use termion::style;
+use termion::{color, style};
 
+use crate::clipboard;
 use crate::position::Position;
 use crate::views::View;
 
@@ -24,6 +25,14 @@ pub enum CursorStyle {
     Underline,
 }
 
+/// How a cell of a view is drawn.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Highlight {
+    Normal,
+    Visual, // Selected text
+    Search, // A match of the last search, with 'hlsearch'
+}
+
 
 impl Interface {
     pub fn new() -> Self {
@@ -64,10 +73,33 @@ impl Interface {
         write!(self.stdout, "{}", termion::cursor::Hide)?;
         let sz = view.get_size();
         let text = view.get_view();
+        let highlights = view.get_highlights();
 
         for r in 0..sz.row {
             write!(self.stdout, "{}", termion::cursor::Goto(pos.col + 1, pos.row + r + 1))?;
-            write!(self.stdout, "{}", text[((r * sz.col) as usize)..((r * sz.col + sz.col -1) as usize)].iter().collect::<String>())?;
+            let row = (r * sz.col) as usize..(r * sz.col + sz.col - 1) as usize;
+            if highlights.is_empty() {
+                write!(self.stdout, "{}", text[row].iter().collect::<String>())?;
+                continue;
+            }
+
+            // Switch style at the start of each run of cells with the same highlight
+            let mut current = Highlight::Normal;
+            for i in row {
+                let highlight = highlights.get(i).copied().unwrap_or(Highlight::Normal);
+                if highlight != current {
+                    match highlight {
+                        Highlight::Normal => write!(self.stdout, "{}", style::Reset)?,
+                        Highlight::Visual => write!(self.stdout, "{}{}", style::Reset, style::Invert)?,
+                        Highlight::Search => {
+                            write!(self.stdout, "{}{}{}", style::Reset, color::Bg(color::Yellow), color::Fg(color::Black))?
+                        }
+                    }
+                    current = highlight;
+                }
+                write!(self.stdout, "{}", text[i])?;
+            }
+            write!(self.stdout, "{}", style::Reset)?;
 /*            for c in 0..sz.col {
                 write!(
                     self.stdout,
@@ -87,11 +119,26 @@ impl Interface {
         Ok(())
     }
 
+    /// Put text on the system clipboard through the terminal. The escape sequence draws nothing,
+    /// so the screen is left as it is.
+    pub fn set_clipboard(&mut self, text: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let tmux = std::env::var_os("TMUX").is_some();
+        write!(self.stdout, "{}", clipboard::osc52(text, tmux))?;
+        self.stdout.flush()?;
+        Ok(())
+    }
+
     pub fn get_keys(&mut self) -> Vec<termion::event::Key> {
         self.io_rx.try_iter().collect()
     }
 }
 
+impl Default for Interface {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Drop for Interface {
     fn drop(&mut self) {}
 }
@@ -4,10 +4,18 @@ use crate::app::App;
 use crate::config::Config;
 
 pub mod app;
+pub mod clipboard;
 pub mod config;
 pub mod interface;
+pub mod motions;
+pub mod numbers;
+pub mod options;
 pub mod piece_table;
 pub mod position;
+pub mod regex;
+pub mod registers;
+pub mod search;
+pub mod undo;
 pub mod views;
 
 pub fn run(cfg: Config) -> Result<(), Box<dyn Error>> {
@@ -0,0 +1,732 @@
+use crate::options::Keywords;
+use crate::piece_table::PieceTable;
+
+pub mod objects;
+
+/// Cursor motions. Each motion maps a position in the text to a new position, so the same
+/// motion can move the cursor or mark out the text an operator acts on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Motion {
+    Left,
+    Right,
+    Up,
+    Down,
+    GotoLine(usize), // gg, or G with a count, lines are numbered from 1
+    LastLine,        // G
+    WordForward(bool), // w, or W for WORDs when true
+    WordBackward(bool), // b and B
+    WordEnd(bool),   // e and E
+    WordEndBackward(bool), // ge and gE
+    ChangeWord(bool), // cw and cW on a word, which only go to the end of it
+    LineStart,       // 0
+    FirstNonBlank,   // ^
+    LineEnd,         // $
+    LastNonBlank,    // g_
+    Column,          // |, the count is the column
+    ScreenTop,       // H
+    ScreenMiddle,    // M
+    ScreenBottom,    // L
+    NextLine,        // + and Enter
+    PrevLine,        // -
+    // f, F, t and T. A repeat by ; or , skips a match next to the cursor for t and T, so
+    // repeating t doesn't get stuck.
+    FindChar { target: char, forward: bool, till: bool, repeat: bool },
+    RepeatFind(bool), // ; or , when true, replaced by the last FindChar before use
+    Sentence(bool),   // ) forward or ( backward
+    Paragraph(bool),  // } forward or { backward
+    // ]] and [[ go to a '{' in the first column, ][ and [] to a '}'
+    Section { forward: bool, brace: char },
+    SearchPrompt(bool), // / or ? when false, replaced by SearchNext once the pattern is typed
+    SearchNext(bool),   // n, or N when true which searches the other way, found by the text view
+    // * and #, search for the keyword under the cursor, or with g* and g# also inside longer words
+    SearchWord { forward: bool, whole: bool },
+    Declaration(bool), // gd, the first use of the keyword in the function, or gD in the file when true
+}
+
+/// How an operator treats the text between the cursor and the end of a motion.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MotionKind {
+    Exclusive, // The character at the end of the motion is not included
+    Inclusive,
+    Linewise,
+}
+
+/// What motions need to know besides the cursor position.
+pub struct MotionContext<'a> {
+    pub text: &'a PieceTable,
+    pub keywords: &'a Keywords,
+    pub top: usize,     // First line shown on the screen
+    pub height: usize,  // Number of lines the screen can show
+    pub operator: bool, // The motion marks out text for an operator
+}
+
+impl Motion {
+    pub fn kind(&self) -> MotionKind {
+        match self {
+            Motion::Left
+            | Motion::Right
+            | Motion::WordForward(_)
+            | Motion::WordBackward(_)
+            | Motion::LineStart
+            | Motion::FirstNonBlank
+            | Motion::Column
+            | Motion::RepeatFind(_)
+            | Motion::Sentence(_)
+            | Motion::Paragraph(_)
+            | Motion::Section { .. }
+            | Motion::SearchPrompt(_)
+            | Motion::SearchNext(_)
+            | Motion::SearchWord { .. }
+            | Motion::Declaration(_)
+            | Motion::FindChar { forward: false, .. } => MotionKind::Exclusive,
+            Motion::WordEnd(_)
+            | Motion::WordEndBackward(_)
+            | Motion::ChangeWord(_)
+            | Motion::LineEnd
+            | Motion::LastNonBlank
+            | Motion::FindChar { forward: true, .. } => MotionKind::Inclusive,
+            Motion::Up
+            | Motion::Down
+            | Motion::GotoLine(_)
+            | Motion::LastLine
+            | Motion::ScreenTop
+            | Motion::ScreenMiddle
+            | Motion::ScreenBottom
+            | Motion::NextLine
+            | Motion::PrevLine => MotionKind::Linewise,
+        }
+    }
+
+    /// Index the motion moves to from idx when repeated count times, or None if the motion
+    /// can't move at all.
+    pub fn apply(&self, ctx: &MotionContext, idx: usize, count: usize) -> Option<usize> {
+        let text = ctx.text;
+        let line = text.get_line_number(idx);
+        let line_start = text.get_line_offset(line).unwrap();
+        let line_end = line_start + text.get_line_length(line).unwrap();
+        let col = idx - line_start;
+        let last_line = text.lines() - 1;
+        let count = count.max(1);
+
+        match self {
+            Motion::Left => {
+                if idx == line_start {
+                    None
+                } else {
+                    Some(idx.saturating_sub(count).max(line_start))
+                }
+            }
+            Motion::Right => {
+                if idx >= line_end {
+                    None
+                } else {
+                    Some(idx.saturating_add(count).min(line_end))
+                }
+            }
+            Motion::Up => {
+                if line == 0 {
+                    None
+                } else {
+                    Some(line_column(text, line.saturating_sub(count), col))
+                }
+            }
+            Motion::Down => {
+                if line >= last_line {
+                    None
+                } else {
+                    Some(line_column(text, line.saturating_add(count).min(last_line), col))
+                }
+            }
+            Motion::GotoLine(n) => Some(first_non_blank(text, n.saturating_sub(1).min(last_line))),
+            Motion::LastLine => Some(first_non_blank(text, last_line)),
+            Motion::WordForward(big) => {
+                let mut cursor = TextCursor { ctx, idx, big: *big };
+                cursor.forward_word(count, ctx.operator);
+                (cursor.idx != idx).then_some(cursor.idx)
+            }
+            Motion::WordBackward(big) => {
+                let mut cursor = TextCursor { ctx, idx, big: *big };
+                cursor.backward_word(count);
+                (cursor.idx != idx).then_some(cursor.idx)
+            }
+            Motion::WordEnd(big) | Motion::ChangeWord(big) => {
+                let mut cursor = TextCursor { ctx, idx, big: *big };
+                cursor.end_word(count, matches!(self, Motion::ChangeWord(_)), false);
+                (cursor.idx != idx || matches!(self, Motion::ChangeWord(_))).then_some(cursor.idx)
+            }
+            Motion::WordEndBackward(big) => {
+                let mut cursor = TextCursor { ctx, idx, big: *big };
+                cursor.backward_end_word(count);
+                (cursor.idx != idx).then_some(cursor.idx)
+            }
+            Motion::LineStart => Some(line_start),
+            Motion::FirstNonBlank => Some(first_non_blank(text, line)),
+            Motion::LineEnd | Motion::LastNonBlank => {
+                let line = line.saturating_add(count - 1);
+                if line > last_line {
+                    return None;
+                }
+                let start = text.get_line_offset(line).unwrap();
+                let chars = text.get_line(line).unwrap();
+                let len = match self {
+                    Motion::LineEnd => chars.len(),
+                    _ => chars.len() - chars.iter().rev().take_while(|c| c.is_whitespace()).count(),
+                };
+                Some(start + len.saturating_sub(1))
+            }
+            Motion::Column => Some(line_start + (count - 1).min((line_end - line_start).saturating_sub(1))),
+            Motion::ScreenTop | Motion::ScreenMiddle | Motion::ScreenBottom => {
+                let top = ctx.top.min(last_line);
+                let bottom = (top + ctx.height.max(1) - 1).min(last_line);
+                let line = match self {
+                    Motion::ScreenTop => top.saturating_add(count - 1).min(bottom),
+                    Motion::ScreenMiddle => top + (bottom - top) / 2,
+                    _ => bottom.saturating_sub(count - 1).max(top),
+                };
+                Some(first_non_blank(text, line))
+            }
+            Motion::NextLine => {
+                if line >= last_line {
+                    None
+                } else {
+                    Some(first_non_blank(text, line.saturating_add(count).min(last_line)))
+                }
+            }
+            Motion::PrevLine => {
+                if line == 0 {
+                    None
+                } else {
+                    Some(first_non_blank(text, line.saturating_sub(count)))
+                }
+            }
+            Motion::FindChar { target, forward, till, repeat } => {
+                let chars = text.get_line(line).unwrap();
+                let mut col = col as isize;
+                let mut skip = *till && *repeat && count == 1;
+                for _ in 0..count {
+                    loop {
+                        col += if *forward { 1 } else { -1 };
+                        if col < 0 || col as usize >= chars.len() {
+                            return None;
+                        }
+                        if chars[col as usize] == *target && !skip {
+                            break;
+                        }
+                        skip = false;
+                    }
+                }
+                if *till {
+                    col += if *forward { -1 } else { 1 };
+                }
+                Some(line_start + col as usize)
+            }
+            Motion::RepeatFind(_)
+            | Motion::SearchPrompt(_)
+            | Motion::SearchNext(_)
+            | Motion::SearchWord { .. }
+            | Motion::Declaration(_) => None,
+            Motion::Sentence(forward) => {
+                let mut cursor = TextCursor { ctx, idx, big: false };
+                cursor.sentence(*forward, count).then_some(cursor.idx).filter(|i| *i != idx)
+            }
+            Motion::Paragraph(forward) => find_paragraph(text, line, *forward, count, None, false),
+            Motion::Section { forward, brace } => {
+                // An operator going forward to a '{' also stops at a '}', and includes its line
+                let both = ctx.operator && *forward && *brace == '{';
+                find_paragraph(text, line, *forward, count, Some(*brace), both)
+            }
+        }
+    }
+}
+
+/// Index of col in line, clamped to the end of the line.
+pub fn line_column(text: &PieceTable, line: usize, col: usize) -> usize {
+    text.get_line_offset(line).unwrap() + col.min(text.get_line_length(line).unwrap())
+}
+
+/// Index of the first non-blank character in line, or the end of the line if it is blank.
+pub fn first_non_blank(text: &PieceTable, line: usize) -> usize {
+    let start = text.get_line_offset(line).unwrap();
+    let line_chars = text.get_line(line).unwrap();
+    start + line_chars.iter().take_while(|c| c.is_whitespace()).count()
+}
+
+/// vim's default 'paragraphs' and 'sections', pairs of characters naming nroff macros.
+const PARAGRAPH_MACROS: &str = "IPLPPPQPP TPHPLIPpLpItpplpipbp";
+const SECTION_MACROS: &str = "SHNHH HUnhsh";
+
+/// Whether the text after a '.' starts with one of the macros.
+fn in_macro(macros: &str, line: &[char]) -> bool {
+    let s0 = line.first().copied();
+    let s1 = line.get(1).copied();
+    let macros: Vec<char> = macros.chars().collect();
+    macros.chunks(2).any(|m| {
+        let m1 = m.get(1).copied().unwrap_or(' ');
+        // A space in the macro name matches a space or the end of the line
+        (Some(m[0]) == s0 || (m[0] == ' ' && matches!(s0, None | Some(' '))))
+            && (Some(m1) == s1 || (m1 == ' ' && (s0.is_none() || matches!(s1, None | Some(' ')))))
+    })
+}
+
+/// Whether a line starts a paragraph, or with brace a section.
+fn starts_paragraph(text: &PieceTable, line: usize, brace: Option<char>, both: bool) -> bool {
+    let chars = text.get_line(line).unwrap();
+    match chars.first() {
+        None => brace.is_none(),
+        Some('\u{c}') => true,
+        Some(c) if Some(*c) == brace || (both && *c == '}') => true,
+        Some('.') => in_macro(SECTION_MACROS, &chars[1..]) || (brace.is_none() && in_macro(PARAGRAPH_MACROS, &chars[1..])),
+        Some(_) => false,
+    }
+}
+
+/// The paragraph and section motions, moving count boundaries from line.
+fn find_paragraph(text: &PieceTable, line: usize, forward: bool, count: usize, brace: Option<char>, both: bool) -> Option<usize> {
+    let last = text.lines() - 1;
+    let mut current = line;
+    for remaining in (0..count).rev() {
+        let mut skipped = false;
+        let mut first = true;
+        loop {
+            // A boundary only counts once the motion has passed some text
+            if text.get_line_length(current).unwrap() > 0 {
+                skipped = true;
+            }
+            if !first && skipped && starts_paragraph(text, current, brace, both) {
+                break;
+            }
+            first = false;
+
+            if (forward && current == last) || (!forward && current == 0) {
+                if remaining > 0 {
+                    return None;
+                }
+                break;
+            }
+            current = if forward { current + 1 } else { current - 1 };
+        }
+    }
+
+    if both && current < last && text.get_line(current).unwrap().first() == Some(&'}') {
+        current += 1;
+    }
+
+    let start = text.get_line_offset(current).unwrap();
+    if current == last && forward && brace != Some('}') {
+        // Past the end of the last line, so an operator includes all of it
+        return Some(start + text.get_line_length(current).unwrap());
+    }
+    Some(start)
+}
+
+/// Walks over the text a character at a time for the word and sentence motions. The newline
+/// ending each line is a position of its own, as is the end of the text, and both count as white
+/// space.
+struct TextCursor<'a> {
+    ctx: &'a MotionContext<'a>,
+    idx: usize,
+    big: bool, // WORDs, everything that isn't white space is one class
+}
+
+impl TextCursor<'_> {
+    fn char(&self) -> Option<char> {
+        (self.idx < self.ctx.text.len()).then(|| self.ctx.text.index(self.idx))
+    }
+
+    /// 0 for white space, 1 for punctuation and 2 for keyword characters.
+    fn class(&self) -> u8 {
+        match self.char() {
+            None | Some(' ') | Some('\t') | Some('\n') => 0,
+            Some(_) if self.big => 1,
+            Some(c) if self.ctx.keywords.contains(c) => 2,
+            Some(_) => 1,
+        }
+    }
+
+    fn on_empty_line(&self) -> bool {
+        let text = self.ctx.text;
+        (self.idx == 0 || text.index(self.idx - 1) == '\n') && matches!(self.char(), None | Some('\n'))
+    }
+
+    fn on_last_line(&self) -> bool {
+        self.ctx.text.get_line_number(self.idx) + 1 >= self.ctx.text.lines()
+    }
+
+    /// Move forward one position. Returns -1 at the end of the text, 1 when moving onto the next
+    /// line, 2 when moving onto the end of a line and 0 otherwise.
+    fn inc(&mut self) -> i8 {
+        match self.char() {
+            None => -1,
+            Some('\n') => {
+                self.idx += 1;
+                1
+            }
+            Some(_) => {
+                self.idx += 1;
+                if matches!(self.char(), None | Some('\n')) {
+                    2
+                } else {
+                    0
+                }
+            }
+        }
+    }
+
+    /// Move back one position. Returns -1 at the start of the text, 1 when moving onto the
+    /// previous line and 0 otherwise.
+    fn dec(&mut self) -> i8 {
+        if self.idx == 0 {
+            return -1;
+        }
+        self.idx -= 1;
+        if self.ctx.text.index(self.idx) == '\n' {
+            1
+        } else {
+            0
+        }
+    }
+
+    /// The character under the cursor, with '\0' at the end of a line or the text.
+    fn gchar(&self) -> char {
+        match self.char() {
+            None | Some('\n') => '\0',
+            Some(c) => c,
+        }
+    }
+
+    fn at_line_start(&self) -> bool {
+        self.idx == 0 || self.ctx.text.index(self.idx - 1) == '\n'
+    }
+
+    /// Move left within the line, false at the start of it.
+    fn one_left(&mut self) -> bool {
+        if self.at_line_start() {
+            return false;
+        }
+        self.idx -= 1;
+        true
+    }
+
+    /// Go back to the start of the word or white space under the cursor, within the line.
+    fn back_in_line(&mut self) {
+        let class = self.class();
+        while !self.at_line_start() {
+            self.dec();
+            if self.class() != class {
+                self.inc();
+                break;
+            }
+        }
+    }
+
+    /// Whether the cursor is within the indent of its line.
+    fn in_indent(&self) -> bool {
+        self.idx < first_non_blank(self.ctx.text, self.ctx.text.get_line_number(self.idx))
+    }
+
+    /// Go back to the start of the white space before the cursor.
+    fn find_first_blank(&mut self) {
+        while self.decl() != -1 {
+            if !matches!(self.gchar(), ' ' | '\t') {
+                self.incl();
+                break;
+            }
+        }
+    }
+
+    /// Move over count sentences, going back to the white space before the last one. The
+    /// cursor starts at the start of a sentence if at_start is true.
+    fn sentence_forward(&mut self, count: usize, mut at_start: bool) {
+        // At the end of the text the moves only go back and forth, so past twice its length
+        // all that matters about a count is whether it is odd or even
+        let most = 2 * (self.ctx.text.len() + 2);
+        let count = if count > most { most + count % 2 } else { count };
+        for remaining in (0..count).rev() {
+            self.sentence(true, 1);
+            if at_start {
+                self.find_first_blank();
+            }
+            if remaining == 0 || at_start {
+                self.decl();
+            }
+            at_start = !at_start;
+        }
+    }
+
+    /// Like inc, but skip over the end of a line that isn't empty.
+    fn incl(&mut self) -> i8 {
+        let r = self.inc();
+        if r >= 1 && !self.at_line_start() {
+            return self.inc();
+        }
+        r
+    }
+
+    /// Like dec, but skip over the end of a line that isn't empty.
+    fn decl(&mut self) -> i8 {
+        let r = self.dec();
+        if r == 1 && !self.at_line_start() {
+            return self.dec();
+        }
+        r
+    }
+
+    /// ( and ). A sentence ends at a '.', '!' or '?' followed by the end of the line or white
+    /// space, optionally with closing ')', ']', '"' or '\'' in between. Paragraph and section
+    /// boundaries are also sentence boundaries. Returns false if the motion fails.
+    fn sentence(&mut self, forward: bool, count: usize) -> bool {
+        let text = self.ctx.text;
+        for remaining in (0..count).rev() {
+            let start = self.idx;
+            let mut no_skip = false;
+            let mut found = false;
+
+            if self.gchar() == '\0' {
+                // On an empty line, skip to a line that isn't
+                loop {
+                    let r = if forward { self.incl() } else { self.decl() };
+                    if r == -1 || self.gchar() != '\0' {
+                        break;
+                    }
+                }
+                found = forward;
+            } else if forward && self.at_line_start() && starts_paragraph(text, text.get_line_number(self.idx), None, false) {
+                let line = text.get_line_number(self.idx);
+                if line + 1 >= text.lines() {
+                    return false;
+                }
+                self.idx = text.get_line_offset(line + 1).unwrap();
+                found = true;
+            } else if !forward {
+                self.decl();
+            }
+
+            if !found {
+                // Go back to the previous character that isn't white space or punctuation
+                let mut found_dot = false;
+                loop {
+                    let c = self.gchar();
+                    if !(c == ' ' || c == '\t' || ".!?)]\"'".contains(c)) || c == '\0' {
+                        break;
+                    }
+                    let mut before = TextCursor { ctx: self.ctx, idx: self.idx, big: false };
+                    if before.decl() == -1 || (forward && before.on_empty_line()) || found_dot {
+                        break;
+                    }
+                    if ".!?".contains(c) {
+                        found_dot = true;
+                    }
+                    if ")]\"'".contains(c) && !".!?)]\"'".contains(before.gchar()) {
+                        break;
+                    }
+                    self.decl();
+                }
+
+                // Find the end of the sentence
+                let start_line = text.get_line_number(self.idx);
+                loop {
+                    let c = self.gchar();
+                    if c == '\0' || (self.at_line_start() && starts_paragraph(text, text.get_line_number(self.idx), None, false)) {
+                        let line = text.get_line_number(self.idx);
+                        if !forward && line != start_line {
+                            self.idx = text.get_line_offset(line + 1).unwrap_or(self.idx);
+                        }
+                        break;
+                    }
+                    if ".!?".contains(c) {
+                        let mut after = TextCursor { ctx: self.ctx, idx: self.idx, big: false };
+                        let mut r;
+                        loop {
+                            r = after.inc();
+                            if r == -1 || !")]\"'".contains(after.gchar()) || after.gchar() == '\0' {
+                                break;
+                            }
+                        }
+                        if r == -1 || matches!(after.gchar(), ' ' | '\t' | '\0') {
+                            self.idx = after.idx;
+                            if self.gchar() == '\0' {
+                                self.inc();
+                            }
+                            break;
+                        }
+                    }
+                    let r = if forward { self.incl() } else { self.decl() };
+                    if r == -1 {
+                        if remaining > 0 {
+                            return false;
+                        }
+                        no_skip = true;
+                        break;
+                    }
+                }
+            }
+
+            // Skip white space to the start of the sentence
+            while !no_skip && matches!(self.gchar(), ' ' | '\t') {
+                if self.incl() == -1 {
+                    break;
+                }
+            }
+            // A move that went nowhere goes nowhere again, however large the count
+            if self.idx == start {
+                break;
+            }
+        }
+        true
+    }
+
+    /// Move past characters of a class, returns true if the start or end of the text was hit.
+    fn skip_class(&mut self, class: u8, forward: bool) -> bool {
+        while self.class() == class {
+            if (if forward { self.inc() } else { self.dec() }) == -1 {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// w. An operator stops at the end of the line rather than going on to the next word. Returns
+    /// false if the end of the text was hit before moving count words.
+    fn forward_word(&mut self, count: usize, operator: bool) -> bool {
+        for n in (0..count).rev() {
+            let start_class = self.class();
+            let last_line = self.on_last_line();
+            let i = self.inc();
+            if i == -1 || (i >= 1 && last_line) {
+                return false;
+            }
+            if i >= 1 && operator && n == 0 {
+                return true;
+            }
+
+            // Go past the end of the current word, then on to the next non-blank. An empty line
+            // counts as a word.
+            if start_class != 0 {
+                while self.class() == start_class {
+                    let i = self.inc();
+                    if i == -1 || (i >= 1 && operator && n == 0) {
+                        return true;
+                    }
+                }
+            }
+            while self.class() == 0 && !self.on_empty_line() {
+                let i = self.inc();
+                if i == -1 || (i >= 1 && operator && n == 0) {
+                    return true;
+                }
+            }
+        }
+        true
+    }
+
+    /// b
+    fn backward_word(&mut self, count: usize) {
+        for _ in 0..count {
+            if self.dec() == -1 {
+                return;
+            }
+
+            // Skip the white space before the word, stopping on an empty line
+            while self.class() == 0 {
+                if self.on_empty_line() {
+                    break;
+                }
+                if self.dec() == -1 {
+                    return;
+                }
+            }
+            if self.class() == 0 {
+                continue;
+            }
+
+            if self.skip_class(self.class(), false) {
+                return;
+            }
+            self.inc();
+        }
+    }
+
+    /// e. With stop the cursor stays put if it is already at the end of a word, as cw does, and
+    /// with empty an empty line counts as a word. Returns false if the end of the text was hit.
+    fn end_word(&mut self, count: usize, mut stop: bool, empty: bool) -> bool {
+        for _ in 0..count {
+            let start_class = self.class();
+            if self.inc() == -1 {
+                return false;
+            }
+
+            if self.class() == start_class && start_class != 0 {
+                // In the middle of a word, go to its end
+                if self.skip_class(start_class, true) {
+                    return false;
+                }
+            } else if !stop || start_class == 0 {
+                // At the end of a word, go to the end of the next one
+                while self.class() == 0 {
+                    if empty && self.on_empty_line() {
+                        break;
+                    }
+                    if self.inc() == -1 {
+                        return false;
+                    }
+                }
+                if self.class() == 0 {
+                    stop = false;
+                    continue;
+                }
+                if self.skip_class(self.class(), true) {
+                    return false;
+                }
+            }
+            self.dec();
+            stop = false;
+        }
+        true
+    }
+
+    /// ge
+    fn backward_end_word(&mut self, count: usize) {
+        for _ in 0..count {
+            let start_class = self.class();
+            if self.dec() == -1 {
+                return;
+            }
+
+            if start_class != 0 {
+                while self.class() == start_class {
+                    if self.dec() == -1 {
+                        return;
+                    }
+                }
+            }
+            while self.class() == 0 && !self.on_empty_line() {
+                if self.dec() == -1 {
+                    return;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::options::Options;
+
+    #[test]
+    fn sentences_and_paragraphs() {
+        let text = PieceTable::new("Hi.  How? Fine (ok.) Yes\nmore\n\npara two.\nend");
+        let options = Options::new();
+        let ctx = MotionContext { text: &text, keywords: &options.iskeyword, top: 0, height: 10, operator: false };
+
+        let forward: Vec<usize> = [0, 5, 10, 21, 30, 31].iter().map(|i| Motion::Sentence(true).apply(&ctx, *i, 1).unwrap()).collect();
+        assert_eq!(forward, [5, 10, 21, 30, 31, 41]);
+        assert_eq!(Motion::Sentence(false).apply(&ctx, 31, 2), Some(21));
+        assert_eq!(Motion::Sentence(false).apply(&ctx, 0, 1), None);
+
+        assert_eq!(Motion::Paragraph(true).apply(&ctx, 3, 1), Some(30));
+        assert_eq!(Motion::Paragraph(true).apply(&ctx, 3, 2), Some(44));
+        assert_eq!(Motion::Paragraph(true).apply(&ctx, 3, 3), None);
+        assert_eq!(Motion::Paragraph(false).apply(&ctx, 42, 1), Some(30));
+    }
+}
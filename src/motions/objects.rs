@@ -0,0 +1,473 @@
+use super::{starts_paragraph, MotionContext, MotionKind, TextCursor};
+use crate::piece_table::PieceTable;
+
+/// Characters that escape a quote inside a string, as vim's 'quoteescape'.
+const QUOTE_ESCAPE: &str = "\\";
+
+/// Text objects, which select text around the cursor for an operator. Inner objects (`i`) leave
+/// out the white space or delimiters that around objects (`a`) include.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextObject {
+    Word { big: bool, around: bool },                 // iw aw iW aW
+    Sentence { around: bool },                        // is as
+    Paragraph { around: bool },                       // ip ap
+    Block { open: char, close: char, around: bool },  // i( a( i{ a{ i[ a[ i< a<
+    Quote { quote: char, around: bool },              // i" a" i' a' i` a`
+    Tag { around: bool },                             // it at
+}
+
+impl TextObject {
+    /// The text selected around idx as a start, end and kind, with the end treated the same way
+    /// as the end of a motion. None if there is no such object around idx.
+    pub fn select(&self, ctx: &MotionContext, idx: usize, count: usize) -> Option<(usize, usize, MotionKind)> {
+        let count = count.max(1);
+        match self {
+            TextObject::Word { big, around } => select_word(ctx, idx, count, *big, *around),
+            TextObject::Sentence { around } => select_sentence(ctx, idx, count, *around),
+            TextObject::Paragraph { around } => select_paragraph(ctx.text, idx, count, *around),
+            TextObject::Block { open, close, around } => select_block(ctx, idx, count, *open, *close, *around),
+            TextObject::Quote { quote, around } => select_quote(ctx.text, idx, count, *quote, *around),
+            TextObject::Tag { around } => select_tag(ctx, idx, count, *around),
+        }
+    }
+}
+
+fn select_word(ctx: &MotionContext, idx: usize, count: usize, big: bool, around: bool) -> Option<(usize, usize, MotionKind)> {
+    let mut cursor = TextCursor { ctx, idx, big };
+    let mut inclusive = true;
+    let mut include_white = false;
+
+    // The word or white space under the cursor
+    cursor.back_in_line();
+    let mut start = cursor.idx;
+    if (cursor.class() == 0) == around {
+        if !cursor.end_word(1, true, true) {
+            return None;
+        }
+    } else {
+        // Go on to the start of the next word and back up to the end of the white space. A word
+        // at the end of a line ends there.
+        cursor.forward_word(1, true);
+        if cursor.at_line_start() {
+            cursor.decl();
+        } else {
+            cursor.one_left();
+        }
+        include_white = around;
+    }
+
+    // Each further count adds a word or the white space after it
+    for remaining in (0..count - 1).rev() {
+        inclusive = true;
+        if cursor.incl() == -1 {
+            return None;
+        }
+        if around != (cursor.class() == 0) {
+            if !cursor.forward_word(1, true) && remaining > 0 {
+                return None;
+            }
+            if !cursor.one_left() {
+                inclusive = false;
+            }
+        } else if !cursor.end_word(1, true, true) {
+            return None;
+        }
+    }
+
+    // Without white space after the word take the white space before it instead, so "daw"
+    // works on the last word of a sentence. Indent is never included.
+    if include_white && (cursor.class() != 0 || (cursor.at_line_start() && !inclusive)) {
+        let mut before = TextCursor { ctx, idx: start, big };
+        if before.one_left() {
+            before.back_in_line();
+            if before.class() == 0 && !before.at_line_start() {
+                start = before.idx;
+            }
+        }
+    }
+
+    let kind = if inclusive { MotionKind::Inclusive } else { MotionKind::Exclusive };
+    Some((start, cursor.idx, kind))
+}
+
+fn select_sentence(ctx: &MotionContext, idx: usize, count: usize, around: bool) -> Option<(usize, usize, MotionKind)> {
+    let white = |c: char| c == ' ' || c == '\t';
+    let mut start = TextCursor { ctx, idx, big: false };
+    let mut cursor = TextCursor { ctx, idx, big: false };
+    cursor.sentence(true, 1);
+
+    // Starting on white space just before a sentence selects that white space first
+    let mut pos = TextCursor { ctx, idx, big: false };
+    while white(pos.gchar()) {
+        if pos.incl() == -1 {
+            break;
+        }
+    }
+    let start_blank = pos.idx == cursor.idx;
+    if start_blank {
+        start.find_first_blank();
+    } else {
+        cursor.sentence(false, 1);
+        start.idx = cursor.idx;
+    }
+
+    let n = if around { count.saturating_mul(2) } else { count - start_blank as usize };
+    if n > 0 {
+        cursor.sentence_forward(n, true);
+    } else {
+        cursor.decl();
+    }
+
+    // Around a sentence includes the white space after it, or before it if there is none after
+    if around {
+        if start_blank {
+            cursor.find_first_blank();
+            if white(cursor.gchar()) {
+                cursor.decl();
+            }
+        } else if !white(cursor.gchar()) {
+            start.find_first_blank();
+        }
+    }
+
+    // Include the newline after the sentence if there is one
+    let kind = if cursor.incl() == -1 { MotionKind::Inclusive } else { MotionKind::Exclusive };
+    Some((start.idx, cursor.idx, kind))
+}
+
+fn select_paragraph(text: &PieceTable, idx: usize, count: usize, around: bool) -> Option<(usize, usize, MotionKind)> {
+    let last = text.lines() - 1;
+    let blank = |line: usize| text.get_line(line).unwrap().iter().all(|c| c.is_whitespace());
+
+    // Back to the start of the paragraph, or of the blank lines the cursor is in
+    let mut first = text.get_line_number(idx);
+    let white_in_front = blank(first);
+    while first > 0 {
+        if white_in_front {
+            if !blank(first - 1) {
+                break;
+            }
+        } else if blank(first - 1) || starts_paragraph(text, first, None, false) {
+            break;
+        }
+        first -= 1;
+    }
+
+    // Each count is a paragraph or a run of blank lines, around a paragraph also takes the
+    // blank lines after it
+    let mut end = first;
+    while end <= last && blank(end) {
+        end += 1;
+    }
+    let mut end = end as isize - 1;
+    let mut n = count;
+    if !around && white_in_front {
+        n -= 1;
+    }
+    for remaining in (0..n).rev() {
+        if end == last as isize {
+            return None;
+        }
+        let do_white = !around && blank((end + 1) as usize);
+        if around || !do_white {
+            end += 1;
+            while (end as usize) < last && !blank(end as usize + 1) && !starts_paragraph(text, end as usize + 1, None, false) {
+                end += 1;
+            }
+        }
+        if remaining == 0 && white_in_front && around {
+            break;
+        }
+        if around || do_white {
+            while (end as usize) < last && blank(end as usize + 1) {
+                end += 1;
+            }
+        }
+    }
+
+    // Without blank lines after the paragraph, take the ones before it instead
+    let end = end.max(first as isize) as usize;
+    if !white_in_front && !blank(end) && around {
+        while first > 0 && blank(first - 1) {
+            first -= 1;
+        }
+    }
+
+    Some((text.get_line_offset(first).unwrap(), text.get_line_offset(end).unwrap(), MotionKind::Linewise))
+}
+
+/// Whether the character at idx is escaped by an odd number of backslashes before it.
+fn is_escaped(text: &PieceTable, idx: usize) -> bool {
+    let mut n = 0;
+    while idx > n && text.index(idx - n - 1) == '\\' {
+        n += 1;
+    }
+    n % 2 == 1
+}
+
+/// The unmatched open bracket before idx, or with forward the unmatched close bracket after it.
+/// Escaped brackets are skipped.
+fn find_unmatched(text: &PieceTable, idx: usize, open: char, close: char, forward: bool) -> Option<usize> {
+    let (find, nest) = if forward { (close, open) } else { (open, close) };
+    let mut depth = 0;
+    let mut i = idx;
+    loop {
+        if forward {
+            i += 1;
+            if i >= text.len() {
+                return None;
+            }
+        } else {
+            i = i.checked_sub(1)?;
+        }
+
+        let c = text.index(i);
+        if (c != find && c != nest) || is_escaped(text, i) {
+            continue;
+        }
+        if c == nest {
+            depth += 1;
+        } else if depth == 0 {
+            return Some(i);
+        } else {
+            depth -= 1;
+        }
+    }
+}
+
+fn select_block(ctx: &MotionContext, idx: usize, count: usize, open: char, close: char, around: bool) -> Option<(usize, usize, MotionKind)> {
+    let text = ctx.text;
+    let mut cursor = TextCursor { ctx, idx, big: false };
+
+    // Starting on the open bracket selects its block. The indent before a '{' is skipped.
+    if open == '{' {
+        while cursor.in_indent() {
+            if cursor.inc() != 0 {
+                break;
+            }
+        }
+    }
+    if cursor.char() == Some(open) {
+        cursor.idx += 1;
+    }
+
+    let mut start = cursor.idx;
+    for _ in 0..count {
+        start = find_unmatched(text, start, open, close, false)?;
+    }
+    let end = find_unmatched(text, start, open, close, true)?;
+
+    let mut start = TextCursor { ctx, idx: start, big: false };
+    let mut end = TextCursor { ctx, idx: end, big: false };
+    if around {
+        return Some((start.idx, end.idx, MotionKind::Inclusive));
+    }
+
+    // Leave out the brackets, and the indent before a close bracket on a line of its own
+    start.incl();
+    let mut start_of_line = end.at_line_start();
+    end.decl();
+    while end.in_indent() {
+        start_of_line = true;
+        if end.decl() != 0 {
+            break;
+        }
+    }
+
+    if start_of_line {
+        end.incl();
+        Some((start.idx, end.idx, MotionKind::Exclusive))
+    } else if start.idx <= end.idx {
+        Some((start.idx, end.idx, MotionKind::Inclusive))
+    } else {
+        // Nothing between the brackets
+        Some((start.idx, start.idx, MotionKind::Exclusive))
+    }
+}
+
+/// Column of the next quote in line from col, skipping quotes after an escape character.
+fn find_next_quote(line: &[char], mut col: usize, quote: char, escape: &str) -> Option<usize> {
+    loop {
+        let c = *line.get(col)?;
+        if escape.contains(c) {
+            col += 1;
+        } else if c == quote {
+            return Some(col);
+        }
+        col += 1;
+    }
+}
+
+/// Column of the previous quote in line before col, or 0 if there is none.
+fn find_prev_quote(line: &[char], mut col: usize, quote: char, escape: &str) -> usize {
+    while col > 0 {
+        col -= 1;
+        let mut n = 0;
+        while col > n && escape.contains(line[col - n - 1]) {
+            n += 1;
+        }
+        if n % 2 == 1 {
+            col -= n;
+        } else if line[col] == quote {
+            break;
+        }
+    }
+    col
+}
+
+fn select_quote(text: &PieceTable, idx: usize, count: usize, quote: char, around: bool) -> Option<(usize, usize, MotionKind)> {
+    let line = text.get_line_number(idx);
+    let line_start = text.get_line_offset(line).unwrap();
+    let chars = text.get_line(line).unwrap();
+    let col = idx - line_start;
+
+    let (mut start, mut end);
+    if chars.get(col) == Some(&quote) {
+        // On a quote, which could open or close a string. Pair up the quotes from the start of
+        // the line to find out.
+        start = 0;
+        loop {
+            start = find_next_quote(&chars, start, quote, "")?;
+            if start > col {
+                return None;
+            }
+            end = find_next_quote(&chars, start + 1, quote, QUOTE_ESCAPE)?;
+            if start <= col && col <= end {
+                break;
+            }
+            start = end + 1;
+        }
+    } else {
+        // Find the quote before the cursor, or the first one after it
+        start = find_prev_quote(&chars, col, quote, QUOTE_ESCAPE);
+        if chars.get(start) != Some(&quote) {
+            start = find_next_quote(&chars, start, quote, "")?;
+        }
+        end = find_next_quote(&chars, start + 1, quote, QUOTE_ESCAPE)?;
+    }
+
+    // Around a string includes the white space after it, or before it if there is none after
+    let white = |c: Option<&char>| matches!(c, Some(' ') | Some('\t'));
+    if around {
+        if white(chars.get(end + 1)) {
+            while white(chars.get(end + 1)) {
+                end += 1;
+            }
+        } else {
+            while start > 0 && white(chars.get(start - 1)) {
+                start -= 1;
+            }
+        }
+    }
+
+    // A count of 2 includes the quotes, but not the white space
+    if around || count > 1 {
+        Some((line_start + start, line_start + end + 1, MotionKind::Exclusive))
+    } else {
+        Some((line_start + start + 1, line_start + end, MotionKind::Exclusive))
+    }
+}
+
+/// An XML or HTML tag, from its '<' to its '>'.
+struct Tag {
+    open: usize,
+    close: usize,
+    name: String,
+    end_tag: bool,
+}
+
+/// Every start and end tag in the text. Self closing tags, comments and declarations are left
+/// out.
+fn find_tags(text: &PieceTable) -> Vec<Tag> {
+    let chars: Vec<char> = text.slice(0, text.len()).chars().collect();
+    let mut tags = Vec::<Tag>::new();
+    let mut i = 0;
+    while i < chars.len() {
+        if chars[i] != '<' {
+            i += 1;
+            continue;
+        }
+
+        let end_tag = chars.get(i + 1) == Some(&'/');
+        let name_start = i + 1 + end_tag as usize;
+        let name: String = chars[name_start.min(chars.len())..]
+            .iter()
+            .take_while(|c| !c.is_whitespace() && **c != '>' && **c != '/' && **c != '<')
+            .collect();
+        let close = match chars[i + 1..].iter().position(|c| *c == '>') {
+            Some(n) => i + 1 + n,
+            None => break,
+        };
+        if name.is_empty() || name.starts_with(['!', '?']) || chars[close - 1] == '/' {
+            i += 1;
+            continue;
+        }
+
+        tags.push(Tag { open: i, close, name: name.to_lowercase(), end_tag });
+        i = close + 1;
+    }
+    tags
+}
+
+fn select_tag(ctx: &MotionContext, idx: usize, count: usize, around: bool) -> Option<(usize, usize, MotionKind)> {
+    let tags = find_tags(ctx.text);
+
+    // Pair each end tag with the nearest open start tag of the same name. Start tags without an
+    // end, such as HTML's <br>, are dropped when an enclosing tag ends.
+    let mut open = Vec::<usize>::new();
+    let mut pairs = Vec::<(usize, usize)>::new();
+    for (i, tag) in tags.iter().enumerate() {
+        if !tag.end_tag {
+            open.push(i);
+        } else if let Some(n) = open.iter().rposition(|s| tags[*s].name == tag.name) {
+            pairs.push((open[n], i));
+            open.truncate(n);
+        }
+    }
+
+    // Skip the indent, then treat the cursor on a start tag as just after it and on an end tag
+    // as just before it
+    let mut cursor = TextCursor { ctx, idx, big: false };
+    while cursor.in_indent() {
+        if cursor.inc() != 0 {
+            break;
+        }
+    }
+    let mut pos = cursor.idx;
+    if let Some(tag) = tags.iter().find(|t| t.open <= pos && pos <= t.close) {
+        pos = if tag.end_tag { tag.open.saturating_sub(1) } else { tag.close };
+    }
+
+    let mut enclosing: Vec<&(usize, usize)> = pairs.iter().filter(|(s, e)| tags[*s].open < pos && pos < tags[*e].open).collect();
+    enclosing.sort_by_key(|(s, _)| std::cmp::Reverse(tags[*s].open));
+    let (start, end) = enclosing.get(count - 1)?;
+    let (start, end) = (&tags[*start], &tags[*end]);
+
+    if around {
+        Some((start.open, end.close, MotionKind::Inclusive))
+    } else {
+        Some((start.close + 1, end.open, MotionKind::Exclusive))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::options::Options;
+
+    #[test]
+    fn nested_objects() {
+        let text = PieceTable::new("f(a, \"x \\\" (y)\", [b]) <p><i>z</i></p>");
+        let options = Options::new();
+        let ctx = MotionContext { text: &text, keywords: &options.iskeyword, top: 0, height: 10, operator: true };
+        let select = |object: TextObject, idx: usize, count: usize| object.select(&ctx, idx, count).map(|(s, e, _)| text.slice(s, e));
+
+        let inner_parens = TextObject::Block { open: '(', close: ')', around: false };
+        assert_eq!(select(inner_parens, 2, 1).as_deref(), Some("a, \"x \\\" (y)\", [b"));
+        assert_eq!(select(TextObject::Block { open: '[', close: ']', around: true }, 19, 1).as_deref(), Some("[b"));
+        assert_eq!(select(TextObject::Quote { quote: '"', around: false }, 7, 1).as_deref(), Some("x \\\" (y)"));
+        assert_eq!(select(TextObject::Tag { around: false }, 30, 1).as_deref(), Some("z"));
+        assert_eq!(select(TextObject::Tag { around: false }, 30, 2).as_deref(), Some("<i>z</i>"));
+        assert_eq!(select(inner_parens, 28, 1), None);
+    }
+}
@@ -0,0 +1,122 @@
+/// The ways a number can be written, besides decimal the 'nrformats' option turns them on.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Radix {
+    Decimal,
+    Hex,    // 0x1f
+    Binary, // 0b101
+    Octal,  // 017
+}
+
+/// Add to the first number in a line that ends after `col`, as Ctrl-A and Ctrl-X do. Returns
+/// the columns the number took up and its new text. Zero padding is kept, and hex digits keep
+/// the case of the rightmost letter.
+pub fn add_to_number(chars: &[char], col: usize, delta: i64, nrformats: &str) -> Option<(usize, usize, String)> {
+    let formats: Vec<&str> = nrformats.split(',').collect();
+    let (start, digits, end, radix) = find_number(chars, col, &formats)?;
+    let text: String = chars[digits..end].iter().collect();
+
+    let new = match radix {
+        Radix::Decimal => {
+            let negative = start < digits;
+            let value = text.parse::<u64>().unwrap_or(u64::MAX) as i128;
+            // Decimal numbers stop at the limits of a signed 64 bit number rather than wrapping
+            let value = if negative { -value } else { value } + delta as i128;
+            let value = value.clamp(i64::MIN.into(), i64::MAX.into());
+            let width = if text.starts_with('0') { text.len() } else { 0 };
+            let sign = if value < 0 { "-" } else { "" };
+            format!("{sign}{:0width$}", value.unsigned_abs())
+        }
+        // Numbers that aren't decimal have no sign, and wrap around
+        _ => {
+            let base = match radix {
+                Radix::Hex => 16,
+                Radix::Binary => 2,
+                _ => 8,
+            };
+            let value = u64::from_str_radix(&text, base).unwrap_or(u64::MAX).wrapping_add(delta as u64);
+            let width = text.len();
+            let prefix: String = chars[start..digits].iter().collect();
+            let upper = text.chars().rev().find(|c| c.is_ascii_alphabetic()).is_some_and(|c| c.is_ascii_uppercase());
+            match radix {
+                Radix::Hex if upper => format!("{prefix}{value:0width$X}"),
+                Radix::Hex => format!("{prefix}{value:0width$x}"),
+                Radix::Binary => format!("{prefix}{value:0width$b}"),
+                _ => format!("{prefix}{value:0width$o}"),
+            }
+        }
+    };
+    Some((start, end, new))
+}
+
+/// The first number that ends after `col`, as its start including any prefix or minus sign,
+/// the start of its digits, its end and its radix.
+fn find_number(chars: &[char], col: usize, formats: &[&str]) -> Option<(usize, usize, usize, Radix)> {
+    let digits_from = |from: usize, radix: u32| from + chars[from..].iter().take_while(|c| c.is_digit(radix)).count();
+    let prefixed = |i: usize, letter: char, radix: u32| {
+        chars[i] == '0'
+            && chars.get(i + 1).is_some_and(|c| c.eq_ignore_ascii_case(&letter))
+            && chars.get(i + 2).is_some_and(|c| c.is_digit(radix))
+    };
+
+    let mut i = 0;
+    while i < chars.len() {
+        if !chars[i].is_ascii_digit() {
+            i += 1;
+            continue;
+        }
+
+        let (start, digits, end, radix) = if formats.contains(&"hex") && prefixed(i, 'x', 16) {
+            (i, i + 2, digits_from(i + 2, 16), Radix::Hex)
+        } else if formats.contains(&"bin") && prefixed(i, 'b', 2) {
+            (i, i + 2, digits_from(i + 2, 2), Radix::Binary)
+        } else {
+            let end = digits_from(i, 10);
+            let octal = chars[i] == '0' && end - i > 1 && chars[i..end].iter().all(|c| c.is_digit(8));
+            if formats.contains(&"octal") && octal {
+                (i, i + 1, end, Radix::Octal)
+            } else if i > 0 && chars[i - 1] == '-' {
+                (i - 1, i, end, Radix::Decimal)
+            } else {
+                (i, i, end, Radix::Decimal)
+            }
+        };
+        if end > col {
+            return Some((start, digits, end, radix));
+        }
+        i = end;
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn add() {
+        let add = |text: &str, col, delta, formats| {
+            let chars: Vec<char> = text.chars().collect();
+            add_to_number(&chars, col, delta, formats).map(|(start, end, new)| {
+                let mut chars = chars;
+                chars.splice(start..end, new.chars());
+                chars.into_iter().collect::<String>()
+            })
+        };
+        assert_eq!(add("x = 9;", 0, 1, "bin,hex").as_deref(), Some("x = 10;"));
+        assert_eq!(add("a-1", 0, 3, "bin,hex").as_deref(), Some("a2"));
+        assert_eq!(add("0", 0, -1, "bin,hex").as_deref(), Some("-1"));
+        assert_eq!(add("007", 0, 5, "bin,hex").as_deref(), Some("012"));
+        assert_eq!(add("007", 0, 1, "octal").as_deref(), Some("010"));
+        assert_eq!(add("0x0f", 3, 1, "bin,hex").as_deref(), Some("0x10"));
+        assert_eq!(add("0X0aF", 0, 1, "bin,hex").as_deref(), Some("0X0B0"));
+        assert_eq!(add("0x00", 0, -1, "hex").as_deref(), Some("0xffffffffffffffff"));
+        assert_eq!(add("0b0111", 0, 1, "bin,hex").as_deref(), Some("0b1000"));
+        assert_eq!(add("0x10", 0, 1, "").as_deref(), Some("1x10"));
+        assert_eq!(add("1 2 3", 2, 1, "").as_deref(), Some("1 3 3"));
+        assert_eq!(add("1 2", 3, 1, ""), None);
+        assert_eq!(add("9223372036854775807", 0, 1, "").as_deref(), Some("9223372036854775807"));
+        assert_eq!(add("9223372036854775800", 0, 100, "").as_deref(), Some("9223372036854775807"));
+        assert_eq!(add("-9223372036854775808", 0, -1, "").as_deref(), Some("-9223372036854775808"));
+        assert_eq!(add("99999999999999999999", 0, -1, "").as_deref(), Some("9223372036854775807"));
+    }
+}
@@ -0,0 +1,307 @@
+/// Editor settings, named after the vim options they mirror.
+#[derive(Debug, Clone)]
+pub struct Options {
+    pub shiftwidth: usize,
+    pub tabstop: usize,
+    pub expandtab: bool,
+    pub tildeop: bool, // ~ is an operator like g~, instead of switching the case of count characters
+    pub ignorecase: bool, // Searches ignore case
+    pub smartcase: bool,  // Unless the pattern has an upper case letter
+    pub hlsearch: bool,   // Highlight the matches of the last search
+    pub iskeyword: Keywords,
+    pub nrformats: String, // Numbers Ctrl-A and Ctrl-X know besides decimal: "bin", "octal" and "hex"
+    pub osc52: bool,       // Copy to the clipboard with the terminal's OSC 52 escape
+    pub clipcopy: String,  // Command that takes clipboard text on its input
+    pub clippaste: String, // Command that writes the clipboard to its output
+}
+
+impl Options {
+    /// Every option, as listed by `:set all`.
+    pub const NAMES: [&'static str; 12] = [
+        "clipcopy", "clippaste", "expandtab", "hlsearch", "ignorecase", "iskeyword", "nrformats", "osc52", "shiftwidth",
+        "smartcase", "tabstop", "tildeop",
+    ];
+
+    pub fn new() -> Self {
+        // Clipboard commands for the session we are in, OSC 52 covers the rest
+        let (clipcopy, clippaste) = if std::env::var_os("WAYLAND_DISPLAY").is_some() {
+            ("wl-copy", "wl-paste --no-newline")
+        } else if std::env::var_os("DISPLAY").is_some() {
+            ("xclip -selection clipboard", "xclip -selection clipboard -o")
+        } else if cfg!(target_os = "macos") {
+            ("pbcopy", "pbpaste")
+        } else {
+            ("", "")
+        };
+
+        Self {
+            shiftwidth: 8,
+            tabstop: 8,
+            expandtab: false,
+            tildeop: false,
+            ignorecase: false,
+            smartcase: false,
+            hlsearch: false,
+            iskeyword: Keywords::parse("@,48-57,_,192-255").unwrap(),
+            nrformats: "bin,hex".to_string(),
+            osc52: true,
+            clipcopy: clipcopy.to_string(),
+            clippaste: clippaste.to_string(),
+        }
+    }
+
+    /// Split the arguments of `:set` on white space, where a backslash keeps a space or
+    /// backslash as part of the value, as in "clipcopy=xclip\ -i".
+    pub fn split_args(args: &str) -> Vec<String> {
+        let mut split = Vec::new();
+        let mut arg = String::new();
+        let mut chars = args.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => match chars.next() {
+                    Some(c @ (' ' | '\t' | '\\')) => arg.push(c),
+                    Some(c) => {
+                        arg.push('\\');
+                        arg.push(c);
+                    }
+                    None => arg.push('\\'),
+                },
+                c if c.is_whitespace() => {
+                    if !arg.is_empty() {
+                        split.push(std::mem::take(&mut arg));
+                    }
+                }
+                c => arg.push(c),
+            }
+        }
+        if !arg.is_empty() {
+            split.push(arg);
+        }
+        split
+    }
+
+    /// Apply one argument of `:set`, e.g. "sw=4", "noexpandtab", "invet" or "isk+=-". Returns the
+    /// text to display for queries such as "sw?".
+    pub fn set(&mut self, arg: &str) -> Result<Option<String>, String> {
+        let (name, op, value) = match arg.find(['=', ':']) {
+            Some(i) => {
+                let (name, op) = match &arg[..i] {
+                    n if n.ends_with('+') || n.ends_with('-') || n.ends_with('^') => (&n[..n.len() - 1], &arg[i - 1..=i]),
+                    n => (n, &arg[i..=i]),
+                };
+                (name, op, Some(&arg[i + 1..]))
+            }
+            None => (arg, "", None),
+        };
+
+        let (name, query) = match name.strip_suffix('?') {
+            Some(name) => (name, true),
+            None => (name, false),
+        };
+
+        // Boolean options
+        let (bool_name, bool_value) = if let Some(n) = name.strip_prefix("no") {
+            (n, Some(false))
+        } else if let Some(n) = name.strip_prefix("inv") {
+            (n, None)
+        } else if let Some(n) = name.strip_suffix('!') {
+            (n, None)
+        } else {
+            (name, Some(true))
+        };
+        if let Some(option) = self.bool_option(bool_name) {
+            if value.is_some() {
+                return Err(format!("E474: Invalid argument: {arg}"));
+            }
+            if query {
+                let prefix = if *option { "  " } else { "no" };
+                return Ok(Some(format!("{prefix}{bool_name}")));
+            }
+            *option = bool_value.unwrap_or(!*option);
+            return Ok(None);
+        }
+
+        match name {
+            "shiftwidth" | "sw" | "tabstop" | "ts" => {
+                let full = if name.starts_with('s') { "shiftwidth" } else { "tabstop" };
+                let option = if full == "shiftwidth" { &mut self.shiftwidth } else { &mut self.tabstop };
+                match value {
+                    None => Ok(Some(format!("  {full}={option}"))),
+                    Some(v) => {
+                        let n: usize = v.parse().map_err(|_| format!("E521: Number required after =: {arg}"))?;
+                        *option = match op {
+                            "+=" => *option + n,
+                            "-=" => option.saturating_sub(n),
+                            "^=" => *option * n,
+                            _ => n,
+                        };
+                        if full == "tabstop" && *option == 0 {
+                            *option = 8;
+                            return Err(format!("E487: Argument must be positive: {arg}"));
+                        }
+                        Ok(None)
+                    }
+                }
+            }
+            "iskeyword" | "isk" => match value {
+                None => Ok(Some(format!("  iskeyword={}", self.iskeyword.spec))),
+                Some(v) => {
+                    self.iskeyword = Keywords::parse(&combine_list(&self.iskeyword.spec, op, v))?;
+                    Ok(None)
+                }
+            },
+            "nrformats" | "nf" => match value {
+                None => Ok(Some(format!("  nrformats={}", self.nrformats))),
+                Some(v) => {
+                    let formats = combine_list(&self.nrformats, op, v);
+                    if formats.split(',').any(|f| !matches!(f, "" | "bin" | "octal" | "hex")) {
+                        return Err(format!("E474: Invalid argument: {arg}"));
+                    }
+                    self.nrformats = formats;
+                    Ok(None)
+                }
+            },
+            "clipcopy" | "clippaste" => {
+                let option = if name == "clipcopy" { &mut self.clipcopy } else { &mut self.clippaste };
+                match (op, value) {
+                    (_, None) => Ok(Some(format!("  {name}={option}"))),
+                    ("=" | ":", Some(v)) => {
+                        *option = v.to_string();
+                        Ok(None)
+                    }
+                    _ => Err(format!("E474: Invalid argument: {arg}")),
+                }
+            }
+            _ => Err(format!("E518: Unknown option: {name}")),
+        }
+    }
+
+    fn bool_option(&mut self, name: &str) -> Option<&mut bool> {
+        match name {
+            "expandtab" | "et" => Some(&mut self.expandtab),
+            "osc52" => Some(&mut self.osc52),
+            "tildeop" | "top" => Some(&mut self.tildeop),
+            "ignorecase" | "ic" => Some(&mut self.ignorecase),
+            "smartcase" | "scs" => Some(&mut self.smartcase),
+            "hlsearch" | "hls" => Some(&mut self.hlsearch),
+            _ => None,
+        }
+    }
+}
+
+/// A comma separated option after :set with "=", "+=", "^=" or "-=".
+fn combine_list(list: &str, op: &str, value: &str) -> String {
+    match op {
+        "+=" if !list.is_empty() => format!("{list},{value}"),
+        "^=" if !list.is_empty() => format!("{value},{list}"),
+        "-=" => list.split(',').filter(|p| *p != value).collect::<Vec<_>>().join(","),
+        _ => value.to_string(),
+    }
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The characters that make up a word, from a vim style 'iskeyword' value such as
+/// "@,48-57,_,192-255". Characters above 255 are keyword characters if they are alphanumeric.
+#[derive(Debug, Clone)]
+pub struct Keywords {
+    spec: String,
+    table: [bool; 256],
+}
+
+impl Keywords {
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let mut table = [false; 256];
+        let invalid = || format!("E474: Invalid argument: iskeyword={spec}");
+
+        // A part is a single character or number, a range of either, or @ for all letters.
+        // A comma can only appear as the first character of a part.
+        let mut parts = Vec::<&str>::new();
+        let mut rest = spec;
+        while !rest.is_empty() {
+            let end = rest.char_indices().skip(1).find(|(_, c)| *c == ',').map_or(rest.len(), |(i, _)| i);
+            parts.push(&rest[..end]);
+            rest = rest[end..].strip_prefix(',').unwrap_or("");
+        }
+
+        let bound = |s: &str| -> Option<u32> {
+            let mut chars = s.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) if !c.is_ascii_digit() => Some(c as u32),
+                _ => s.parse().ok(),
+            }
+        };
+
+        for part in parts {
+            let (value, part) = match part.strip_prefix('^') {
+                Some(p) if !p.is_empty() => (false, p),
+                _ => (true, part),
+            };
+
+            if part == "@" {
+                for (c, entry) in table.iter_mut().enumerate() {
+                    if char::from_u32(c as u32).is_some_and(|c| c.is_alphabetic()) {
+                        *entry = value;
+                    }
+                }
+                continue;
+            }
+
+            let (from, to) = match part.char_indices().skip(1).find(|(_, c)| *c == '-') {
+                Some((i, _)) if i + 1 < part.len() => (bound(&part[..i]), bound(&part[i + 1..])),
+                _ => (bound(part), bound(part)),
+            };
+            let (from, to) = match (from, to) {
+                (Some(from), Some(to)) if from <= to && to < 256 => (from, to),
+                _ => return Err(invalid()),
+            };
+            for c in from..=to {
+                table[c as usize] = value;
+            }
+        }
+
+        Ok(Self { spec: spec.to_string(), table })
+    }
+
+    pub fn contains(&self, c: char) -> bool {
+        match self.table.get(c as usize) {
+            Some(v) => *v,
+            None => c.is_alphanumeric(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn iskeyword() {
+        let keywords = Keywords::parse("@,48-57,_,192-255,^x,@-@,-").unwrap();
+        assert!(keywords.contains('a') && keywords.contains('7') && keywords.contains('é'));
+        assert!(keywords.contains('@') && keywords.contains('-') && keywords.contains('_'));
+        assert!(!keywords.contains('x') && !keywords.contains('.'));
+        assert!(Keywords::parse("50-40").is_err());
+
+        let mut options = Options::new();
+        assert_eq!(options.set("isk+=."), Ok(None));
+        assert!(options.iskeyword.contains('.'));
+        assert_eq!(options.set("isk-=."), Ok(None));
+        assert!(!options.iskeyword.contains('.'));
+        assert_eq!(options.set("sw=4"), Ok(None));
+        assert_eq!(options.set("sw?"), Ok(Some("  shiftwidth=4".to_string())));
+        assert!(options.set("noet").is_ok() && !options.expandtab);
+        assert_eq!(Options::split_args(r"clipcopy=xclip\ -i  et"), ["clipcopy=xclip -i", "et"]);
+        assert_eq!(options.set("clipcopy=cat"), Ok(None));
+        assert_eq!(options.set("clipcopy?"), Ok(Some("  clipcopy=cat".to_string())));
+        assert!(options.set("foo").is_err());
+        assert_eq!(options.set("nf-=bin"), Ok(None));
+        assert_eq!(options.set("nf+=octal"), Ok(None));
+        assert_eq!(options.nrformats, "hex,octal");
+        assert!(options.set("nf=alpha").is_err());
+    }
+}
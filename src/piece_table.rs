@@ -66,8 +66,9 @@ impl PieceTable {
     pub fn insert(&mut self, idx: usize, c: char) {
         assert!(idx <= self.length, "PieceTabe::insert, Tried to insert character at {idx} with file length {}.", self.length);
         if idx == self.length { // Append to end of file
-            let end_idx = self.piece_table.len() - 1;
-            if self.piece_table[end_idx].buffer == PieceTableBuffers::Add
+            let end_idx = self.piece_table.len().saturating_sub(1);
+            if !self.piece_table.is_empty()
+                && self.piece_table[end_idx].buffer == PieceTableBuffers::Add
                 && self.piece_table[end_idx].start_index + self.piece_table[end_idx].length
                     == self.add_buffer.len()
             {
@@ -204,6 +205,141 @@ impl PieceTable {
         }
     }
 
+    /// Insert a whole string at idx as a single new piece.
+    pub fn insert_str(&mut self, idx: usize, s: &str) {
+        assert!(idx <= self.length, "PieceTable::insert_str, Tried to insert string at {idx} with file length {}.", self.length);
+        let chars: Vec<char> = s.chars().collect();
+        if chars.is_empty() {
+            return;
+        }
+
+        let new_entry = PieceTableEntry {
+            buffer: PieceTableBuffers::Add,
+            start_index: self.add_buffer.len(),
+            length: chars.len(),
+        };
+
+        // Find the entry containing idx, splitting it if idx falls inside of it
+        let mut entry_idx: usize = 0;
+        let mut offset: usize = 0;
+        while entry_idx < self.piece_table.len() && offset + self.piece_table[entry_idx].length <= idx {
+            offset += self.piece_table[entry_idx].length;
+            entry_idx += 1;
+        }
+
+        if entry_idx < self.piece_table.len() && idx > offset {
+            let split_entry = PieceTableEntry {
+                buffer: self.piece_table[entry_idx].buffer,
+                start_index: self.piece_table[entry_idx].start_index,
+                length: idx - offset,
+            };
+            self.piece_table[entry_idx].start_index += split_entry.length;
+            self.piece_table[entry_idx].length -= split_entry.length;
+            self.piece_table.insert(entry_idx, split_entry);
+            entry_idx += 1;
+        }
+
+        if entry_idx > 0
+            && self.piece_table[entry_idx - 1].buffer == PieceTableBuffers::Add
+            && self.piece_table[entry_idx - 1].start_index + self.piece_table[entry_idx - 1].length == self.add_buffer.len()
+        {
+            self.piece_table[entry_idx - 1].length += chars.len();
+        } else {
+            self.piece_table.insert(entry_idx, new_entry);
+        }
+
+        self.add_buffer.extend_from_slice(&chars);
+        self.length += chars.len();
+
+        let first_moved = self.line_starts.partition_point(|s| *s <= idx);
+        for ln in self.line_starts[first_moved..].iter_mut() {
+            *ln += chars.len();
+        }
+        let new_lines = chars.iter().enumerate().filter_map(|(i, c)| if *c == '\n' { Some(idx + i + 1) } else { None });
+        self.line_starts.splice(first_moved..first_moved, new_lines);
+    }
+
+    /// Delete the characters in start..end.
+    pub fn delete_range(&mut self, start: usize, end: usize) {
+        assert!(start <= end && end <= self.length, "PieceTable::delete_range, Attempted to delete {start}..{end}, when buffer length is {}", self.length);
+        if start == end {
+            return;
+        }
+
+        let mut entries = Vec::<PieceTableEntry>::with_capacity(self.piece_table.len() + 1);
+        let mut offset: usize = 0;
+        for entry in self.piece_table.drain(..) {
+            let entry_start = offset;
+            let entry_end = offset + entry.length;
+            offset = entry_end;
+
+            if entry_end <= start || entry_start >= end {
+                entries.push(entry);
+                continue;
+            }
+
+            if entry_start < start {
+                entries.push(PieceTableEntry {
+                    buffer: entry.buffer,
+                    start_index: entry.start_index,
+                    length: start - entry_start,
+                });
+            }
+            if entry_end > end {
+                entries.push(PieceTableEntry {
+                    buffer: entry.buffer,
+                    start_index: entry.start_index + (end - entry_start),
+                    length: entry_end - end,
+                });
+            }
+        }
+        self.piece_table = entries;
+        self.length -= end - start;
+
+        // Lines starting inside the deleted range had their newline removed
+        let first_removed = self.line_starts.partition_point(|s| *s <= start);
+        let first_kept = self.line_starts.partition_point(|s| *s <= end);
+        self.line_starts.drain(first_removed..first_kept);
+        for ln in self.line_starts[first_removed..].iter_mut() {
+            *ln -= end - start;
+        }
+    }
+
+    /// Collect the characters in start..end.
+    pub fn slice(&self, start: usize, end: usize) -> String {
+        let end = end.min(self.length);
+        let mut s = String::new();
+        let mut offset: usize = 0;
+        for entry in self.piece_table.iter() {
+            if offset >= end {
+                break;
+            }
+            let entry_end = offset + entry.length;
+            if entry_end > start {
+                let from = start.max(offset) - offset + entry.start_index;
+                let to = end.min(entry_end) - offset + entry.start_index;
+                let buffer = match entry.buffer {
+                    PieceTableBuffers::Original => &self.original_buffer,
+                    PieceTableBuffers::Add => &self.add_buffer,
+                };
+                s.extend(buffer[from..to].iter());
+            }
+            offset = entry_end;
+        }
+        s
+    }
+
+    /// The text as the slices of the buffers its pieces point to, in order.
+    pub fn chunks(&self) -> impl Iterator<Item = &[char]> + '_ {
+        self.piece_table.iter().map(|entry| {
+            let buffer = match entry.buffer {
+                PieceTableBuffers::Original => &self.original_buffer,
+                PieceTableBuffers::Add => &self.add_buffer,
+            };
+            &buffer[entry.start_index..entry.start_index + entry.length]
+        })
+    }
+
     fn find_entry(&self, idx: usize) -> Option<usize> {
         if idx == self.length {
             None
@@ -229,11 +365,11 @@ impl PieceTable {
 
     pub fn get_line(&self, line_number: usize) -> Option<Vec<char>> {
         if line_number + 1 < self.line_starts.len() {
-            Some((self.line_starts[line_number]..(self.line_starts[line_number+1]-1)).map(|i| self.index(i)).collect())
+            Some(self.slice(self.line_starts[line_number], self.line_starts[line_number+1]-1).chars().collect())
         } else if line_number < self.line_starts.len() {
-            Some((self.line_starts[line_number]..self.length).map(|i| self.index(i)).collect())
+            Some(self.slice(self.line_starts[line_number], self.length).chars().collect())
         } else {
-            return None
+            None
         }
     }
 
@@ -249,9 +385,17 @@ impl PieceTable {
         if line_number + 1 < self.line_starts.len() {
             Some(self.line_starts[line_number+1] - self.line_starts[line_number] - 1)
         } else if line_number < self.line_starts.len() {
-            Some(self.length - self.line_starts[line_number] + 1)
+            Some(self.length - self.line_starts[line_number])
         } else {
-            return None
+            None
+        }
+    }
+
+    /// Find the line containing the character at idx, idx == len() maps to the last line.
+    pub fn get_line_number(&self, idx: usize) -> usize {
+        match self.line_starts.binary_search(&idx) {
+            Ok(ln) => ln,
+            Err(ln) => ln - 1,
         }
     }
 
@@ -430,4 +574,24 @@ mod tests {
             "Lor dolor sitet, consectetur".to_string()
         );
     }
+
+    #[test]
+    fn bulk_edits() {
+        let mut table = PieceTable::new("one\ntwo\nthree");
+        table.insert_str(4, "1.5\n");
+        assert_eq!(table.slice(0, table.len()), "one\n1.5\ntwo\nthree");
+        assert_eq!(table.lines(), 4);
+        assert_eq!(table.get_line_offset(2), Some(8));
+
+        table.delete_range(2, 10);
+        assert_eq!(table.slice(0, table.len()), "ono\nthree");
+        assert_eq!(table.lines(), 2);
+        assert_eq!(table.get_line(1), Some("three".chars().collect()));
+        assert_eq!(table.get_line_number(5), 1);
+
+        table.delete_range(0, table.len());
+        assert!(table.is_empty());
+        table.insert_str(0, "again");
+        assert_eq!(table.slice(1, 4), "gai");
+    }
 }
@@ -0,0 +1,801 @@
+use std::cell::Cell;
+
+use crate::options::{Keywords, Options};
+use crate::piece_table::PieceTable;
+
+/// Text a pattern is matched against, read in place from the chunks of a piece table so that a
+/// match can run across pieces and lines.
+pub struct Haystack<'a> {
+    chunks: Vec<&'a [char]>,
+    starts: Vec<usize>,
+    len: usize,
+    last: Cell<usize>, // Chunk of the last lookup, matching mostly reads the text in order
+}
+
+impl<'a> Haystack<'a> {
+    pub fn new(text: &'a PieceTable) -> Self {
+        Self::from_chunks(text.chunks())
+    }
+
+    pub fn from_chunks(chunks: impl Iterator<Item = &'a [char]>) -> Self {
+        let chunks: Vec<&[char]> = chunks.filter(|c| !c.is_empty()).collect();
+        let mut starts = Vec::with_capacity(chunks.len());
+        let mut len = 0;
+        for chunk in chunks.iter() {
+            starts.push(len);
+            len += chunk.len();
+        }
+        Self { chunks, starts, len, last: Cell::new(0) }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn get(&self, idx: usize) -> Option<char> {
+        if idx >= self.len {
+            return None;
+        }
+        let mut i = self.last.get();
+        if idx < self.starts[i] || idx >= self.starts[i] + self.chunks[i].len() {
+            i = self.starts.partition_point(|s| *s <= idx) - 1;
+            self.last.set(i);
+        }
+        Some(self.chunks[i][idx - self.starts[i]])
+    }
+}
+
+/// How much of a pattern has a special meaning without a backslash, set by \v, \m, \M and \V.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Level {
+    VeryMagic,
+    Magic,
+    NoMagic,
+    VeryNoMagic,
+}
+
+impl Level {
+    /// The characters that are special without a backslash, a backslash makes them literal.
+    /// The other characters of TOGGLED are special with a backslash.
+    fn special(self) -> &'static str {
+        match self {
+            Level::VeryMagic => TOGGLED,
+            Level::Magic => "*.[~^$",
+            Level::NoMagic => "^$",
+            Level::VeryNoMagic => "",
+        }
+    }
+}
+
+/// Characters whose meaning the magic level decides.
+const TOGGLED: &str = "()|+?={@%<>*.[~^$&";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Token {
+    Special(char),
+    Literal(char),
+    Escape(char), // A backslash and a character that isn't toggled by magic, as \s or \z
+}
+
+/// Character classes of \s, \d and the like, and of [:alpha:] in a collection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Class {
+    Space, // Space and tab
+    Digit,
+    Word, // [0-9A-Za-z_]
+    Alpha,
+    Lower,
+    Upper,
+    Hex,
+    Octal,
+    Head, // [A-Za-z_], the start of a word
+    Keyword,
+    KeywordNoDigit,
+    Printable,
+    Alnum,
+    Blank, // Any white space
+    Control,
+    Graph,
+    Punct,
+}
+
+impl Class {
+    fn from_escape(c: char) -> Option<(Class, bool)> {
+        let class = match c.to_ascii_lowercase() {
+            's' => Class::Space,
+            'd' => Class::Digit,
+            'w' => Class::Word,
+            'a' => Class::Alpha,
+            'l' => Class::Lower,
+            'u' => Class::Upper,
+            'x' => Class::Hex,
+            'o' => Class::Octal,
+            'h' => Class::Head,
+            'i' | 'k' => Class::Keyword,
+            'f' => Class::Graph,
+            'p' => Class::Printable,
+            _ => return None,
+        };
+        // Upper case negates, except that \K \I \F and \P leave out digits
+        Some(match c {
+            'K' | 'I' => (Class::KeywordNoDigit, false),
+            'F' | 'P' => (class, false),
+            c => (class, c.is_ascii_uppercase()),
+        })
+    }
+
+    fn from_name(name: &str) -> Option<Class> {
+        Some(match name {
+            "alnum" => Class::Alnum,
+            "alpha" => Class::Alpha,
+            "blank" => Class::Space,
+            "cntrl" => Class::Control,
+            "digit" => Class::Digit,
+            "graph" => Class::Graph,
+            "lower" => Class::Lower,
+            "print" => Class::Printable,
+            "punct" => Class::Punct,
+            "space" => Class::Blank,
+            "upper" => Class::Upper,
+            "xdigit" => Class::Hex,
+            _ => return None,
+        })
+    }
+
+    fn contains(self, c: char, keywords: &Keywords) -> bool {
+        match self {
+            Class::Space => c == ' ' || c == '\t',
+            Class::Digit => c.is_ascii_digit(),
+            Class::Word => c.is_ascii_alphanumeric() || c == '_',
+            Class::Alpha => c.is_ascii_alphabetic(),
+            Class::Lower => c.is_lowercase(),
+            Class::Upper => c.is_uppercase(),
+            Class::Hex => c.is_ascii_hexdigit(),
+            Class::Octal => c.is_digit(8),
+            Class::Head => c.is_ascii_alphabetic() || c == '_',
+            Class::Keyword => keywords.contains(c),
+            Class::KeywordNoDigit => keywords.contains(c) && !c.is_ascii_digit(),
+            Class::Printable => !c.is_control(),
+            Class::Alnum => c.is_alphanumeric(),
+            Class::Blank => c.is_whitespace(),
+            Class::Control => c.is_control(),
+            Class::Graph => !c.is_control() && !c.is_whitespace(),
+            Class::Punct => c.is_ascii_punctuation(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SetItem {
+    Range(char, char),
+    Class(Class),
+}
+
+/// A collection such as [a-z], or a class such as \s. Line breaks only match when asked for,
+/// with \_ or a \n in the collection.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Set {
+    items: Vec<SetItem>,
+    negated: bool,
+    newline: bool,
+}
+
+impl Set {
+    fn class(class: Class, negated: bool, newline: bool) -> Self {
+        Set { items: vec![SetItem::Class(class)], negated, newline }
+    }
+
+    fn contains(&self, c: char, ignore_case: bool, keywords: &Keywords) -> bool {
+        if c == '\n' {
+            return self.newline || (!self.negated && self.items.contains(&SetItem::Range('\n', '\n')));
+        }
+        let has = |c: char| {
+            self.items.iter().any(|item| match item {
+                SetItem::Range(from, to) => (*from..=*to).contains(&c),
+                SetItem::Class(class) => class.contains(c, keywords),
+            })
+        };
+        let found = if ignore_case {
+            has(c) || c.to_lowercase().any(has) || c.to_uppercase().any(has)
+        } else {
+            has(c)
+        };
+        found != self.negated
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Node {
+    Char(char),
+    Any(bool), // ., or \_. when true which also matches a line break
+    Set(Set),
+    LineStart,
+    LineEnd,
+    WordStart,
+    WordEnd,
+    TextStart,  // \%^
+    TextEnd,    // \%$
+    MatchStart, // \zs
+    MatchEnd,   // \ze
+    Group(Vec<Vec<Node>>, Option<usize>), // Alternatives, and the number of a capturing group
+    Repeat(Box<Node>, usize, Option<usize>, bool), // The least and most times, and whether it is greedy
+    BackRef(usize),
+}
+
+impl Node {
+    /// Nodes that always take exactly one character, repeats of them are matched without
+    /// recursing for each character.
+    fn is_single(&self) -> bool {
+        matches!(self, Node::Char(_) | Node::Any(_) | Node::Set(_))
+    }
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+    magic: Level,
+    groups: usize,
+    case: Option<bool>, // \c makes it true to ignore case, \C false
+    upper: bool,        // The pattern has an upper case letter, for 'smartcase'
+}
+
+impl Parser {
+    fn token(&mut self) -> Option<Token> {
+        loop {
+            let c = *self.chars.get(self.pos)?;
+            self.pos += 1;
+            if c != '\\' {
+                return Some(match self.magic.special().contains(c) {
+                    true => Token::Special(c),
+                    false => Token::Literal(c),
+                });
+            }
+            let Some(&c) = self.chars.get(self.pos) else {
+                return Some(Token::Literal('\\'));
+            };
+            self.pos += 1;
+            match c {
+                'v' => self.magic = Level::VeryMagic,
+                'm' => self.magic = Level::Magic,
+                'M' => self.magic = Level::NoMagic,
+                'V' => self.magic = Level::VeryNoMagic,
+                'c' => self.case = Some(true),
+                'C' => self.case = Some(false),
+                c if TOGGLED.contains(c) => {
+                    return Some(match self.magic.special().contains(c) {
+                        true => Token::Literal(c),
+                        false => Token::Special(c),
+                    });
+                }
+                c => return Some(Token::Escape(c)),
+            }
+        }
+    }
+
+    fn peek(&mut self) -> Option<Token> {
+        let (pos, magic, case) = (self.pos, self.magic, self.case);
+        let token = self.token();
+        (self.pos, self.magic, self.case) = (pos, magic, case);
+        token
+    }
+
+    /// The next character as it is, for the parts of a pattern that aren't tokens.
+    fn raw(&mut self) -> Option<char> {
+        let c = self.chars.get(self.pos).copied();
+        self.pos += c.is_some() as usize;
+        c
+    }
+
+    /// Alternatives separated by \|, up to a \) or the end of the pattern.
+    fn branches(&mut self) -> Result<Vec<Vec<Node>>, String> {
+        let mut branches = vec![self.concat()?];
+        while self.peek() == Some(Token::Special('|')) {
+            self.token();
+            branches.push(self.concat()?);
+        }
+        Ok(branches)
+    }
+
+    fn concat(&mut self) -> Result<Vec<Node>, String> {
+        let mut nodes = Vec::new();
+        loop {
+            let token = match self.peek() {
+                None | Some(Token::Special('|' | ')')) => return Ok(nodes),
+                Some(token) => token,
+            };
+            self.token();
+            let node = match token {
+                Token::Special('^') if nodes.is_empty() => Node::LineStart,
+                Token::Special('$') if matches!(self.peek(), None | Some(Token::Special('|' | ')'))) => Node::LineEnd,
+                Token::Special(c @ ('^' | '$')) => Node::Char(c),
+                Token::Special('.') => Node::Any(false),
+                Token::Special('[') => self.collection(false)?,
+                // There is no substitute string for ~ to match, as vim says before the first :s
+                Token::Special('~') => return Err("E33: No previous substitute regular expression".to_string()),
+                Token::Special('(') => self.group(true)?,
+                Token::Special('<') => Node::WordStart,
+                Token::Special('>') => Node::WordEnd,
+                Token::Special('%') => self.percent()?,
+                Token::Special(c @ ('@' | '&')) => return Err(format!("E869: Unknown operator '\\{c}'")),
+                // A * at the start is taken literally, like vim does
+                Token::Special('*') if nodes.is_empty() => Node::Char('*'),
+                Token::Special(c) => return Err(format!("E64: {c} follows nothing")),
+                Token::Literal(c) => {
+                    self.upper |= c.is_uppercase();
+                    Node::Char(c)
+                }
+                Token::Escape(c) => self.escape(c)?,
+            };
+            nodes.push(self.multi(node)?);
+        }
+    }
+
+    /// Repeats after an atom: *, \+, \=, \? and \{n,m}.
+    fn multi(&mut self, mut node: Node) -> Result<Node, String> {
+        loop {
+            let repeat = match self.peek() {
+                Some(Token::Special(c @ ('*' | '+' | '=' | '?' | '{'))) => c,
+                _ => return Ok(node),
+            };
+            self.token();
+            let (min, max, greedy) = match repeat {
+                '*' => (0, None, true),
+                '+' => (1, None, true),
+                '{' => self.brace()?,
+                _ => (0, Some(1), true),
+            };
+            node = Node::Repeat(Box::new(node), min, max, greedy);
+        }
+    }
+
+    /// The counts of \{n,m}, with a leading - for as few as possible.
+    fn brace(&mut self) -> Result<(usize, Option<usize>, bool), String> {
+        let mut inside = String::new();
+        loop {
+            match self.raw() {
+                Some('}') => break,
+                Some('\\') if self.chars.get(self.pos) == Some(&'}') => {
+                    self.pos += 1;
+                    break;
+                }
+                Some(c) if c.is_ascii_digit() || c == ',' || c == '-' => inside.push(c),
+                _ => return Err("E554: Syntax error in \\{...}".to_string()),
+            }
+        }
+        let invalid = || "E554: Syntax error in \\{...}".to_string();
+        let (greedy, counts) = match inside.strip_prefix('-') {
+            Some(rest) => (false, rest),
+            None => (true, inside.as_str()),
+        };
+        let number = |s: &str| if s.is_empty() { Ok(None) } else { s.parse().map(Some).map_err(|_| invalid()) };
+        let (min, max) = match counts.split_once(',') {
+            Some((min, max)) => (number(min)?.unwrap_or(0), number(max)?),
+            None => match number(counts)? {
+                Some(n) => (n, Some(n)),
+                None => (0, None),
+            },
+        };
+        // \{2,1} is taken as \{1,2}, as vim does
+        let (min, max) = match max {
+            Some(max) if max < min => (max, Some(min)),
+            max => (min, max),
+        };
+        Ok((min, max, greedy))
+    }
+
+    fn group(&mut self, capture: bool) -> Result<Node, String> {
+        let number = if capture {
+            self.groups += 1;
+            Some(self.groups)
+        } else {
+            None
+        };
+        let branches = self.branches()?;
+        match self.token() {
+            Some(Token::Special(')')) => Ok(Node::Group(branches, number)),
+            _ => Err(format!("E54: Unmatched {}", if self.magic == Level::VeryMagic { "(" } else { "\\(" })),
+        }
+    }
+
+    /// \%( for a group that isn't captured, \%^ and \%$ for the ends of the text, and \%d, \%x,
+    /// \%u, \%U and \%o for a character by its code.
+    fn percent(&mut self) -> Result<Node, String> {
+        let invalid = || "E71: Invalid character after \\%".to_string();
+        let (radix, max) = match self.raw() {
+            Some('(') => return self.group(false),
+            Some('^') => return Ok(Node::TextStart),
+            Some('$') => return Ok(Node::TextEnd),
+            Some('d') => (10, 10),
+            Some('x') => (16, 2),
+            Some('u') => (16, 4),
+            Some('U') => (16, 8),
+            Some('o') => (8, 11),
+            _ => return Err(invalid()),
+        };
+        let digits: String = self.chars[self.pos..].iter().take(max).take_while(|c| c.is_digit(radix)).collect();
+        self.pos += digits.len();
+        u32::from_str_radix(&digits, radix).ok().and_then(char::from_u32).map(Node::Char).ok_or_else(invalid)
+    }
+
+    fn escape(&mut self, c: char) -> Result<Node, String> {
+        if let Some((class, negated)) = Class::from_escape(c) {
+            return Ok(Node::Set(Set::class(class, negated, false)));
+        }
+        Ok(match c {
+            'n' => Node::Char('\n'),
+            't' => Node::Char('\t'),
+            'e' => Node::Char('\x1b'),
+            'r' => Node::Char('\r'),
+            '1'..='9' => Node::BackRef(c.to_digit(10).unwrap() as usize),
+            'z' => match self.raw() {
+                Some('s') => Node::MatchStart,
+                Some('e') => Node::MatchEnd,
+                _ => return Err("E68: Invalid character after \\z".to_string()),
+            },
+            // \_x is x that also matches a line break
+            '_' => match self.raw() {
+                Some('.') => Node::Any(true),
+                Some('[') => self.collection(true)?,
+                Some('^') => Node::LineStart,
+                Some('$') => Node::LineEnd,
+                Some(c) => match Class::from_escape(c) {
+                    Some((class, negated)) => Node::Set(Set::class(class, negated, true)),
+                    None => return Err("E63: Invalid use of \\_".to_string()),
+                },
+                None => return Err("E63: Invalid use of \\_".to_string()),
+            },
+            c => Node::Char(c),
+        })
+    }
+
+    /// A [] collection, after the [. Without a closing ] the [ is taken literally.
+    fn collection(&mut self, newline: bool) -> Result<Node, String> {
+        let start = self.pos;
+        let negated = self.chars.get(self.pos) == Some(&'^');
+        self.pos += negated as usize;
+        let mut items = Vec::new();
+        let mut first = true;
+        loop {
+            let c = match self.raw() {
+                None => {
+                    self.pos = start;
+                    return Ok(Node::Char('['));
+                }
+                Some(']') if !first => break,
+                Some('[') if self.chars.get(self.pos) == Some(&':') => {
+                    let rest: String = self.chars[self.pos + 1..].iter().collect();
+                    match rest.find(":]").and_then(|end| Class::from_name(&rest[..end]).map(|class| (class, end))) {
+                        Some((class, end)) => {
+                            items.push(SetItem::Class(class));
+                            self.pos += end + 3;
+                            first = false;
+                            continue;
+                        }
+                        None => '[',
+                    }
+                }
+                Some('\\') => self.collection_escape(),
+                Some(c) => c,
+            };
+            first = false;
+            self.upper |= c.is_uppercase();
+
+            let to = match (self.chars.get(self.pos), self.chars.get(self.pos + 1)) {
+                (Some('-'), Some(next)) if *next != ']' => {
+                    self.pos += 1;
+                    match self.raw() {
+                        Some('\\') => self.collection_escape(),
+                        Some(c) => c,
+                        None => unreachable!(),
+                    }
+                }
+                _ => c,
+            };
+            if to < c {
+                return Err("E944: Reverse range in character class".to_string());
+            }
+            self.upper |= to.is_uppercase();
+            items.push(SetItem::Range(c, to));
+        }
+        Ok(Node::Set(Set { items, negated, newline }))
+    }
+
+    /// A backslash in a collection, which only escapes a few characters.
+    fn collection_escape(&mut self) -> char {
+        let c = match self.chars.get(self.pos) {
+            Some('e') => '\x1b',
+            Some('t') => '\t',
+            Some('r') => '\r',
+            Some('n') => '\n',
+            Some('b') => '\x08',
+            Some(c @ ('\\' | ']' | '^' | '-')) => *c,
+            _ => return '\\',
+        };
+        self.pos += 1;
+        c
+    }
+}
+
+/// Steps a search may take before giving up, so a pattern that backtracks without end such as
+/// \v(a*)*c can't freeze the editor, as vim gives up after 'redrawtime'. Each position a match is
+/// tried from adds some, so a search through a large file isn't cut short.
+const BASE_STEPS: usize = 100_000;
+const STEPS_PER_START: usize = 1_000;
+
+/// Captured groups and where \zs and \ze were passed, while matching, and the steps left.
+#[derive(Debug, Clone)]
+struct State {
+    groups: Vec<Option<(usize, usize)>>,
+    start: Option<usize>,
+    end: Option<usize>,
+    steps: usize,
+}
+
+impl State {
+    /// Go back to a saved state when backtracking, keeping the steps taken since.
+    fn restore(&mut self, saved: State) {
+        let steps = self.steps;
+        *self = saved;
+        self.steps = steps;
+    }
+}
+
+/// A compiled vim regular expression.
+#[derive(Debug, Clone)]
+pub struct Regex {
+    root: Node,
+    groups: usize,
+    ignore_case: bool,
+    keywords: Keywords,
+}
+
+impl Regex {
+    /// Compile a pattern in vim's syntax. Case is ignored with 'ignorecase', unless 'smartcase'
+    /// is set and the pattern has an upper case letter. \c and \C in the pattern override both.
+    pub fn new(pattern: &str, options: &Options) -> Result<Self, String> {
+        let mut parser = Parser {
+            chars: pattern.chars().collect(),
+            pos: 0,
+            magic: Level::Magic,
+            groups: 0,
+            case: None,
+            upper: false,
+        };
+        let branches = parser.branches()?;
+        // Only a \) can be left, flags such as \c at the end are taken by reading on
+        if parser.token().is_some() {
+            let paren = if parser.magic == Level::VeryMagic { ")" } else { "\\)" };
+            return Err(format!("E55: Unmatched {paren}"));
+        }
+        let ignore_case = parser.case.unwrap_or(options.ignorecase && !(options.smartcase && parser.upper));
+
+        Ok(Self {
+            root: Node::Group(branches, None),
+            groups: parser.groups,
+            ignore_case,
+            keywords: options.iskeyword.clone(),
+        })
+    }
+
+    /// The first match that starts in from..=to, as start..end after any \zs and \ze. None
+    /// when there is none, or when finding it takes too long.
+    pub fn find_at(&self, text: &Haystack, from: usize, to: usize) -> Option<(usize, usize)> {
+        let mut steps = BASE_STEPS;
+        for start in from..=to.min(text.len()) {
+            steps += STEPS_PER_START;
+            let mut state = State { groups: vec![None; self.groups + 1], start: None, end: None, steps };
+            let mut found = None;
+            let mut accept = |end: usize, state: &mut State| {
+                found = Some((state.start.unwrap_or(start), state.end.unwrap_or(end)));
+                true
+            };
+            if self.sequence(text, std::slice::from_ref(&self.root), start, &mut state, &mut accept) {
+                return found;
+            }
+            if state.steps == 0 {
+                return None;
+            }
+            steps = state.steps;
+        }
+        None
+    }
+
+    fn chars_equal(&self, a: char, b: char) -> bool {
+        a == b || (self.ignore_case && a.to_lowercase().eq(b.to_lowercase()))
+    }
+
+    fn is_keyword(&self, c: Option<char>) -> bool {
+        c.is_some_and(|c| self.keywords.contains(c))
+    }
+
+    /// Match nodes in order from pos, calling `next` with where they end. Returns true as soon
+    /// as `next` accepts, and backtracks otherwise, or fails once it runs out of steps.
+    fn sequence(&self, text: &Haystack, nodes: &[Node], pos: usize, state: &mut State, next: &mut dyn FnMut(usize, &mut State) -> bool) -> bool {
+        if state.steps == 0 {
+            return false;
+        }
+        state.steps -= 1;
+        let Some((node, rest)) = nodes.split_first() else {
+            return next(pos, state);
+        };
+        match node {
+            Node::Group(branches, number) => {
+                for branch in branches {
+                    let saved = state.clone();
+                    let mut after = |end: usize, state: &mut State| {
+                        if let Some(n) = number {
+                            state.groups[*n] = Some((pos, end));
+                        }
+                        self.sequence(text, rest, end, state, next)
+                    };
+                    if self.sequence(text, branch, pos, state, &mut after) {
+                        return true;
+                    }
+                    state.restore(saved);
+                }
+                false
+            }
+            Node::Repeat(inner, min, max, greedy) if inner.is_single() => {
+                // Each repeat takes one character, so the ends are pos, pos + 1 and so on
+                let mut count = 0;
+                if *greedy {
+                    while max.is_none_or(|max| count < max) && self.step(text, inner, pos + count, state).is_some() {
+                        count += 1;
+                    }
+                    (*min..=count).rev().any(|n| self.sequence(text, rest, pos + n, state, next))
+                } else {
+                    loop {
+                        if count >= *min && self.sequence(text, rest, pos + count, state, next) {
+                            return true;
+                        }
+                        if max.is_some_and(|max| count >= max) || self.step(text, inner, pos + count, state).is_none() {
+                            return false;
+                        }
+                        count += 1;
+                    }
+                }
+            }
+            Node::Repeat(inner, min, max, greedy) => self.repeat(text, (inner, *min, *max, *greedy), 0, pos, rest, state, next),
+            Node::MatchStart | Node::MatchEnd => {
+                let mark = if *node == Node::MatchStart { &mut state.start } else { &mut state.end };
+                let old = mark.replace(pos);
+                if self.sequence(text, rest, pos, state, next) {
+                    return true;
+                }
+                let mark = if *node == Node::MatchStart { &mut state.start } else { &mut state.end };
+                *mark = old;
+                false
+            }
+            _ => match self.step(text, node, pos, state) {
+                Some(end) => self.sequence(text, rest, end, state, next),
+                None => false,
+            },
+        }
+    }
+
+    /// A repeat of a group, matched once more for each level of recursion. An empty match
+    /// doesn't count once the least number of repeats is reached, so it can't go on forever.
+    #[allow(clippy::too_many_arguments)]
+    fn repeat(
+        &self,
+        text: &Haystack,
+        repeat: (&Node, usize, Option<usize>, bool),
+        count: usize,
+        pos: usize,
+        rest: &[Node],
+        state: &mut State,
+        next: &mut dyn FnMut(usize, &mut State) -> bool,
+    ) -> bool {
+        let (inner, min, max, greedy) = repeat;
+        if !greedy && count >= min && self.sequence(text, rest, pos, state, next) {
+            return true;
+        }
+        if max.is_none_or(|max| count < max) {
+            let saved = state.clone();
+            let mut again = |end: usize, state: &mut State| {
+                (end != pos || count < min) && self.repeat(text, repeat, count + 1, end, rest, state, next)
+            };
+            if self.sequence(text, std::slice::from_ref(inner), pos, state, &mut again) {
+                return true;
+            }
+            state.restore(saved);
+        }
+        greedy && count >= min && self.sequence(text, rest, pos, state, next)
+    }
+
+    /// Match a node that doesn't branch, returning where it ends.
+    fn step(&self, text: &Haystack, node: &Node, pos: usize, state: &State) -> Option<usize> {
+        let c = text.get(pos);
+        let before = pos.checked_sub(1).and_then(|i| text.get(i));
+        let matched = match node {
+            Node::Char(want) => return c.filter(|c| self.chars_equal(*c, *want)).map(|_| pos + 1),
+            Node::Any(newline) => return c.filter(|c| *c != '\n' || *newline).map(|_| pos + 1),
+            Node::Set(set) => return c.filter(|c| set.contains(*c, self.ignore_case, &self.keywords)).map(|_| pos + 1),
+            Node::LineStart => before.is_none_or(|c| c == '\n'),
+            Node::LineEnd => c.is_none_or(|c| c == '\n'),
+            Node::WordStart => self.is_keyword(c) && !self.is_keyword(before),
+            Node::WordEnd => self.is_keyword(before) && !self.is_keyword(c),
+            Node::TextStart => pos == 0,
+            Node::TextEnd => pos == text.len(),
+            Node::BackRef(n) => {
+                let Some((start, end)) = state.groups.get(*n).copied().flatten() else {
+                    return Some(pos);
+                };
+                let length = end - start;
+                let same = (0..length).all(|i| match (text.get(start + i), text.get(pos + i)) {
+                    (Some(a), Some(b)) => self.chars_equal(a, b),
+                    _ => false,
+                });
+                return same.then_some(pos + length);
+            }
+            Node::Group(..) | Node::Repeat(..) | Node::MatchStart | Node::MatchEnd => false,
+        };
+        matched.then_some(pos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn find(pattern: &str, text: &str) -> Option<String> {
+        let mut options = Options::new();
+        options.ignorecase = true;
+        options.smartcase = true;
+        let chars: Vec<char> = text.chars().collect();
+        // Split the text so that matches run across chunks
+        let haystack = Haystack::from_chunks(chars.chunks(2));
+        let regex = Regex::new(pattern, &options).expect(pattern);
+        regex.find_at(&haystack, 0, chars.len()).map(|(s, e)| chars[s..e].iter().collect())
+    }
+
+    #[test]
+    fn regex() {
+        assert_eq!(find("b.d", "abcde").as_deref(), Some("bcd"));
+        assert_eq!(find("a*b\\+", "xaabbbc").as_deref(), Some("aabbb"));
+        assert_eq!(find("\\v(ab)+c", "xababcd").as_deref(), Some("ababc"));
+        assert_eq!(find("\\(foo\\|bar\\)\\{2}", "foo barfoo").as_deref(), Some("barfoo"));
+        assert_eq!(find("a\\{-1,}", "aaa").as_deref(), Some("a"));
+        assert_eq!(find("\\<is\\>", "this is").as_deref(), Some("is"));
+        assert_eq!(find("piece_\\zstable\\ze(", "piece_table(").as_deref(), Some("table"));
+        assert_eq!(find("\\d\\+\\s\\w", "ab 12 x").as_deref(), Some("12 x"));
+        assert_eq!(find("[a-c]\\+[^a-c]", "xxabcabd").as_deref(), Some("abcabd"));
+        assert_eq!(find("[[:upper:]]x", "aAx").as_deref(), Some("Ax"));
+        assert_eq!(find("\\Va.b", "axb a.b").as_deref(), Some("a.b"));
+        assert_eq!(find("\\Ma*", "aa a*").as_deref(), Some("a*"));
+        assert_eq!(find("^b", "ab\nb").as_deref(), Some("b"));
+        assert_eq!(find("a$", "ab\nba").as_deref(), Some("a"));
+        assert_eq!(find("a\\nb", "xa\nb").as_deref(), Some("a\nb"));
+        assert_eq!(find("a\\_s*b", "a \n b").as_deref(), Some("a \n b"));
+        assert_eq!(find("a.*b", "a\nb"), None);
+        assert_eq!(find("\\(a\\)\\1", "abaa").as_deref(), Some("aa"));
+        assert_eq!(find("foo", "FOO").as_deref(), Some("FOO"));
+        assert_eq!(find("Foo", "FOO foo Foo").as_deref(), Some("Foo"));
+        assert_eq!(find("\\CFOO", "foo"), None);
+        assert_eq!(find("Foo\\c", "fOO").as_deref(), Some("fOO"));
+        assert_eq!(find("\\%x41", "xA").as_deref(), Some("A"));
+        assert!(Regex::new("\\(a", &Options::new()).is_err());
+        assert!(Regex::new("a\\)", &Options::new()).is_err());
+        assert!(Regex::new("a\\{x}", &Options::new()).is_err());
+        assert_eq!(Regex::new("a~", &Options::new()).err().as_deref(), Some("E33: No previous substitute regular expression"));
+        assert_eq!(find("a\\~", "a~").as_deref(), Some("a~"));
+        assert_eq!(find("\\Va~", "a~").as_deref(), Some("a~"));
+    }
+
+    #[test]
+    fn backtracking_gives_up() {
+        let started = std::time::Instant::now();
+        assert_eq!(find("\\v(a*)*c", &"a".repeat(63)), None);
+        assert_eq!(find("\\v(a|aa)*c", &"a".repeat(200)), None);
+        assert!(started.elapsed().as_secs() < 1);
+
+        // A search through a long text without a match still finds one at the end
+        let text = format!("{}c", "a".repeat(50_000));
+        assert_eq!(find("\\va*c", &text).map(|m| m.len()), Some(50_001));
+        assert_eq!(find("b\\|c", &text).as_deref(), Some("c"));
+    }
+}
@@ -0,0 +1,250 @@
+/// How the text of a register was taken, which decides how it is put back.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum RegisterKind {
+    Chars,
+    Lines,
+    Block(usize), // Display width of the block
+}
+
+/// Text held in a register, one entry per line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Register {
+    pub lines: Vec<String>,
+    pub kind: RegisterKind,
+}
+
+impl Register {
+    pub fn new(text: &str, kind: RegisterKind) -> Self {
+        Self {
+            lines: text.split('\n').map(|l| l.to_string()).collect(),
+            kind,
+        }
+    }
+
+    /// Text from outside, such as the system clipboard. It is linewise if it ends in a newline.
+    pub fn from_text(text: &str) -> Self {
+        match text.strip_suffix('\n') {
+            Some(text) => Self::new(text, RegisterKind::Lines),
+            None => Self::new(text, RegisterKind::Chars),
+        }
+    }
+
+    /// The text as it would be written out, linewise text ends in a newline.
+    pub fn text(&self) -> String {
+        let mut text = self.lines.join("\n");
+        if self.kind == RegisterKind::Lines {
+            text.push('\n');
+        }
+        text
+    }
+
+    /// Add text to the end of the register, as yanking into "A does for "a. Anything linewise
+    /// makes the result linewise.
+    fn append(&mut self, other: Register) {
+        match (self.kind, other.kind) {
+            (RegisterKind::Chars, RegisterKind::Chars) => {
+                let mut lines = other.lines.into_iter();
+                if let (Some(last), Some(first)) = (self.lines.last_mut(), lines.next()) {
+                    last.push_str(&first);
+                }
+                self.lines.extend(lines);
+            }
+            (RegisterKind::Block(a), RegisterKind::Block(b)) => {
+                self.lines.extend(other.lines);
+                self.kind = RegisterKind::Block(a.max(b));
+            }
+            (RegisterKind::Lines, _) | (_, RegisterKind::Lines) => {
+                self.lines.extend(other.lines);
+                self.kind = RegisterKind::Lines;
+            }
+            _ => self.lines.extend(other.lines),
+        }
+    }
+}
+
+/// The registers of vim, selected with "x before a command.
+///
+/// Yanks go to "0 and deletes to "1, shifting the older deletes up to "9, or to "- if they are
+/// within a line. Naming a register stores the text there instead, an uppercase name appends
+/// to it, and "_ throws the text away. The unnamed register "" is whichever register was
+/// written last. ". : % and / hold the last inserted text, command line, file name and search,
+/// and can only be read. "+ and "* are the system clipboard, the text written to them is kept
+/// until it has been handed over with `take_copied`.
+#[derive(Debug, Default)]
+pub struct Registers {
+    named: [Option<Register>; 26],
+    numbered: [Option<Register>; 10],
+    small_delete: Option<Register>,
+    read_only: [Option<String>; 4],
+    clipboard: Option<Register>, // The last text copied to the clipboard from here
+    copied: Option<String>,      // Clipboard text that still has to be copied
+    unnamed: Option<char>,
+}
+
+const READ_ONLY: [char; 4] = ['.', ':', '%', '/'];
+
+impl Registers {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_valid(name: char) -> bool {
+        name.is_ascii_alphanumeric() || "\"-_+*".contains(name) || READ_ONLY.contains(&name)
+    }
+
+    pub fn get(&self, name: char) -> Option<Register> {
+        match name {
+            '"' => self.get(self.unnamed?),
+            '0'..='9' => self.numbered[name as usize - '0' as usize].clone(),
+            'a'..='z' | 'A'..='Z' => self.named[name.to_ascii_lowercase() as usize - 'a' as usize].clone(),
+            '-' => self.small_delete.clone(),
+            '+' | '*' => self.clipboard.clone(),
+            _ => {
+                let i = READ_ONLY.iter().position(|r| *r == name)?;
+                let text = self.read_only[i].as_ref()?;
+                Some(Register::new(text, RegisterKind::Chars))
+            }
+        }
+    }
+
+    /// Store yanked text, in "0 unless a register is named.
+    pub fn yank(&mut self, name: Option<char>, register: Register) -> Result<(), String> {
+        match name {
+            None | Some('"') => self.set('0', register),
+            Some(name) => self.set(name, register),
+        }
+    }
+
+    /// Store deleted text. Deletes of more than a line, or with a motion that always uses it,
+    /// go to "1 even when a register is named.
+    pub fn delete(&mut self, name: Option<char>, register: Register, use_reg_one: bool) -> Result<(), String> {
+        let name = name.filter(|n| *n != '"');
+        if name == Some('_') {
+            return Ok(());
+        }
+        if let Some(name) = name {
+            self.set(name, register.clone())?;
+        }
+
+        let small = register.kind != RegisterKind::Lines && register.lines.len() == 1;
+        if !small || use_reg_one {
+            self.numbered[1..].rotate_right(1);
+            self.set('1', register)?;
+        } else if name.is_none() {
+            self.set('-', register)?;
+        }
+        Ok(())
+    }
+
+    /// Store the keys of a recording. Unlike yanks this leaves the unnamed register alone.
+    pub fn record(&mut self, name: char, keys: &str) -> Result<(), String> {
+        let unnamed = self.unnamed;
+        self.set(name, Register::new(keys, RegisterKind::Chars))?;
+        self.unnamed = unnamed;
+        Ok(())
+    }
+
+    /// Text written to "+ or "* since the last call, for the system clipboard.
+    pub fn take_copied(&mut self) -> Option<String> {
+        self.copied.take()
+    }
+
+    /// Record the text of a read-only register, which the user can't write.
+    pub fn set_read_only(&mut self, name: char, text: &str) {
+        if let Some(i) = READ_ONLY.iter().position(|r| *r == name) {
+            self.read_only[i] = Some(text.to_string());
+        }
+    }
+
+    fn set(&mut self, name: char, register: Register) -> Result<(), String> {
+        match name {
+            '_' => return Ok(()),
+            '0'..='9' => self.numbered[name as usize - '0' as usize] = Some(register),
+            'a'..='z' => self.named[name as usize - 'a' as usize] = Some(register),
+            'A'..='Z' => {
+                let slot = &mut self.named[name.to_ascii_lowercase() as usize - 'a' as usize];
+                match slot {
+                    Some(old) => old.append(register),
+                    None => *slot = Some(register),
+                }
+            }
+            '-' => self.small_delete = Some(register),
+            '+' | '*' => {
+                self.copied = Some(register.text());
+                self.clipboard = Some(register);
+            }
+            _ => return Err(format!("E354: Invalid register name: '{name}'")),
+        }
+        self.unnamed = Some(name.to_ascii_lowercase());
+        Ok(())
+    }
+
+    /// The contents of the registers for :registers, limited to `names` if it isn't empty.
+    pub fn list(&self, names: &str) -> Vec<String> {
+        let mut lines = vec!["Type Name Content".to_string()];
+        // An upper case name reads the same register as the lower case one
+        let names = names.to_lowercase();
+        let all = "\"0123456789abcdefghijklmnopqrstuvwxyz-*+.:%/";
+        for name in all.chars().filter(|n| names.is_empty() || names.contains(*n)) {
+            let Some(register) = self.get(name) else {
+                continue;
+            };
+            let kind = match register.kind {
+                RegisterKind::Chars => 'c',
+                RegisterKind::Lines => 'l',
+                RegisterKind::Block(_) => 'b',
+            };
+            lines.push(format!("  {kind}  \"{name}   {}", printable(&register.text())));
+        }
+        lines
+    }
+}
+
+/// Control characters as ^X, so a newline shows as ^J.
+fn printable(text: &str) -> String {
+    text.chars()
+        .map(|c| match c as u32 {
+            0..=31 => format!("^{}", (c as u8 + b'@') as char),
+            127 => "^?".to_string(),
+            _ => c.to_string(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn yank_and_delete() {
+        let mut registers = Registers::new();
+        let chars = |text: &str| Register::new(text, RegisterKind::Chars);
+        let lines = |text: &str| Register::new(text, RegisterKind::Lines);
+
+        registers.yank(None, chars("foo")).unwrap();
+        registers.delete(None, chars("x"), false).unwrap();
+        registers.delete(None, lines("one"), false).unwrap();
+        registers.delete(None, lines("two"), false).unwrap();
+        assert_eq!(registers.get('0'), Some(chars("foo")));
+        assert_eq!(registers.get('-'), Some(chars("x")));
+        assert_eq!(registers.get('1'), Some(lines("two")));
+        assert_eq!(registers.get('2'), Some(lines("one")));
+        assert_eq!(registers.get('"'), Some(lines("two")));
+
+        registers.yank(Some('a'), chars("ab")).unwrap();
+        registers.yank(Some('A'), chars("cd")).unwrap();
+        assert_eq!(registers.get('a'), Some(chars("abcd")));
+        registers.yank(Some('A'), lines("ef")).unwrap();
+        assert_eq!(registers.get('a'), Some(lines("abcd\nef")));
+        assert_eq!(registers.get('"'), registers.get('a'));
+
+        registers.delete(Some('_'), chars("gone"), false).unwrap();
+        assert_eq!(registers.get('-'), Some(chars("x")));
+        assert!(registers.yank(Some('.'), chars("no")).is_err());
+        registers.set_read_only('.', "typed");
+        assert_eq!(registers.get('.'), Some(chars("typed")));
+        assert_eq!(registers.list("a."), ["Type Name Content", "  l  \"a   abcd^Jef^J", "  c  \".   typed"]);
+        assert_eq!(registers.get('A'), registers.get('a'));
+        assert_eq!(registers.list("A"), registers.list("a"));
+    }
+}
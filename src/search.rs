@@ -0,0 +1,122 @@
+use crate::options::Options;
+use crate::regex::{Haystack, Regex};
+
+/// Where the cursor goes relative to a match, from the offset typed after the pattern as in
+/// "/foo/e+1".
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SearchOffset {
+    Lines(isize), // [num], +[num] or -[num], lines below the match in the first column, linewise
+    Start(isize), // s[num] or b[num], characters from the start of the match
+    End(isize),   // e[num], characters from the last character of the match, inclusive
+}
+
+/// A search as typed after / or ?, which n and N repeat.
+#[derive(Debug, Clone)]
+pub struct Search {
+    pub pattern: Pattern,
+    pub forward: bool,
+    pub offset: SearchOffset,
+}
+
+impl Search {
+    /// Parse the text typed after / or ?: a pattern up to the first unescaped `/` or `?`, and an
+    /// offset after it. The pattern is None when it is empty, which searches for the last one.
+    pub fn parse(typed: &str, forward: bool, options: &Options) -> Result<(Option<Pattern>, SearchOffset), String> {
+        let delimiter = if forward { '/' } else { '?' };
+        let mut escaped = false;
+        let end = typed
+            .char_indices()
+            .find(|(_, c)| {
+                let found = *c == delimiter && !escaped;
+                escaped = *c == '\\' && !escaped;
+                found
+            })
+            .map(|(i, _)| i);
+
+        let (pattern, offset) = match end {
+            Some(i) => (&typed[..i], parse_offset(&typed[i + 1..])?),
+            None => (typed, SearchOffset::Start(0)),
+        };
+        let pattern = match pattern {
+            "" => None,
+            p => Some(Pattern::new(&p.replace(&format!("\\{delimiter}"), &delimiter.to_string()), options)?),
+        };
+        Ok((pattern, offset))
+    }
+}
+
+fn parse_offset(s: &str) -> Result<SearchOffset, String> {
+    let invalid = || format!("E488: Trailing characters: {s}");
+    let (kind, number) = match s.chars().next() {
+        Some(c @ ('e' | 's' | 'b')) => (Some(c), &s[1..]),
+        _ => (None, s),
+    };
+    let amount = match number {
+        "" => 0,
+        "+" => 1,
+        "-" => -1,
+        n => n.strip_prefix('+').unwrap_or(n).parse::<isize>().map_err(|_| invalid())?,
+    };
+
+    Ok(match kind {
+        Some('e') => SearchOffset::End(amount),
+        Some(_) => SearchOffset::Start(amount),
+        None if number.is_empty() => SearchOffset::Start(0),
+        None => SearchOffset::Lines(amount),
+    })
+}
+
+/// A pattern to search for, a vim regular expression.
+#[derive(Debug, Clone)]
+pub struct Pattern {
+    source: String,
+    regex: Regex,
+}
+
+impl Pattern {
+    pub fn new(source: &str, options: &Options) -> Result<Self, String> {
+        Ok(Self { source: source.to_string(), regex: Regex::new(source, options)? })
+    }
+
+    /// The pattern as it was typed, for messages and the "/ register.
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// Every match that starts in from..=to, in order. Each match starts after the end of the
+    /// one before it.
+    pub fn find_all(&self, text: &Haystack, from: usize, to: usize) -> Vec<(usize, usize)> {
+        self.matches(text, from, to).collect()
+    }
+
+    /// The matches of `find_all` one at a time, for when only the first few are wanted.
+    pub fn matches<'a>(&'a self, text: &'a Haystack<'a>, from: usize, to: usize) -> impl Iterator<Item = (usize, usize)> + 'a {
+        let mut pos = from;
+        std::iter::from_fn(move || {
+            let (start, end) = self.regex.find_at(text, pos, to)?;
+            pos = end.max(start + 1).max(pos + 1);
+            Some((start, end))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let options = Options::new();
+        let parse = |typed, forward| Search::parse(typed, forward, &options).map(|(p, o)| (p.map(|p| p.source().to_string()), o));
+        assert_eq!(parse("foo", true), Ok((Some("foo".to_string()), SearchOffset::Start(0))));
+        assert_eq!(parse("foo/e+1", true), Ok((Some("foo".to_string()), SearchOffset::End(1))));
+        assert_eq!(parse("a\\/b/-", true), Ok((Some("a/b".to_string()), SearchOffset::Lines(-1))));
+        assert_eq!(parse("a/b?b-2", false), Ok((Some("a/b".to_string()), SearchOffset::Start(-2))));
+        assert_eq!(parse("/3", true), Ok((None, SearchOffset::Lines(3))));
+        assert!(parse("foo/x", true).is_err());
+
+        let pattern = Pattern::new("a\\|\\zeb", &options).unwrap();
+        let chars: Vec<char> = "aaba".chars().collect();
+        assert_eq!(pattern.find_all(&Haystack::from_chunks(std::iter::once(&chars[..])), 0, 4), [(0, 1), (1, 2), (2, 2), (3, 4)]);
+    }
+}
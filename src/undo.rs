@@ -0,0 +1,460 @@
+use std::time::{Duration, SystemTime};
+
+use crate::piece_table::PieceTable;
+
+/// A single reversible modification of a `PieceTable`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Edit {
+    Insert { idx: usize, text: String },
+    Delete { idx: usize, text: String },
+}
+
+impl Edit {
+    pub fn apply(&self, table: &mut PieceTable) {
+        match self {
+            Edit::Insert { idx, text } => table.insert_str(*idx, text),
+            Edit::Delete { idx, text } => table.delete_range(*idx, idx + text.chars().count()),
+        }
+    }
+
+    pub fn invert(&self) -> Edit {
+        match self {
+            Edit::Insert { idx, text } => Edit::Delete { idx: *idx, text: text.clone() },
+            Edit::Delete { idx, text } => Edit::Insert { idx: *idx, text: text.clone() },
+        }
+    }
+
+    pub fn start(&self) -> usize {
+        match self {
+            Edit::Insert { idx, .. } | Edit::Delete { idx, .. } => *idx,
+        }
+    }
+
+    /// Try to fold `next` into this edit, as happens when typing or backspacing one character at
+    /// a time. Returns false if the two edits are not contiguous.
+    fn merge(&mut self, next: &Edit) -> bool {
+        match (self, next) {
+            (Edit::Insert { idx, text }, Edit::Insert { idx: n_idx, text: n_text })
+                if *n_idx == *idx + text.chars().count() =>
+            {
+                text.push_str(n_text);
+                true
+            }
+            (Edit::Delete { idx, text }, Edit::Delete { idx: n_idx, text: n_text }) => {
+                if *n_idx == *idx {
+                    // Delete under the cursor, the next character slides into place
+                    text.push_str(n_text);
+                    true
+                } else if *n_idx + n_text.chars().count() == *idx {
+                    // Backspace, the deleted text grows towards the start of the buffer
+                    *idx = *n_idx;
+                    text.insert_str(0, n_text);
+                    true
+                } else {
+                    false
+                }
+            }
+            _ => false,
+        }
+    }
+}
+
+/// One undo step, all of the edits made by a single command or insert session.
+#[derive(Debug, Clone)]
+pub struct Change {
+    pub edits: Vec<Edit>,
+    pub cursor: usize, // Cursor index before the change was made
+}
+
+impl Change {
+    /// Revert the change, returning the index the cursor should be restored to.
+    pub fn undo(&self, table: &mut PieceTable) -> usize {
+        for edit in self.edits.iter().rev() {
+            edit.invert().apply(table);
+        }
+        self.cursor
+    }
+
+    /// Reapply the change, returning the index of the start of the first edit.
+    pub fn redo(&self, table: &mut PieceTable) -> usize {
+        for edit in self.edits.iter() {
+            edit.apply(table);
+        }
+        self.edits.first().map_or(self.cursor, |e| e.start())
+    }
+}
+
+/// How far to travel through the undo tree with `:earlier` and `:later`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UndoDistance {
+    Steps(usize),
+    Time(Duration),
+    FileWrites(usize),
+}
+
+impl UndoDistance {
+    /// Parse the argument of `:earlier`/`:later`, e.g. "5", "10s", "5m", "1h", "2d" or "3f".
+    pub fn parse(s: &str) -> Option<Self> {
+        let s = s.trim();
+        if s.is_empty() {
+            return Some(UndoDistance::Steps(1));
+        }
+
+        let (n, unit) = match s.find(|c: char| !c.is_ascii_digit()) {
+            Some(i) => (&s[..i], &s[i..]),
+            None => (s, ""),
+        };
+        let n: u64 = n.parse().ok()?;
+        match unit {
+            "" => Some(UndoDistance::Steps(n as usize)),
+            "s" => Some(UndoDistance::Time(Duration::from_secs(n))),
+            "m" => Some(UndoDistance::Time(Duration::from_secs(n * 60))),
+            "h" => Some(UndoDistance::Time(Duration::from_secs(n * 60 * 60))),
+            "d" => Some(UndoDistance::Time(Duration::from_secs(n * 24 * 60 * 60))),
+            "f" => Some(UndoDistance::FileWrites(n as usize)),
+            _ => None,
+        }
+    }
+}
+
+struct UndoNode {
+    parent: usize,
+    children: Vec<usize>,
+    redo_child: Option<usize>, // Child that Ctrl-R follows, the branch most recently undone from
+    change: Change,
+    time: SystemTime,
+    save: Option<usize>, // Number of the last file write made in this state
+}
+
+/// Every text state the buffer has been in. Nodes are numbered in the order the changes were
+/// made, node 0 is the text as it was loaded. Undoing and then making a new change starts a new
+/// branch instead of discarding the undone changes.
+pub struct UndoTree {
+    nodes: Vec<UndoNode>,
+    current: usize,
+    pending: Option<Change>,
+    saves: usize,
+}
+
+impl UndoTree {
+    pub fn new() -> Self {
+        let root = UndoNode {
+            parent: 0,
+            children: Vec::<usize>::new(),
+            redo_child: None,
+            change: Change { edits: Vec::<Edit>::new(), cursor: 0 },
+            time: SystemTime::now(),
+            save: None,
+        };
+
+        Self {
+            nodes: vec![root],
+            current: 0,
+            pending: None,
+            saves: 0,
+        }
+    }
+
+    /// Record an edit that has already been applied to the text. Edits are grouped into the
+    /// pending change until `commit` is called.
+    pub fn record(&mut self, edit: Edit, cursor: usize) {
+        match &mut self.pending {
+            Some(change) => {
+                let merged = match change.edits.last_mut() {
+                    Some(last) => last.merge(&edit),
+                    None => false,
+                };
+                if !merged {
+                    change.edits.push(edit);
+                }
+            }
+            None => {
+                self.pending = Some(Change { edits: vec![edit], cursor });
+            }
+        }
+    }
+
+    /// Close the pending change so that it becomes a single undo step.
+    pub fn commit(&mut self) {
+        if let Some(change) = self.pending.take() {
+            let seq = self.nodes.len();
+            self.nodes.push(UndoNode {
+                parent: self.current,
+                children: Vec::<usize>::new(),
+                redo_child: None,
+                change,
+                time: SystemTime::now(),
+                save: None,
+            });
+            self.nodes[self.current].children.push(seq);
+            self.nodes[self.current].redo_child = Some(seq);
+            self.current = seq;
+        }
+    }
+
+    pub fn undo(&mut self, table: &mut PieceTable) -> Option<usize> {
+        self.commit();
+        if self.current == 0 {
+            return None;
+        }
+
+        let node = self.current;
+        let cursor = self.nodes[node].change.undo(table);
+        self.current = self.nodes[node].parent;
+        self.nodes[self.current].redo_child = Some(node);
+        Some(cursor)
+    }
+
+    pub fn redo(&mut self, table: &mut PieceTable) -> Option<usize> {
+        self.commit();
+        let node = self.nodes[self.current].redo_child?;
+        let cursor = self.nodes[node].change.redo(table);
+        self.current = node;
+        Some(cursor)
+    }
+
+    /// Move the text to the state after change `target`, undoing back to the common ancestor
+    /// and redoing down the target's branch.
+    pub fn jump_to(&mut self, table: &mut PieceTable, target: usize) -> Option<usize> {
+        self.commit();
+        if target >= self.nodes.len() || target == self.current {
+            return None;
+        }
+
+        let mut path = vec![target];
+        while *path.last().unwrap() != 0 {
+            path.push(self.nodes[*path.last().unwrap()].parent);
+        }
+
+        let mut cursor = None;
+        while !path.contains(&self.current) {
+            cursor = self.undo(table);
+        }
+
+        let ancestor = path.iter().position(|n| *n == self.current).unwrap();
+        for node in path[..ancestor].iter().rev() {
+            self.nodes[self.current].redo_child = Some(*node);
+            cursor = self.redo(table);
+        }
+
+        cursor
+    }
+
+    pub fn earlier(&mut self, table: &mut PieceTable, distance: UndoDistance) -> Option<usize> {
+        self.commit();
+        let target = match distance {
+            UndoDistance::Steps(n) => self.current.saturating_sub(n),
+            UndoDistance::Time(d) => {
+                let time = self.nodes[self.current].time.checked_sub(d).unwrap_or(SystemTime::UNIX_EPOCH);
+                self.nodes.iter().rposition(|n| n.time <= time).unwrap_or(0)
+            }
+            UndoDistance::FileWrites(n) => {
+                let saves = self.saved_states();
+                match saves.iter().rposition(|s| *s <= self.current) {
+                    Some(i) => {
+                        // With changes since the last write, 1f only reverts them
+                        let n = if saves[i] == self.current { n } else { n.saturating_sub(1) };
+                        if n > i { 0 } else { saves[i - n] }
+                    }
+                    None => 0,
+                }
+            }
+        };
+
+        self.jump_to(table, target)
+    }
+
+    pub fn later(&mut self, table: &mut PieceTable, distance: UndoDistance) -> Option<usize> {
+        self.commit();
+        let last = self.nodes.len() - 1;
+        let target = match distance {
+            UndoDistance::Steps(n) => (self.current + n).min(last),
+            UndoDistance::Time(d) => {
+                let time = self.nodes[self.current].time + d;
+                self.nodes.iter().rposition(|n| n.time <= time).unwrap_or(last).max(self.current)
+            }
+            UndoDistance::FileWrites(n) => {
+                let saves: Vec<usize> = self.saved_states().into_iter().filter(|s| *s > self.current).collect();
+                if n == 0 || n > saves.len() {
+                    last
+                } else {
+                    saves[n - 1]
+                }
+            }
+        };
+
+        self.jump_to(table, target)
+    }
+
+    /// Note that the text was written to disk in the current state.
+    pub fn mark_saved(&mut self) {
+        self.commit();
+        self.saves += 1;
+        self.nodes[self.current].save = Some(self.saves);
+    }
+
+    /// Whether the text has changed since it was last written, or since it was loaded. Undoing
+    /// back to the state last written makes it unchanged again.
+    pub fn is_modified(&self) -> bool {
+        let saved = self.saved_states().last().copied().unwrap_or(0);
+        self.pending.is_some() || self.current != saved
+    }
+
+    fn saved_states(&self) -> Vec<usize> {
+        let mut saves: Vec<(usize, usize)> = self.nodes.iter().enumerate().filter_map(|(i, n)| n.save.map(|s| (s, i))).collect();
+        saves.sort();
+        saves.into_iter().map(|(_, i)| i).collect()
+    }
+
+    pub fn current(&self) -> usize {
+        self.current
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.len() == 1
+    }
+
+    pub fn children(&self, node: usize) -> &[usize] {
+        &self.nodes[node].children
+    }
+
+    pub fn time(&self, node: usize) -> SystemTime {
+        self.nodes[node].time
+    }
+
+    pub fn save(&self, node: usize) -> Option<usize> {
+        self.nodes[node].save
+    }
+
+    /// The `:undolist` table, one row per leaf of the tree.
+    pub fn undolist(&self) -> Vec<String> {
+        let mut rows = vec!["number changes  when               saved".to_string()];
+        for (i, node) in self.nodes.iter().enumerate().skip(1) {
+            if node.children.is_empty() {
+                let mut depth = 0;
+                let mut n = i;
+                while n != 0 {
+                    n = self.nodes[n].parent;
+                    depth += 1;
+                }
+
+                let save = node.save.map_or("".to_string(), |s| s.to_string());
+                rows.push(format!("{i:>6} {depth:>7}  {:<18} {save:>5}", format_age(node.time)));
+            }
+        }
+        rows
+    }
+}
+
+impl Default for UndoTree {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Describe a time as vim does in `:undolist`, e.g. "12 seconds ago".
+pub fn format_age(time: SystemTime) -> String {
+    let secs = SystemTime::now().duration_since(time).unwrap_or_default().as_secs();
+    if secs < 100 {
+        format!("{secs} seconds ago")
+    } else if secs < 100 * 60 {
+        format!("{} minutes ago", secs / 60)
+    } else if secs < 48 * 60 * 60 {
+        format!("{} hours ago", secs / (60 * 60))
+    } else {
+        format!("{} days ago", secs / (24 * 60 * 60))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn contents(table: &PieceTable) -> String {
+        (0..table.len()).map(|i| table.index(i)).collect()
+    }
+
+    #[test]
+    fn grouped_undo_redo() {
+        let mut table = PieceTable::new("ipsum");
+        let mut history = UndoTree::new();
+
+        for (i, c) in "Lorem ".chars().enumerate() {
+            table.insert(i, c);
+            history.record(Edit::Insert { idx: i, text: c.to_string() }, 0);
+        }
+        history.commit();
+
+        table.delete(10);
+        history.record(Edit::Delete { idx: 10, text: "m".to_string() }, 10);
+        table.delete(9);
+        history.record(Edit::Delete { idx: 9, text: "u".to_string() }, 10);
+        history.commit();
+        assert_eq!(contents(&table), "Lorem ips");
+
+        assert_eq!(history.undo(&mut table), Some(10));
+        assert_eq!(contents(&table), "Lorem ipsum");
+        assert_eq!(history.undo(&mut table), Some(0));
+        assert_eq!(contents(&table), "ipsum");
+        assert_eq!(history.undo(&mut table), None);
+
+        assert_eq!(history.redo(&mut table), Some(0));
+        assert_eq!(contents(&table), "Lorem ipsum");
+
+        // A new change starts a branch, the undone delete is kept as a sibling
+        table.insert(11, '!');
+        history.record(Edit::Insert { idx: 11, text: "!".to_string() }, 11);
+        history.commit();
+        assert_eq!(history.redo(&mut table), None);
+        assert_eq!(contents(&table), "Lorem ipsum!");
+        assert_eq!(history.children(1), &[2, 3]);
+
+        history.jump_to(&mut table, 2);
+        assert_eq!(contents(&table), "Lorem ips");
+        history.earlier(&mut table, UndoDistance::Steps(1));
+        assert_eq!(contents(&table), "Lorem ipsum");
+        history.later(&mut table, UndoDistance::Steps(2));
+        assert_eq!(contents(&table), "Lorem ipsum!");
+    }
+
+    #[test]
+    fn file_write_states() {
+        let mut table = PieceTable::new("");
+        let mut history = UndoTree::new();
+        assert!(!history.is_modified());
+
+        for (i, c) in "abc".chars().enumerate() {
+            table.insert(i, c);
+            history.record(Edit::Insert { idx: i, text: c.to_string() }, i);
+            history.commit();
+            if i != 1 {
+                history.mark_saved();
+            }
+        }
+        assert!(!history.is_modified());
+        table.insert(3, 'd');
+        history.record(Edit::Insert { idx: 3, text: "d".to_string() }, 3);
+        assert!(history.is_modified());
+        history.commit();
+
+        history.earlier(&mut table, UndoDistance::FileWrites(1));
+        assert_eq!(contents(&table), "abc");
+        history.earlier(&mut table, UndoDistance::FileWrites(1));
+        assert_eq!(contents(&table), "a");
+        history.earlier(&mut table, UndoDistance::FileWrites(1));
+        assert_eq!(contents(&table), "");
+        history.later(&mut table, UndoDistance::FileWrites(2));
+        assert_eq!(contents(&table), "abc");
+        assert!(!history.is_modified());
+        history.later(&mut table, UndoDistance::FileWrites(1));
+        assert_eq!(contents(&table), "abcd");
+
+        assert_eq!(UndoDistance::parse("5m"), Some(UndoDistance::Time(Duration::from_secs(300))));
+        assert_eq!(UndoDistance::parse("3f"), Some(UndoDistance::FileWrites(3)));
+        assert_eq!(UndoDistance::parse("2x"), None);
+    }
+}
@@ -1,8 +1,9 @@
-use crate::interface::CursorStyle;
+use crate::interface::{CursorStyle, Highlight};
 use crate::position::Position;
 
 pub mod command_view;
 pub mod text_view;
+pub mod undo_tree_view;
 
 pub trait View {
     fn set_size(&mut self, p: Position);
@@ -11,4 +12,9 @@ pub trait View {
     fn get_updates(&mut self) -> Vec<bool>;
     fn get_cursor_pos(&self) -> Position;
     fn get_cursor_style(&self) -> CursorStyle;
+
+    /// Highlight of each cell of the view, empty if nothing is highlighted.
+    fn get_highlights(&self) -> Vec<Highlight> {
+        Vec::new()
+    }
 }
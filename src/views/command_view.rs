@@ -7,12 +7,27 @@ use crate::position::Position;
 use crate::views::View;
 
 use crate::app::ApplicationCommand;
-use crate::views::text_view::TextCommand;
+use crate::undo::UndoDistance;
+use crate::motions::Motion;
+use crate::motions::objects::TextObject;
+use crate::options::Options;
+use crate::search::Search;
+use crate::views::text_view::{InsertAt, LineAddress, LineRange, LineSpec, MAX_REPEAT_LENGTH, MotionForce, Operator, OperatorTarget, TextCommand};
 
+use insert::{parse_insert, InsertCommand};
+use normal::{parse_normal, parse_visual, NormalCommand, Parsed, VisualCommand, VisualExtent};
+
+mod insert;
+mod normal;
+
+#[allow(clippy::enum_variant_names)]
 enum CommandViewModes {
     NormalMode,
     InsertMode,
-    CommandLineMode,
+    CommandLineMode(char), // After :, or / and ? which type a search
+    UndoTreeMode,
+    VisualMode(MotionForce),
+    ReplaceMode(bool), // R, or gR when true
 }
 
 pub struct CommandView {
@@ -24,6 +39,55 @@ pub struct CommandView {
     sz: Position,
     view: Vec<char>,
     updates: Vec<bool>,
+    message: Vec<String>,
+    insert_count: usize, // Times to repeat the text typed in insert mode, from 3ihello<Esc>
+    insert_lines: bool,  // The text is repeated on new lines, after o or O
+    inserted: String,      // Keys of the text typed since insert mode started, as in a register
+    last_inserted: String, // Keys of the text typed the last time, kept in ". for Ctrl-A
+    insert_once: bool, // Running a single normal mode command from Ctrl-O
+    cmdline: Vec<char>, // The command line being typed after :
+    cmdline_pos: usize,
+    options: Options,
+    last_find: Option<Motion>, // The last f, F, t or T, repeated by ; and ,
+    pending_search: Option<PendingSearch>, // The command that / or ? started, run once the search is typed
+    last_visual: Option<MotionForce>, // Kind of the last visual selection, for gv
+    register: Option<(char, Option<usize>)>, // From "x, with the count typed before it
+    change: Option<Change>,      // The change being typed, until insert mode is left
+    last_change: Option<Change>, // The last complete change, repeated by .
+    recording: Option<(char, Vec<Key>)>, // Register and keys typed since q{reg}
+    awaiting: bool, // Waiting for the text view to send the keys of @x or :normal
+    playing: Vec<(usize, bool)>, // Keys after each macro being run, and whether it started the playback
+}
+
+/// A change to the text as . repeats it: the command with its count and register, and the
+/// keys typed in insert mode after it, up to and including the Esc.
+#[derive(Debug, Clone)]
+struct Change {
+    cmd: NormalCommand,
+    count: Option<usize>,
+    register: Option<char>,
+    typed: Vec<Key>,
+}
+
+impl Change {
+    /// Typing that goes on after the cursor moved in insert mode, which . repeats as i.
+    fn typing() -> Self {
+        Change { cmd: NormalCommand::Insert(InsertAt::Cursor), count: None, register: None, typed: Vec::new() }
+    }
+
+    fn is_empty_typing(&self) -> bool {
+        self.cmd == NormalCommand::Insert(InsertAt::Cursor) && self.count.is_none() && self.typed == [Key::Esc]
+    }
+}
+
+/// A command with / or ? as its motion, which goes on once the search is typed, and the kind of
+/// selection to go back to when it was typed in visual mode.
+#[derive(Debug, Clone, Copy)]
+struct PendingSearch {
+    cmd: NormalCommand,
+    count: Option<usize>,
+    register: Option<char>,
+    visual: Option<MotionForce>,
 }
 
 impl CommandView {
@@ -37,14 +101,85 @@ impl CommandView {
             sz: Position { row: 0, col: 0 },
             view: Vec::<char>::new(),
             updates: Vec::<bool>::new(),
+            message: Vec::<String>::new(),
+            insert_count: 1,
+            insert_lines: false,
+            inserted: String::new(),
+            last_inserted: String::new(),
+            insert_once: false,
+            cmdline: Vec::new(),
+            cmdline_pos: 0,
+            options: Options::new(),
+            last_find: None,
+            pending_search: None,
+            last_visual: None,
+            register: None,
+            change: None,
+            last_change: None,
+            recording: None,
+            awaiting: false,
+            playing: Vec::new(),
+        }
+    }
+
+    pub fn set_options(&mut self, options: &Options) {
+        self.options = options.clone();
+    }
+
+    /// Display a message in place of the mode line until the next key press. Messages with
+    /// several lines need `get_required_rows` rows to be displayed.
+    pub fn show_message(&mut self, msg: &str) {
+        self.message = msg.lines().map(|l| l.to_string()).collect();
+        self.refresh_view();
+    }
+
+    pub fn get_required_rows(&self) -> u16 {
+        if self.message.len() > 1 {
+            self.message.len() as u16 + 1
+        } else {
+            1
         }
     }
 
     pub fn add_keystrokes(&mut self, mut keys: Vec<Key>) {
+        if let Some((_, recorded)) = self.recording.as_mut() {
+            recorded.extend_from_slice(&keys);
+        }
+        for (after, _) in self.playing.iter_mut() {
+            *after += keys.len();
+        }
         self.cmd.append(&mut keys);
         self.parse_commands();
     }
 
+    /// Run keys from @x or :normal as if typed, before any keys typed since. Each string is run
+    /// in turn, an error in the text view skips the rest of that run.
+    pub fn play_keys(&mut self, runs: Vec<String>) {
+        self.awaiting = false;
+        if self.playing.len() >= MAX_PLAYBACK_DEPTH {
+            self.message = vec!["E223: recursive mapping".to_string()];
+            let typed = self.playing[0].0;
+            self.cmd.drain(..self.cmd.len().saturating_sub(typed));
+            self.end_playback();
+            self.refresh_view();
+            return;
+        }
+
+        // A macro whose keys are used up is replaced instead of nested, so j@a can recurse
+        // through a whole file
+        while self.playing.last().is_some_and(|(after, _)| self.cmd.len() <= *after) {
+            self.playing.pop();
+        }
+        let first = self.playing.is_empty();
+        let mut keys = Vec::new();
+        for run in runs.iter().rev() {
+            self.playing.push((self.cmd.len() + keys.len(), first));
+            keys.splice(0..0, text_to_keys(run));
+        }
+        self.cmd.splice(0..0, keys);
+        self.parse_commands();
+    }
+
     pub fn has_text_command(&mut self) -> bool {
         !self.txt_cmds.is_empty()
     }
@@ -63,208 +198,919 @@ impl CommandView {
 
     fn refresh_view(&mut self) {
         match self.state {
-            CommandViewModes::NormalMode => {
-                let placeholder = "-- Normal --";
+            CommandViewModes::NormalMode if !self.message.is_empty() => {
+                for i in 0..self.view.len() {
+                    self.view[i] = ' ';
+                    self.updates[i] = true;
+                }
+
+                let mut lines = self.message.clone();
+                if lines.len() > 1 {
+                    lines.push("Press ENTER or type command to continue".to_string());
+                }
+                for (r, line) in lines.iter().enumerate().take(self.sz.row as usize) {
+                    for (i, c) in line.chars().enumerate() {
+                        if i < (self.sz.col as usize) {
+                            self.view[r * (self.sz.col as usize) + i] = c;
+                        }
+                    }
+                }
+            }
+            CommandViewModes::NormalMode | CommandViewModes::VisualMode(_) => {
+                for i in 0..self.view.len() {
+                    self.view[i] = ' ';
+                }
+                let placeholder = self.mode_line(match self.state {
+                    CommandViewModes::VisualMode(MotionForce::Charwise) => "-- Visual --",
+                    CommandViewModes::VisualMode(MotionForce::Linewise) => "-- Visual Line --",
+                    CommandViewModes::VisualMode(MotionForce::Blockwise) => "-- Visual Block --",
+                    _ if self.insert_once => "-- (insert) --",
+                    _ => "-- Normal --",
+                });
                 for (i, c) in placeholder.chars().enumerate() {
                     if i < (self.sz.col as usize) {
                         self.view[i] = c;
                     }
                 }
+
+                let showcmd_col = (self.sz.col as usize).saturating_sub(12);
+                for (i, c) in self.showcmd().chars().enumerate() {
+                    if showcmd_col + i < self.view.len() && showcmd_col > placeholder.len() {
+                        self.view[showcmd_col + i] = c;
+                    }
+                }
             },
-            CommandViewModes::InsertMode => {
-                let placeholder = "-- Insert --";
+            CommandViewModes::InsertMode | CommandViewModes::ReplaceMode(_) => {
+                for i in 0..self.view.len() {
+                    self.view[i] = ' ';
+                }
+                let placeholder = self.mode_line(match self.state {
+                    CommandViewModes::ReplaceMode(false) => "-- Replace --",
+                    CommandViewModes::ReplaceMode(true) => "-- VReplace --",
+                    _ => "-- Insert --",
+                });
                 for (i, c) in placeholder.chars().enumerate() {
                     if i < (self.sz.col as usize) {
                         self.view[i] = c;
                     }
                 }
             }
-            CommandViewModes::CommandLineMode => {
-                for c in 0..(self.sz.col as usize) {
-                    if self.view[c] != ' ' {
-                        self.view[c] = ' ';
+            CommandViewModes::CommandLineMode(prompt) => {
+                let width = self.sz.col as usize;
+                for i in 0..self.view.len() {
+                    self.view[i] = ' ';
+                }
+                self.view[0] = prompt;
+
+                // A line too long for the screen scrolls to keep the cursor in view
+                let skip = (self.cmdline_pos + 2).saturating_sub(width);
+                for (i, c) in self.cmdline.iter().skip(skip).enumerate() {
+                    if i + 1 < width {
+                        self.view[i + 1] = *c;
                     }
                 }
-                self.view[0] = ':';
+                self.cursor.col = (1 + self.cmdline_pos - skip) as u16;
             }
+            CommandViewModes::UndoTreeMode => {
+                for i in 0..self.view.len() {
+                    self.view[i] = ' ';
+                }
+                let placeholder = "-- Undo Tree --";
+                for (i, c) in placeholder.chars().enumerate() {
+                    if i < (self.sz.col as usize) {
+                        self.view[i] = c;
+                    }
+                }
+            }
+        }
+    }
+
+    /// The name of a mode for the mode line, followed by the register while recording.
+    fn mode_line(&self, mode: &str) -> String {
+        match self.recording {
+            Some((name, _)) => format!("{mode} recording @{name}"),
+            None => mode.to_string(),
         }
     }
 
-    fn parse_app_command(&self, s: &str) -> Result<ApplicationCommand, &'static str> {
-        if s == "q" {
-            return Ok(ApplicationCommand::Quit(false));
-        } else if s == "q!" {
-            return Ok(ApplicationCommand::Quit(true));
+    fn parse_command_line(&mut self, s: &str) -> Result<(), String> {
+        let s = s.trim();
+        let (range, rest) = parse_range(s)?;
+        let rest = rest.trim_start();
+        if let Some(range) = range {
+            return self.parse_range_command(range, rest);
         }
 
-        Err("Unknown command")
+        let s = rest;
+        let (name, arg) = match s.find(' ') {
+            Some(i) => (&s[..i], s[i + 1..].trim()),
+            None => (s, ""),
+        };
+
+        match name {
+            "q" | "quit" => self.app_cmds.push_front(ApplicationCommand::Quit(false)),
+            "q!" | "quit!" => self.app_cmds.push_front(ApplicationCommand::Quit(true)),
+            "w" | "write" => {
+                let fname = if arg.is_empty() { None } else { Some(arg.to_string()) };
+                self.app_cmds.push_front(ApplicationCommand::Write(fname));
+            }
+            "wq" => self.app_cmds.push_front(ApplicationCommand::WriteQuit(true)),
+            "x" | "xit" | "exi" | "exit" => self.app_cmds.push_front(ApplicationCommand::WriteQuit(false)),
+            "ea" | "earlier" | "lat" | "later" => {
+                let distance = UndoDistance::parse(arg).ok_or(format!("E475: Invalid argument: {arg}"))?;
+                if name.starts_with('e') {
+                    self.txt_cmds.push_front(TextCommand::Earlier(distance));
+                } else {
+                    self.txt_cmds.push_front(TextCommand::Later(distance));
+                }
+            }
+            "se" | "set" => self.app_cmds.push_front(ApplicationCommand::Set(arg.to_string())),
+            "undol" | "undolist" => self.txt_cmds.push_front(TextCommand::UndoList),
+            "noh" | "nohl" | "nohls" | "nohlse" | "nohlsea" | "nohlsear" | "nohlsearc" | "nohlsearch" => {
+                self.txt_cmds.push_front(TextCommand::NoHighlight)
+            }
+            "norm" | "norma" | "normal" | "norm!" | "norma!" | "normal!" => {
+                return self.run_normal(None, &s[name.len()..]);
+            }
+            "reg" | "registers" | "di" | "display" => {
+                let names: String = arg.chars().filter(|c| !c.is_whitespace()).collect();
+                self.txt_cmds.push_front(TextCommand::ListRegisters(names));
+            }
+            "undot" | "undotree" => {
+                self.app_cmds.push_front(ApplicationCommand::UndoTreeOpen);
+                self.state = CommandViewModes::UndoTreeMode;
+                self.refresh_view();
+            }
+            _ => return Err(format!("E492: Not an editor command: {s}")),
+        }
+
+        Ok(())
+    }
+
+    /// Ex commands that act on a range of lines, as :'<,'>d or :3,5>.
+    fn parse_range_command(&mut self, range: LineRange, s: &str) -> Result<(), String> {
+        if s.is_empty() {
+            self.txt_cmds.push_front(TextCommand::Goto(range.end));
+            return Ok(());
+        }
+
+        // Shifts may be repeated, :>>> shifts three times
+        let shifts = s.chars().take_while(|c| *c == '>' || *c == '<').count();
+        let (name, arg) = if shifts > 0 {
+            (&s[..1], s[shifts..].trim())
+        } else {
+            let end = s.find(|c: char| !c.is_ascii_alphabetic()).unwrap_or(s.len());
+            (&s[..end], s[end..].trim())
+        };
+        if matches!(name, "norm" | "norma" | "normal") {
+            return self.run_normal(Some(range), arg);
+        }
+        if !arg.is_empty() {
+            return Err(format!("E488: Trailing characters: {arg}"));
+        }
+
+        let (op, count) = match name {
+            "d" | "de" | "del" | "dele" | "delet" | "delete" => (Operator::Delete, 1),
+            "y" | "ya" | "yan" | "yank" => (Operator::Yank, 1),
+            ">" => (Operator::ShiftRight, shifts),
+            "<" => (Operator::ShiftLeft, shifts),
+            "j" | "jo" | "joi" | "join" => (Operator::Join(true), 1),
+            _ if name.is_empty() => return Err(format!("E492: Not an editor command: {s}")),
+            _ => return Err("E481: No range allowed".to_string()),
+        };
+        self.txt_cmds.push_front(TextCommand::Operate(op, OperatorTarget::Range(range), count));
+        self.txt_cmds.push_front(TextCommand::UndoBreak);
+        Ok(())
     }
 
     fn parse_txt_command(&mut self) {
         while !self.cmd.is_empty() {
-            match self.cmd[0] {
-                Key::Char(':') => {
-                    return;
-                },
-                Key::Char('h') => {
-                    self.txt_cmds.push_front(TextCommand::CursorLeft(1));
-                    self.cmd.drain(0..1);
-                },
-                Key::Char('j') => {
-                    self.txt_cmds.push_front(TextCommand::CursorDown(1));
-                    self.cmd.drain(0..1);
-                },
-                Key::Char('k') => {
-                    self.txt_cmds.push_front(TextCommand::CursorUp(1));
+            if self.cmd[0] == Key::Char(':') {
+                return;
+            }
+            if self.recording.is_some() && self.cmd[0] == Key::Char('q') {
+                self.stop_recording();
+                continue;
+            }
+
+            match parse_normal(&self.cmd, self.options.tildeop) {
+                Parsed::Done((count, cmd), n) => {
+                    self.cmd.drain(0..n);
+                    self.execute_normal(cmd, count);
+                    self.end_playback();
+                    if self.register.is_none() && self.pending_search.is_none() {
+                        self.resume_insert();
+                    }
+                    if self.awaiting || !matches!(self.state, CommandViewModes::NormalMode) {
+                        return;
+                    }
+                }
+                Parsed::Pending => return,
+                Parsed::Invalid(n) => {
+                    self.cmd.drain(0..n.min(self.cmd.len()));
+                    self.resume_insert();
+                }
+                Parsed::NoMatch => {
                     self.cmd.drain(0..1);
-                },
-                Key::Char('l') => {
-                    self.txt_cmds.push_front(TextCommand::CursorRight(1));
+                    self.resume_insert();
+                }
+            }
+            if !matches!(self.state, CommandViewModes::NormalMode) {
+                return;
+            }
+        }
+    }
+
+    fn parse_visual_command(&mut self) {
+        while !self.cmd.is_empty() {
+            if self.recording.is_some() && self.cmd[0] == Key::Char('q') {
+                self.stop_recording();
+                continue;
+            }
+
+            match parse_visual(&self.cmd) {
+                Parsed::Done((count, cmd), n) => {
+                    self.cmd.drain(0..n);
+                    self.execute_visual(cmd, count);
+                    self.end_playback();
+                    if !matches!(self.state, CommandViewModes::VisualMode(_)) {
+                        return;
+                    }
+                }
+                Parsed::Pending => return,
+                Parsed::Invalid(n) => {
+                    self.cmd.drain(0..n.min(self.cmd.len()));
+                }
+                Parsed::NoMatch => {
                     self.cmd.drain(0..1);
-                },
-                Key::Char('i') => {
-                    self.txt_cmds.push_front(TextCommand::SetCursorStyle(CursorStyle::Bar));
+                }
+            }
+        }
+    }
+
+    /// Remember character searches and turn ; and , into the search they repeat. None if there
+    /// is nothing to repeat.
+    fn resolve_find(&mut self, motion: Motion) -> Option<Motion> {
+        match motion {
+            Motion::FindChar { .. } => {
+                self.last_find = Some(motion);
+                Some(motion)
+            }
+            Motion::RepeatFind(reverse) => match self.last_find? {
+                Motion::FindChar { target, forward, till, .. } => Some(Motion::FindChar {
+                    target,
+                    forward: forward != reverse,
+                    till,
+                    repeat: true,
+                }),
+                _ => None,
+            },
+            _ => Some(motion),
+        }
+    }
+
+    /// Store the keys typed since q{reg}. Keys typed after this q came in with it, and are left
+    /// out along with the q.
+    fn stop_recording(&mut self) {
+        if let Some((name, mut keys)) = self.recording.take() {
+            keys.truncate(keys.len().saturating_sub(self.cmd.len()));
+            self.txt_cmds.push_front(TextCommand::Record(name, keys_to_text(&keys)));
+        }
+        self.cmd.drain(0..1);
+        self.refresh_view();
+    }
+
+    /// Tell the text view about macros whose keys have all been used, so an error in one of them
+    /// no longer skips commands. Nothing has ended while the keys of another are awaited.
+    fn end_playback(&mut self) {
+        if self.awaiting {
+            return;
+        }
+        while let Some(&(after, first)) = self.playing.last() {
+            if self.cmd.len() > after {
+                break;
+            }
+            self.playing.pop();
+            if first {
+                self.txt_cmds.push_front(TextCommand::PlaybackEnd(self.playing.is_empty()));
+            }
+        }
+    }
+
+    /// Run :normal, on each line of a range if there is one.
+    fn run_normal(&mut self, range: Option<LineRange>, keys: &str) -> Result<(), String> {
+        let keys = keys.strip_prefix('!').unwrap_or(keys).trim_start();
+        if keys.is_empty() {
+            return Err("E471: Argument required".to_string());
+        }
+        self.txt_cmds.push_front(TextCommand::Normal(range, keys.to_string()));
+        self.awaiting = true;
+        Ok(())
+    }
+
+    /// Keep a "x register for the command after it. A count before the register multiplies
+    /// the count of that command, as in 2"a3yy.
+    fn take_register(&mut self, count: Option<usize>) -> (Option<char>, Option<usize>) {
+        match self.register.take() {
+            Some((name, Some(a))) => (Some(name), Some(count.map_or(a, |b| a.saturating_mul(b)))),
+            Some((name, None)) => (Some(name), count),
+            None => (None, count),
+        }
+    }
+
+    fn use_register(&mut self, register: Option<char>) {
+        if let Some(name) = register {
+            self.txt_cmds.push_front(TextCommand::UseRegister(name));
+        }
+    }
+
+    fn execute_normal(&mut self, cmd: NormalCommand, count: Option<usize>) {
+        if let NormalCommand::Register(name) = cmd {
+            self.register = Some((name, count));
+            return;
+        }
+        let (register, count) = self.take_register(count);
+        if cmd == NormalCommand::Repeat {
+            self.repeat_change(count, register);
+            return;
+        }
+        if cmd.is_change() {
+            let change = Change { cmd, count, register, typed: Vec::new() };
+            self.change = Some(change);
+        }
+        if let NormalCommand::Move(Motion::SearchPrompt(forward))
+        | NormalCommand::Operate(_, OperatorTarget::Motion(Motion::SearchPrompt(forward), _)) = cmd
+        {
+            self.start_search(PendingSearch { cmd, count, register, visual: None }, forward);
+            return;
+        }
+        let n = count.unwrap_or(1);
+        let cmd = match cmd {
+            NormalCommand::Move(motion) => match self.resolve_find(motion) {
+                Some(motion) => NormalCommand::Move(motion),
+                None => return,
+            },
+            NormalCommand::Operate(op, OperatorTarget::Motion(motion, force)) => match self.resolve_find(motion) {
+                Some(motion) => NormalCommand::Operate(op, OperatorTarget::Motion(motion, force)),
+                None => return,
+            },
+            cmd => cmd,
+        };
+
+        match cmd {
+            NormalCommand::Move(motion) => self.txt_cmds.push_front(TextCommand::Move(motion, n)),
+            NormalCommand::Operate(op, target) => {
+                self.use_register(register);
+                self.txt_cmds.push_front(TextCommand::Operate(op, target, n));
+                if op == Operator::Change {
+                    // The undo step stays open until insert mode is left
                     self.state = CommandViewModes::InsertMode;
                     self.refresh_view();
-                    self.cmd.drain(0..1);
-                    return;
+                } else {
+                    self.txt_cmds.push_front(TextCommand::UndoBreak);
+                }
+            }
+            NormalCommand::Insert(at) => {
+                self.txt_cmds.push_front(TextCommand::StartInsert(at));
+                self.state = CommandViewModes::InsertMode;
+                self.insert_count = n;
+                self.insert_lines = matches!(at, InsertAt::LineBelow | InsertAt::LineAbove);
+                self.refresh_view();
+            }
+            NormalCommand::DeleteChar => {
+                self.use_register(register);
+                self.txt_cmds.push_front(TextCommand::Operate(Operator::Delete, OperatorTarget::Motion(Motion::Right, None), n));
+                self.txt_cmds.push_front(TextCommand::UndoBreak);
+            }
+            NormalCommand::Undo => self.txt_cmds.push_front(TextCommand::Undo(n)),
+            NormalCommand::Redo => self.txt_cmds.push_front(TextCommand::Redo(n)),
+            NormalCommand::UndoLine => self.txt_cmds.push_front(TextCommand::UndoLine),
+            NormalCommand::Earlier => self.txt_cmds.push_front(TextCommand::Earlier(UndoDistance::Steps(n))),
+            NormalCommand::Later => self.txt_cmds.push_front(TextCommand::Later(UndoDistance::Steps(n))),
+            NormalCommand::Visual(kind) => {
+                self.txt_cmds.push_front(TextCommand::Visual(Some(kind)));
+                self.state = CommandViewModes::VisualMode(kind);
+                self.refresh_view();
+            }
+            NormalCommand::Put { before, follow } => {
+                self.use_register(register);
+                self.txt_cmds.push_front(TextCommand::Put { before, follow, count: n });
+                self.txt_cmds.push_front(TextCommand::UndoBreak);
+            }
+            NormalCommand::Replace(screen) => {
+                self.txt_cmds.push_front(TextCommand::SetCursorStyle(CursorStyle::Underline));
+                self.state = CommandViewModes::ReplaceMode(screen);
+                self.insert_count = n;
+                self.refresh_view();
+            }
+            NormalCommand::ReplaceChar(c) => {
+                self.txt_cmds.push_front(TextCommand::ReplaceChars(c, n));
+                self.txt_cmds.push_front(TextCommand::UndoBreak);
+            }
+            NormalCommand::SwitchCase => {
+                self.txt_cmds.push_front(TextCommand::SwitchCase(n));
+                self.txt_cmds.push_front(TextCommand::UndoBreak);
+            }
+            NormalCommand::AddNumber(subtract) => {
+                self.txt_cmds.push_front(TextCommand::AddNumber { subtract, count: n });
+                self.txt_cmds.push_front(TextCommand::UndoBreak);
+            }
+            NormalCommand::ScrollPage(forward) => self.txt_cmds.push_front(TextCommand::ScrollPage(forward, n)),
+            NormalCommand::Record(name) => {
+                self.recording = Some((name, self.cmd.clone()));
+                self.refresh_view();
+            }
+            NormalCommand::Play(name) => {
+                self.txt_cmds.push_front(TextCommand::Play(name, n));
+                self.awaiting = true;
+            }
+            NormalCommand::Register(_) | NormalCommand::Repeat => (),
+            NormalCommand::Reselect => {
+                if let Some(kind) = self.last_visual {
+                    self.txt_cmds.push_front(TextCommand::VisualRestore);
+                    self.txt_cmds.push_front(TextCommand::Visual(Some(kind)));
+                    self.state = CommandViewModes::VisualMode(kind);
+                    self.refresh_view();
                 }
-                Key::Char('a') => {
+            }
+        }
+
+        // Changes that don't go on in insert mode are complete already
+        if matches!(self.state, CommandViewModes::NormalMode) && self.change.is_some() {
+            self.last_change = self.change.take();
+        }
+    }
+
+    /// Run the last change again. A count replaces the one it was given, and a register the one
+    /// it used, except that "1p is repeated as "2p and so on to step through the deletes.
+    fn repeat_change(&mut self, count: Option<usize>, register: Option<char>) {
+        let Some(change) = self.last_change.clone() else {
+            return;
+        };
+        let register = register.or(match change.register {
+            Some(name @ '1'..='8') if matches!(change.cmd, NormalCommand::Put { .. }) => {
+                char::from_digit(name.to_digit(10).unwrap_or(0) + 1, 10)
+            }
+            name => name,
+        });
+        self.register = register.map(|name| (name, None));
+        self.execute_normal(change.cmd, count.or(change.count));
+        // The typed keys come before anything still waiting, insert mode takes them from there
+        self.cmd.splice(0..0, change.typed);
+    }
+
+    fn execute_visual(&mut self, cmd: VisualCommand, count: Option<usize>) {
+        if let VisualCommand::Register(name) = cmd {
+            self.register = Some((name, count));
+            return;
+        }
+        let (register, count) = self.take_register(count);
+        let n = count.unwrap_or(1);
+        let CommandViewModes::VisualMode(kind) = self.state else {
+            return;
+        };
+
+        match cmd {
+            VisualCommand::Move(Motion::SearchPrompt(forward)) => {
+                let pending = PendingSearch { cmd: NormalCommand::Move(Motion::SearchPrompt(forward)), count, register, visual: Some(kind) };
+                self.start_search(pending, forward);
+            }
+            VisualCommand::Move(motion) => {
+                if let Some(motion) = self.resolve_find(motion) {
+                    self.txt_cmds.push_front(TextCommand::Move(motion, n));
+                }
+            }
+            VisualCommand::Select(object) => {
+                // Like vim, selecting paragraphs switches to linewise mode
+                if matches!(object, TextObject::Paragraph { .. }) && kind == MotionForce::Charwise {
+                    self.txt_cmds.push_front(TextCommand::Visual(Some(MotionForce::Linewise)));
+                    self.state = CommandViewModes::VisualMode(MotionForce::Linewise);
+                }
+                self.txt_cmds.push_front(TextCommand::VisualSelect(object, n));
+            }
+            VisualCommand::Operate(op, extent) => {
+                let block = kind == MotionForce::Blockwise;
+                let lines = match extent {
+                    VisualExtent::Selection => false,
+                    VisualExtent::Lines => true,
+                    VisualExtent::LinesUnlessBlock => !block,
+                    VisualExtent::LinesOrLineEnds => {
+                        if block {
+                            self.txt_cmds.push_front(TextCommand::Move(Motion::LineEnd, 1));
+                        }
+                        !block
+                    }
+                };
+                self.last_visual = Some(kind);
+                self.use_register(register);
+                self.txt_cmds.push_front(TextCommand::Operate(op, OperatorTarget::Visual(lines), n));
+                // . takes as much text again from the cursor
+                let cmd = NormalCommand::Operate(op, OperatorTarget::LastVisual(lines));
+                if cmd.is_change() {
+                    self.change = Some(Change { cmd, count, register, typed: Vec::new() });
+                }
+                if op == Operator::Change {
                     self.txt_cmds.push_front(TextCommand::SetCursorStyle(CursorStyle::Bar));
-                    self.txt_cmds.push_front(TextCommand::CursorRight(1));
                     self.state = CommandViewModes::InsertMode;
-                    self.refresh_view();
-                    self.cmd.drain(0..1);
-                    return;
+                } else {
+                    self.txt_cmds.push_front(TextCommand::UndoBreak);
+                    self.state = CommandViewModes::NormalMode;
+                    if cmd.is_change() {
+                        self.last_change = self.change.take();
+                    }
                 }
-                Key::Char('x') => {
-                    self.txt_cmds.push_front(TextCommand::Delete);
-                    self.cmd.drain(0..1);
+            }
+            VisualCommand::Mode(mode) if mode == kind => self.exit_visual(kind),
+            VisualCommand::Mode(mode) => {
+                self.txt_cmds.push_front(TextCommand::Visual(Some(mode)));
+                self.state = CommandViewModes::VisualMode(mode);
+            }
+            VisualCommand::Insert(append) => {
+                self.last_visual = Some(kind);
+                self.txt_cmds.push_front(TextCommand::SetCursorStyle(CursorStyle::Bar));
+                self.txt_cmds.push_front(TextCommand::VisualInsert(append));
+                self.state = CommandViewModes::InsertMode;
+            }
+            VisualCommand::SwapEnds(corner) => self.txt_cmds.push_front(TextCommand::VisualSwap(corner)),
+            VisualCommand::Exit => self.exit_visual(kind),
+            VisualCommand::CommandLine => {
+                self.exit_visual(kind);
+                self.enter_command_line(':', "'<,'>");
+            }
+            VisualCommand::Register(_) => (),
+            VisualCommand::ScrollPage(forward) => self.txt_cmds.push_front(TextCommand::ScrollPage(forward, n)),
+        }
+        self.refresh_view();
+    }
+
+    fn exit_visual(&mut self, kind: MotionForce) {
+        self.last_visual = Some(kind);
+        self.txt_cmds.push_front(TextCommand::Visual(None));
+        self.state = CommandViewModes::NormalMode;
+    }
+
+    fn execute_insert(&mut self, cmd: InsertCommand) {
+        // Replace mode types over the text, and backspace puts it back
+        let replace = match self.state {
+            CommandViewModes::ReplaceMode(screen) => Some(screen),
+            _ => None,
+        };
+        match cmd {
+            InsertCommand::Type(c) => self.txt_cmds.push_front(match replace {
+                Some(screen) => TextCommand::Overwrite(c, screen),
+                None => TextCommand::Insert(c),
+            }),
+            InsertCommand::Backspace => self.txt_cmds.push_front(match replace {
+                Some(_) => TextCommand::Restore,
+                None => TextCommand::Delete,
+            }),
+            InsertCommand::DeleteWord => self.txt_cmds.push_front(TextCommand::DeleteBefore(Motion::WordBackward(false))),
+            InsertCommand::DeleteLine => self.txt_cmds.push_front(TextCommand::DeleteBefore(Motion::FirstNonBlank)),
+            InsertCommand::Shift(right) => self.txt_cmds.push_front(TextCommand::ShiftLine(right)),
+            InsertCommand::PutRegister(name) => self.txt_cmds.push_front(TextCommand::InsertRegister(name)),
+            InsertCommand::DeleteForward => self.txt_cmds.push_front(TextCommand::DeleteForward),
+            InsertCommand::Move(motion) => {
+                self.restart_insert();
+                self.change = Some(Change::typing());
+                self.txt_cmds.push_front(TextCommand::Move(motion, 1));
+            }
+            InsertCommand::ScrollPage(forward) => {
+                self.restart_insert();
+                self.change = Some(Change::typing());
+                self.txt_cmds.push_front(TextCommand::ScrollPage(forward, 1));
+            }
+            InsertCommand::ToggleReplace => {
+                let (state, style) = match self.state {
+                    CommandViewModes::ReplaceMode(_) => (CommandViewModes::InsertMode, CursorStyle::Bar),
+                    _ => (CommandViewModes::ReplaceMode(false), CursorStyle::Underline),
+                };
+                self.state = state;
+                self.txt_cmds.push_front(TextCommand::SetCursorStyle(style));
+                self.refresh_view();
+            }
+            InsertCommand::Normal => {
+                self.restart_insert();
+                self.insert_once = true;
+                self.state = CommandViewModes::NormalMode;
+                self.refresh_view();
+            }
+            InsertCommand::Exit => {
+                let keys = text_to_keys(&self.inserted);
+                // Each repeat of o or O adds a line even when nothing was typed
+                let size = self.inserted.len() + usize::from(self.insert_lines);
+                if size.saturating_mul(self.insert_count) > MAX_REPEAT_LENGTH {
+                    self.message = vec!["E1240: Resulting text too long".to_string()];
+                    self.insert_count = 1;
+                } else if size == 0 {
+                    self.insert_count = 1;
                 }
-                k => {
-                    eprintln!("Unhandled input in normal mode: {:?}", k);
-                    self.cmd.drain(0..1);
+                for _ in 1..self.insert_count {
+                    if self.insert_lines {
+                        self.txt_cmds.push_front(TextCommand::StartInsert(InsertAt::LineBelow));
+                    }
+                    let mut rest = &keys[..];
+                    while let Parsed::Done(cmd, n) = parse_insert(rest) {
+                        if cmd.is_text() {
+                            self.execute_insert(cmd);
+                        }
+                        rest = &rest[n..];
+                    }
+                }
+                self.insert_count = 1;
+                self.insert_lines = false;
+                // Leaving right after the cursor moved, . still repeats the text typed before
+                if let Some(change) = self.change.take().filter(|change| !change.is_empty_typing()) {
+                    self.last_change = Some(change);
+                }
+                self.txt_cmds.push_front(TextCommand::ReadOnlyRegister('.', self.inserted.clone()));
+                self.last_inserted = std::mem::take(&mut self.inserted);
+                self.txt_cmds.push_front(TextCommand::InsertEnd);
+                self.txt_cmds.push_front(TextCommand::UndoBreak);
+                self.state = CommandViewModes::NormalMode;
+                self.refresh_view();
+            }
+        }
+    }
+
+    /// Moving the cursor in insert mode, or Ctrl-O, starts over with a new undo step. The count
+    /// is dropped and . only repeats the text typed before it.
+    fn restart_insert(&mut self) {
+        if let Some(mut change) = self.change.take() {
+            change.typed.push(Key::Esc);
+            self.last_change = Some(change);
+        }
+        self.insert_count = 1;
+        self.insert_lines = false;
+        self.txt_cmds.push_front(TextCommand::ReadOnlyRegister('.', self.inserted.clone()));
+        self.last_inserted = std::mem::take(&mut self.inserted);
+        self.txt_cmds.push_front(TextCommand::UndoBreak);
+    }
+
+    /// Go back to insert mode after the command run with Ctrl-O, unless it left normal mode
+    /// itself.
+    fn resume_insert(&mut self) {
+        if !self.insert_once {
+            return;
+        }
+        self.insert_once = false;
+        if matches!(self.state, CommandViewModes::NormalMode) {
+            self.change = Some(Change::typing());
+            self.txt_cmds.push_front(TextCommand::StartInsert(InsertAt::Cursor));
+            self.state = CommandViewModes::InsertMode;
+            self.refresh_view();
+        }
+    }
+
+    /// Switch to the command line after the prompt : / or ?, starting out holding `prefill`.
+    fn enter_command_line(&mut self, prompt: char, prefill: &str) {
+        self.app_cmds.push_front(ApplicationCommand::FocusCommand);
+        self.state = CommandViewModes::CommandLineMode(prompt);
+        self.cmdline = prefill.chars().collect();
+        self.cmdline_pos = self.cmdline.len();
+        self.refresh_view();
+    }
+
+    fn leave_command_line(&mut self) {
+        self.cmdline.clear();
+        self.cmdline_pos = 0;
+        self.cursor.col = 0;
+        self.app_cmds.push_front(ApplicationCommand::FocusText);
+        self.state = CommandViewModes::NormalMode;
+        self.refresh_view();
+    }
+
+    /// Edit the command line with a key, and run it on Enter. Ctrl or Shift with Left and Right
+    /// move by blank separated words.
+    fn edit_command_line(&mut self, key: Key) {
+        let CommandViewModes::CommandLineMode(prompt) = self.state else {
+            return;
+        };
+        let pos = self.cmdline_pos;
+        match key {
+            Key::Char('\n') => {
+                let s: String = self.cmdline.iter().collect();
+                self.leave_command_line();
+                if prompt != ':' {
+                    self.finish_search(&s, prompt == '/');
+                } else {
+                    if let Err(e) = self.parse_command_line(&s) {
+                        self.message = vec![e];
+                    }
+                    if !s.trim().is_empty() {
+                        self.txt_cmds.push_front(TextCommand::ReadOnlyRegister(':', s));
+                    }
                 }
+                self.refresh_view();
+                self.resume_insert();
+                return;
+            }
+            Key::Esc => {
+                self.leave_command_line();
+                self.cancel_search();
+                self.resume_insert();
+                return;
+            }
+            Key::Backspace | Key::Ctrl('h') if self.cmdline.is_empty() => {
+                self.leave_command_line();
+                self.cancel_search();
+                self.resume_insert();
+                return;
+            }
+            Key::Char(c) => {
+                self.cmdline.insert(pos, c);
+                self.cmdline_pos += 1;
+            }
+            Key::Delete if pos < self.cmdline.len() => {
+                self.cmdline.remove(pos);
+            }
+            // At the end of the line Del takes the character before the cursor, like backspace
+            Key::Backspace | Key::Ctrl('h') | Key::Delete if pos > 0 => {
+                self.cmdline.remove(pos - 1);
+                self.cmdline_pos -= 1;
+            }
+            Key::Left => self.cmdline_pos = pos.saturating_sub(1),
+            Key::Right => self.cmdline_pos = (pos + 1).min(self.cmdline.len()),
+            Key::Home | Key::Ctrl('b') => self.cmdline_pos = 0,
+            Key::End | Key::Ctrl('e') => self.cmdline_pos = self.cmdline.len(),
+            Key::CtrlLeft | Key::ShiftLeft => {
+                let before = &self.cmdline[..pos];
+                let blanks = before.iter().rev().take_while(|c| c.is_whitespace()).count();
+                let word = before[..pos - blanks].iter().rev().take_while(|c| !c.is_whitespace()).count();
+                self.cmdline_pos = pos - blanks - word;
+            }
+            Key::CtrlRight | Key::ShiftRight => {
+                let after = &self.cmdline[pos..];
+                let word = after.iter().take_while(|c| !c.is_whitespace()).count();
+                let blanks = after[word..].iter().take_while(|c| c.is_whitespace()).count();
+                self.cmdline_pos = pos + word + blanks;
+            }
+            _ => (),
+        }
+        if prompt != ':' {
+            let typed = self.cmdline.iter().collect();
+            self.txt_cmds.push_front(TextCommand::SearchPreview(Some((typed, prompt == '/'))));
+        }
+        self.refresh_view();
+    }
+
+    /// Wait for the search typed after / or ?, with the command that started it.
+    fn start_search(&mut self, pending: PendingSearch, forward: bool) {
+        self.pending_search = Some(pending);
+        self.enter_command_line(if forward { '/' } else { '?' }, "");
+    }
+
+    /// Set the search typed at the prompt and run the command waiting for it, which goes to the
+    /// match as n would.
+    fn finish_search(&mut self, typed: &str, forward: bool) {
+        let Some(pending) = self.pending_search.take() else {
+            return;
+        };
+        self.txt_cmds.push_front(TextCommand::SearchPreview(None));
+        if let Err(e) = Search::parse(typed, forward, &self.options) {
+            self.message = vec![e];
+            self.change = None;
+            if let Some(kind) = pending.visual {
+                self.state = CommandViewModes::VisualMode(kind);
             }
+            return;
         }
+        self.txt_cmds.push_front(TextCommand::Search(typed.to_string(), forward));
+
+        let next = Motion::SearchNext(false);
+        if let Some(kind) = pending.visual {
+            let n = pending.count.unwrap_or(1);
+            self.txt_cmds.push_front(TextCommand::Move(next, n));
+            self.state = CommandViewModes::VisualMode(kind);
+            return;
+        }
+        let cmd = match pending.cmd {
+            NormalCommand::Operate(op, OperatorTarget::Motion(_, force)) => NormalCommand::Operate(op, OperatorTarget::Motion(next, force)),
+            _ => NormalCommand::Move(next),
+        };
+        // . repeats the command with / or ?, followed by the typed search
+        let change = self.change.take();
+        self.register = pending.register.map(|name| (name, None));
+        self.execute_normal(cmd, pending.count);
+        if let Some(change) = change {
+            match self.state {
+                CommandViewModes::NormalMode => self.last_change = Some(change),
+                _ => self.change = Some(change),
+            }
+        }
+    }
+
+    /// Leaving the search prompt with Esc drops the command that was waiting for it.
+    fn cancel_search(&mut self) {
+        let Some(pending) = self.pending_search.take() else {
+            return;
+        };
+        self.txt_cmds.push_front(TextCommand::SearchPreview(None));
+        self.change = None;
+        if let Some(kind) = pending.visual {
+            self.state = CommandViewModes::VisualMode(kind);
+            self.refresh_view();
+        }
+    }
+
+    /// Keys of a partially typed normal mode command, as shown by vim's 'showcmd'.
+    fn showcmd(&self) -> String {
+        let keys: String = self
+            .cmd
+            .iter()
+            .map(|k| match k {
+                Key::Char(c) => c.to_string(),
+                Key::Ctrl(c) => format!("^{}", c.to_ascii_uppercase()),
+                _ => "".to_string(),
+            })
+            .collect();
+
+        // Only the last 10 characters fit in the showcmd column
+        let skip = keys.chars().count().saturating_sub(10);
+        keys.chars().skip(skip).collect()
     }
 
     fn parse_commands(&mut self) {
-        if !self.cmd.is_empty() {
+        if !self.message.is_empty() && !self.cmd.is_empty() {
+            // Any key dismisses a message, the key is only swallowed by the continue prompt
+            if self.message.len() > 1 && matches!(self.cmd[0], Key::Char('\n') | Key::Char(' ') | Key::Esc) {
+                self.cmd.drain(0..1);
+            }
+            self.message.clear();
+            self.refresh_view();
+        }
+
+        // Stop once a pass leaves the keys as they were. Comparing the keys rather than counting
+        // them goes on after . swaps its keys for as many typed ones, as 2. does for "a<Esc>"
+        let mut pending = Vec::new();
+        while !self.awaiting && !self.cmd.is_empty() && self.cmd != pending {
+            pending = self.cmd.clone();
             match self.state {
                 CommandViewModes::NormalMode => {
                     match self.cmd[0] {
                         Key::Char(':') => {
-                            self.app_cmds.push_front(ApplicationCommand::FocusCommand);
-                            self.state = CommandViewModes::CommandLineMode;
-                            self.refresh_view();
-
-                            self.cursor.col = 1;
                             self.cmd.drain(0..1);
-
-                            for i in 0..self.cmd.len() {
-                                if i+1 < (self.sz.col as usize) {
-                                    if let Key::Char(c) = self.cmd[i] {
-                                        self.view[i+1] = c;
-                                        self.updates[i+1] = true;
-                                        self.cursor.col += 1;
-                                    }
-                                }
-                            }
+                            self.enter_command_line(':', "");
                         }
-                        Key::Char(_) => {
+                        _ => {
                             self.parse_txt_command();
                         }
-                        _ => {
-                            self.cmd.drain(0..1);
-                        },
                     }
                 },
-                CommandViewModes::InsertMode => {
-                    while !self.cmd.is_empty() {
-                        match self.cmd[0] {
-                            Key::Esc => {
-                                self.cmd.drain(0..1);
-                                self.txt_cmds.push_front(TextCommand::SetCursorStyle(CursorStyle::Block));
-                                self.txt_cmds.push_front(TextCommand::CursorLeft(1));
-                                self.state = CommandViewModes::NormalMode;
-                                self.refresh_view();
-                                break;
-                            },
-                            Key::Char(c) => {
-                                self.txt_cmds.push_front(TextCommand::Insert(c));
-                                self.cmd.drain(0..1);
-                            },
-                            Key::Backspace => {
-                                self.txt_cmds.push_front(TextCommand::Delete);
-                                self.cmd.drain(0..1);
+                CommandViewModes::InsertMode | CommandViewModes::ReplaceMode(_) => {
+                    while !self.cmd.is_empty() && matches!(self.state, CommandViewModes::InsertMode | CommandViewModes::ReplaceMode(_)) {
+                        match parse_insert(&self.cmd) {
+                            Parsed::Done(InsertCommand::PutRegister('.'), n) => {
+                                // Ctrl-A and Ctrl-R . type the keys of the last insert again, so a
+                                // Ctrl-W or backspace in them acts rather than being inserted
+                                self.cmd.splice(0..n, text_to_keys(&self.last_inserted));
                             }
-                            k => {
-                                eprintln!("Unhandled input in insert mode: {:?}", k);
+                            Parsed::Done(cmd, n) => {
+                                let keys: Vec<Key> = self.cmd.drain(0..n).collect();
+                                if let Some(change) = self.change.as_mut() {
+                                    change.typed.extend_from_slice(&keys);
+                                }
+                                // The text to repeat for a count, and to keep in ".
+                                match cmd {
+                                    InsertCommand::Type(c) => self.inserted.push(c),
+                                    InsertCommand::Backspace => {
+                                        self.inserted.pop();
+                                    }
+                                    _ if cmd.is_text() => self.inserted.push_str(&keys_to_text(&keys)),
+                                    _ => (),
+                                }
+                                self.execute_insert(cmd);
+                            }
+                            Parsed::Pending => break,
+                            Parsed::Invalid(n) => {
+                                self.cmd.drain(0..n.min(self.cmd.len()));
+                            }
+                            Parsed::NoMatch => {
                                 self.cmd.drain(0..1);
-                            },
+                            }
                         }
+                        self.end_playback();
                     }
                 },
-                CommandViewModes::CommandLineMode => {
-                    let mut i = 0;
-                    while i < self.cmd.len() {
-                        match self.cmd[i] {
-                            Key::Char('\n') => {
-                                for j in 0..=i {
-                                    self.view[j] = ' ';
-                                    self.updates[j] = true;
-                                }
-                                self.cursor.col = 0;
-
-                                let s: String = self.cmd.drain(..=i).filter_map(|k| match k {
-                                    Key::Char('\n') => None,
-                                    Key::Char(c) => Some(c),
-                                    _ => None,
-                                }).collect();
-
-                                match self.parse_app_command(&s) {
-                                    Ok(cmd) => self.app_cmds.push_front(cmd),
-                                    Err(_e) => (),
-                                }
-                                self.app_cmds.push_front(ApplicationCommand::FocusText);
-                                self.state = CommandViewModes::NormalMode;
-                                self.refresh_view();
+                CommandViewModes::CommandLineMode(_) => {
+                    while !self.cmd.is_empty() && matches!(self.state, CommandViewModes::CommandLineMode(_)) {
+                        let key = self.cmd.remove(0);
+                        // . types the search again when it repeats the command
+                        if self.pending_search.is_some() {
+                            if let Some(change) = self.change.as_mut() {
+                                change.typed.push(key);
                             }
-                            Key::Char(c) => {
-                                if i+1 < (self.sz.col as usize) && self.view[i+1] != c {
-                                    self.view[i+1] = c;
-                                    self.updates[i+1] = true;
-                                    self.cursor.col += 1;
-                                }
-                                i += 1;
-                            },
-                            Key::Backspace => {
-                                if i == 0 {
-                                    self.cmd.drain(0..1);
-                                    self.view[i] = ' ';
-                                    self.updates[i] = true;
-                                
-                                    self.app_cmds.push_front(ApplicationCommand::FocusText);
-                                    self.state = CommandViewModes::NormalMode;
-                                    self.refresh_view();
-                                    break;
-                                } else {
-                                    self.cmd.drain(i-1..=i);
-                                    self.view[i] = ' ';
-                                    self.updates[i] = true;
-                                    self.cursor.col -= 1;
-                                } 
-                            }
-                            Key::Esc => {
-                                for j in 0..=i {
-                                    self.view[j] = ' ';
-                                    self.updates[j] = true;
-                                }
-                                self.cursor.col = 0;
-
-                                self.cmd.drain(..=i);
-                                self.app_cmds.push_front(ApplicationCommand::FocusText);
+                        }
+                        self.edit_command_line(key);
+                    }
+                },
+                CommandViewModes::VisualMode(_) => self.parse_visual_command(),
+                CommandViewModes::UndoTreeMode => {
+                    while !self.cmd.is_empty() {
+                        match self.cmd.remove(0) {
+                            Key::Char('j') | Key::Down => self.app_cmds.push_front(ApplicationCommand::UndoTreeMove(1)),
+                            Key::Char('k') | Key::Up => self.app_cmds.push_front(ApplicationCommand::UndoTreeMove(-1)),
+                            k @ (Key::Char('\n') | Key::Char('q') | Key::Esc) => {
+                                self.app_cmds.push_front(ApplicationCommand::UndoTreeClose(k == Key::Char('\n')));
                                 self.state = CommandViewModes::NormalMode;
                                 self.refresh_view();
                                 break;
@@ -275,6 +1121,131 @@ impl CommandView {
                 },
             }
         }
+
+        self.end_playback();
+        if matches!(self.state, CommandViewModes::NormalMode | CommandViewModes::VisualMode(_)) {
+            self.refresh_view();
+        }
+    }
+}
+
+/// Deepest nesting of macros, as vim's 'maxmapdepth'.
+const MAX_PLAYBACK_DEPTH: usize = 1000;
+
+/// Keys without a character of their own, kept in registers as the sequences terminals send.
+const SPECIAL_KEYS: [(Key, &str); 20] = [
+    (Key::Up, "\x1b[A"),
+    (Key::Down, "\x1b[B"),
+    (Key::Right, "\x1b[C"),
+    (Key::Left, "\x1b[D"),
+    (Key::Home, "\x1b[H"),
+    (Key::End, "\x1b[F"),
+    (Key::Insert, "\x1b[2~"),
+    (Key::Delete, "\x1b[3~"),
+    (Key::PageUp, "\x1b[5~"),
+    (Key::PageDown, "\x1b[6~"),
+    (Key::ShiftUp, "\x1b[1;2A"),
+    (Key::ShiftDown, "\x1b[1;2B"),
+    (Key::ShiftRight, "\x1b[1;2C"),
+    (Key::ShiftLeft, "\x1b[1;2D"),
+    (Key::CtrlUp, "\x1b[1;5A"),
+    (Key::CtrlDown, "\x1b[1;5B"),
+    (Key::CtrlRight, "\x1b[1;5C"),
+    (Key::CtrlLeft, "\x1b[1;5D"),
+    (Key::CtrlHome, "\x1b[1;5H"),
+    (Key::CtrlEnd, "\x1b[1;5F"),
+];
+
+/// Recorded keys as the text of a register, where Esc, Backspace and Ctrl keys are control
+/// characters.
+fn keys_to_text(keys: &[Key]) -> String {
+    keys.iter()
+        .map(|key| match key {
+            Key::Char(c) => c.to_string(),
+            Key::Ctrl(c) => ((*c as u8 & 0x1f) as char).to_string(),
+            Key::Esc => "\x1b".to_string(),
+            Key::Backspace => "\x08".to_string(),
+            key => SPECIAL_KEYS.iter().find(|(k, _)| k == key).map_or(String::new(), |(_, s)| s.to_string()),
+        })
+        .collect()
+}
+
+/// The keys to run for the text of a register, the reverse of `keys_to_text`.
+fn text_to_keys(text: &str) -> Vec<Key> {
+    let mut keys = Vec::new();
+    let mut rest = text;
+    while let Some(c) = rest.chars().next() {
+        if let Some((key, seq)) = SPECIAL_KEYS.iter().find(|(_, seq)| rest.starts_with(seq)) {
+            keys.push(*key);
+            rest = &rest[seq.len()..];
+            continue;
+        }
+        keys.push(match c {
+            '\x1b' => Key::Esc,
+            '\x08' | '\x7f' => Key::Backspace,
+            '\r' => Key::Char('\n'),
+            '\n' | '\t' => Key::Char(c),
+            c if c.is_ascii_control() => Key::Ctrl((c as u8 | 0x60) as char),
+            c => Key::Char(c),
+        });
+        rest = &rest[c.len_utf8()..];
+    }
+    keys
+}
+
+/// Split a leading Ex range off a command line, as in :'<,'>d or :.,$y. A single address is
+/// both ends of the range, and % is every line.
+fn parse_range(s: &str) -> Result<(Option<LineRange>, &str), String> {
+    if let Some(rest) = s.strip_prefix('%') {
+        let start = LineAddress { spec: LineSpec::Number(1), offset: 0 };
+        let end = LineAddress { spec: LineSpec::Last, offset: 0 };
+        return Ok((Some(LineRange { start, end }), rest));
+    }
+
+    let (start, mut rest) = parse_address(s)?;
+    let Some(start) = start else {
+        return Ok((None, s));
+    };
+    let mut end = start;
+    if let Some(r) = rest.strip_prefix(',').or_else(|| rest.strip_prefix(';')) {
+        let (address, r) = parse_address(r)?;
+        end = address.unwrap_or(LineAddress { spec: LineSpec::Current, offset: 0 });
+        rest = r;
+    }
+    Ok((Some(LineRange { start, end }), rest))
+}
+
+fn parse_address(s: &str) -> Result<(Option<LineAddress>, &str), String> {
+    let digits = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (spec, mut rest) = if digits > 0 {
+        let n = s[..digits].parse().map_err(|_| "E16: Invalid range".to_string())?;
+        (Some(LineSpec::Number(n)), &s[digits..])
+    } else if let Some(rest) = s.strip_prefix('.') {
+        (Some(LineSpec::Current), rest)
+    } else if let Some(rest) = s.strip_prefix('$') {
+        (Some(LineSpec::Last), rest)
+    } else if let Some(rest) = s.strip_prefix('\'') {
+        match rest.chars().next() {
+            Some(c @ ('<' | '>')) => (Some(LineSpec::Mark(c)), &rest[1..]),
+            _ => return Err("E20: Mark not set".to_string()),
+        }
+    } else {
+        (None, s)
+    };
+
+    // Offsets without a line number are relative to the cursor, :+2 or :-
+    let mut offset = 0;
+    while let Some(sign) = rest.chars().next().filter(|c| *c == '+' || *c == '-') {
+        let r = &rest[1..];
+        let digits = r.find(|c: char| !c.is_ascii_digit()).unwrap_or(r.len());
+        let n: isize = if digits == 0 { 1 } else { r[..digits].parse().map_err(|_| "E16: Invalid range".to_string())? };
+        offset += if sign == '+' { n } else { -n };
+        rest = &r[digits..];
+    }
+
+    match spec {
+        None if rest.len() == s.len() => Ok((None, s)),
+        spec => Ok((Some(LineAddress { spec: spec.unwrap_or(LineSpec::Current), offset }), rest)),
     }
 }
 
@@ -319,3 +1290,564 @@ impl View for CommandView {
         CursorStyle::Block
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interface::Highlight;
+    use crate::views::text_view::TextView;
+
+    /// A text view driven by a command view the way the app does it, to test commands by the
+    /// keys typed.
+    struct Editor {
+        text: TextView,
+        cmd: CommandView,
+        options: Options,
+    }
+
+    impl Editor {
+        fn new(text: &str) -> Self {
+            let mut editor = Self { text: TextView::new(text), cmd: CommandView::new(), options: Options::new() };
+            editor.text.set_size(Position { row: 20, col: 80 });
+            editor.cmd.set_size(Position { row: 1, col: 80 });
+            editor
+        }
+
+        /// Type keys one at a time, written as in a register: "\x1b" for Esc, "\x01" for Ctrl-A.
+        fn keys(&mut self, keys: &str) -> &mut Self {
+            self.press(text_to_keys(keys))
+        }
+
+        fn press(&mut self, keys: Vec<Key>) -> &mut Self {
+            for key in keys {
+                self.cmd.add_keystrokes(vec![key]);
+                loop {
+                    while let Some(cmd) = self.cmd.get_app_command() {
+                        if let ApplicationCommand::Set(args) = cmd {
+                            for arg in Options::split_args(&args) {
+                                self.options.set(&arg).unwrap();
+                            }
+                            self.text.set_options(&self.options);
+                            self.cmd.set_options(&self.options);
+                        }
+                    }
+                    while let Some(cmd) = self.cmd.get_text_command() {
+                        self.text.process_command(cmd);
+                    }
+                    match self.text.take_playback() {
+                        Some(runs) => self.cmd.play_keys(runs),
+                        None => break,
+                    }
+                }
+                if let Some(msg) = self.text.take_message() {
+                    self.cmd.show_message(&msg);
+                }
+            }
+            self
+        }
+
+        fn text(&self) -> String {
+            self.text.get_text()
+        }
+
+        /// The characters drawn as matches of the last search, in screen order.
+        fn highlighted(&self) -> String {
+            let highlights = self.text.get_highlights();
+            let view = self.text.get_view();
+            view.iter().zip(&highlights).filter(|(_, &h)| h == Highlight::Search).map(|(c, _)| c).collect()
+        }
+
+        /// The message or mode line shown in the command view.
+        fn message(&self) -> String {
+            self.cmd.get_view().iter().collect::<String>().trim_end().to_string()
+        }
+
+        /// Line and column of the cursor, counted from 0.
+        fn cursor(&self) -> (u16, u16) {
+            let pos = self.text.get_cursor_pos();
+            (pos.row, pos.col - 5)
+        }
+    }
+
+    #[test]
+    fn ranges() {
+        let address = |spec, offset| LineAddress { spec, offset };
+
+        let (range, rest) = parse_range("'<,'>d").unwrap();
+        assert_eq!(range, Some(LineRange { start: address(LineSpec::Mark('<'), 0), end: address(LineSpec::Mark('>'), 0) }));
+        assert_eq!(rest, "d");
+
+        let (range, rest) = parse_range(".+2;$-1>>").unwrap();
+        assert_eq!(range, Some(LineRange { start: address(LineSpec::Current, 2), end: address(LineSpec::Last, -1) }));
+        assert_eq!(rest, ">>");
+
+        let (range, rest) = parse_range("12").unwrap();
+        assert_eq!(range, Some(LineRange { start: address(LineSpec::Number(12), 0), end: address(LineSpec::Number(12), 0) }));
+        assert_eq!(rest, "");
+
+        assert_eq!(parse_range("set sw=4").unwrap(), (None, "set sw=4"));
+        assert!(parse_range("'a,'bd").is_err());
+    }
+
+    #[test]
+    fn block_visual() {
+        let mut editor = Editor::new("abcd\nefgh\nijkl");
+        assert_eq!(editor.keys("l\x16jld").text(), "ad\neh\nijkl");
+        assert_eq!(editor.keys("ugg0l\x16jjIX\x1b").text(), "aXbcd\neXfgh\niXjkl");
+        assert_eq!(editor.keys("ugg0l\x16jjlAY\x1b").text(), "abcYd\nefgYh\nijkYl");
+        assert_eq!(editor.keys("ugg0l\x16jlcZ\x1b").text(), "aZd\neZh\nijkl");
+        assert_eq!(editor.keys("u").text(), "abcd\nefgh\nijkl");
+
+        // $ takes each line to its end, and A appends there on ragged lines
+        let mut editor = Editor::new("ab\nabcd\na");
+        assert_eq!(editor.keys("\x16jj$A;\x1b").text(), "ab;\nabcd;\na;");
+        assert_eq!(editor.keys("ugg0l\x16j$d").text(), "a\na\na");
+
+        // Lines too short to reach the block are left alone by I and padded by A
+        let mut editor = Editor::new("abcd\nx\nabcd");
+        assert_eq!(editor.keys("ll\x16jjIX\x1b").text(), "abXcd\nx\nabXcd");
+        assert_eq!(editor.keys("ugg0ll\x16jjAY\x1b").text(), "abcYd\nx  Y\nabcYd");
+    }
+
+    #[test]
+    fn recorded_keys() {
+        let keys = [Key::Char('i'), Key::Char('é'), Key::Backspace, Key::Left, Key::Ctrl('w'), Key::Esc, Key::Char('\n')];
+        let text = keys_to_text(&keys);
+        assert_eq!(text, "ié\x08\x1b[D\x17\x1b\n");
+        assert_eq!(text_to_keys(&text), keys);
+        assert_eq!(text_to_keys("a\x1bjx\x7f"), [Key::Char('a'), Key::Esc, Key::Char('j'), Key::Char('x'), Key::Backspace]);
+
+        let keys = [Key::CtrlLeft, Key::ShiftRight, Key::Insert, Key::CtrlEnd, Key::Delete];
+        assert_eq!(text_to_keys(&keys_to_text(&keys)), keys);
+    }
+
+    #[test]
+    fn counts() {
+        let mut editor = Editor::new("one two three four five six seven eight");
+        assert_eq!(editor.keys("2d3w").text(), "seven eight");
+        assert_eq!(editor.keys("u$d0").text(), "t");
+        assert_eq!(editor.keys("u0 10x").text(), "oee four five six seven eight");
+        assert_eq!(editor.keys("u03l2d0").text(), " two three four five six seven eight");
+        assert_eq!(editor.keys("u$03w").cursor(), (0, 14));
+
+        // Keys that make no command are dropped, so the ones after them still work
+        assert_eq!(editor.keys("u0dZx").text(), "ne two three four five six seven eight");
+        assert_eq!(editor.keys("\"\x1bx").text(), "e two three four five six seven eight");
+        assert_eq!(editor.keys("3\x1bx").text(), " two three four five six seven eight");
+    }
+
+    #[test]
+    fn find_char() {
+        let mut editor = Editor::new("a,b,c,d (x) e,f");
+        assert_eq!(editor.keys("f,").cursor(), (0, 1));
+        assert_eq!(editor.keys(";").cursor(), (0, 3));
+        assert_eq!(editor.keys("2;").cursor(), (0, 13));
+        assert_eq!(editor.keys(",").cursor(), (0, 5));
+        assert_eq!(editor.keys("F(").cursor(), (0, 5));
+        assert_eq!(editor.keys("$F(").cursor(), (0, 8));
+
+        // ; after t skips the match right after the cursor instead of staying put
+        assert_eq!(editor.keys("0t,").cursor(), (0, 0));
+        assert_eq!(editor.keys(";").cursor(), (0, 2));
+        assert_eq!(editor.keys(";").cursor(), (0, 4));
+        assert_eq!(editor.keys(",").cursor(), (0, 2));
+        assert_eq!(editor.keys("$T(").cursor(), (0, 9));
+        assert_eq!(editor.keys("0fz").cursor(), (0, 0));
+
+        assert_eq!(editor.keys("0dt(").text(), "(x) e,f");
+        assert_eq!(editor.keys("u0df)").text(), " e,f");
+        assert_eq!(editor.keys("u0f,d2;").text(), "ad (x) e,f");
+    }
+
+    #[test]
+    fn repeat() {
+        // A count given to . replaces the count of the change
+        let mut editor = Editor::new("a b c d e f g h");
+        assert_eq!(editor.keys("d2w").text(), "c d e f g h");
+        assert_eq!(editor.keys("3.").text(), "f g h");
+        assert_eq!(editor.keys(".").text(), "");
+
+        let mut editor = Editor::new("one two three");
+        assert_eq!(editor.keys("cwxx\x1bw.").text(), "xx xx three");
+        assert_eq!(editor.keys("w.").text(), "xx xx xx");
+        assert_eq!(editor.keys("u").text(), "xx xx three");
+
+        let mut editor = Editor::new("\n");
+        assert_eq!(editor.keys("3ia\x1bj0.").text(), "aaa\naaa");
+        assert_eq!(editor.keys("2.").text(), "aaa\naaaaa");
+        assert_eq!(Editor::new("x").keys("ia\x1b2.").text(), "aaax");
+
+        // The pattern typed for a search motion is repeated with it
+        let mut editor = Editor::new("xx t1 yy t2 zz t3");
+        assert_eq!(editor.keys("d/t\n").text(), "t1 yy t2 zz t3");
+        assert_eq!(editor.keys("w.").text(), "t1 t2 zz t3");
+        assert_eq!(editor.keys("0c/t\nA\x1b").text(), "At2 zz t3");
+        assert_eq!(editor.keys("w.").text(), "At2 At3");
+    }
+
+    #[test]
+    fn macros() {
+        let mut editor = Editor::new("1 a\n2 b\n3 c\n4 d\n5 e");
+        assert_eq!(editor.keys("qa0ix\x1bjq").text(), "x1 a\n2 b\n3 c\n4 d\n5 e");
+        assert_eq!(editor.keys("2@a").text(), "x1 a\nx2 b\nx3 c\n4 d\n5 e");
+        assert_eq!(editor.keys("@@").text(), "x1 a\nx2 b\nx3 c\nx4 d\n5 e");
+        assert_eq!(editor.cursor(), (4, 0));
+
+        // A recursive macro runs until a motion fails, here j on the last line
+        let mut editor = Editor::new("a\nb\nc\nd");
+        assert_eq!(editor.keys("qbqqbA;\x1bj@bq").text(), "a;\nb\nc\nd");
+        assert_eq!(editor.keys("@b").text(), "a;\nb;\nc;\nd;");
+
+        let mut editor = Editor::new("a\nb\nc\nd");
+        assert_eq!(editor.keys(":%normal A!\n").text(), "a!\nb!\nc!\nd!");
+        assert_eq!(editor.keys(":2,3normal! 0x\n").text(), "a!\n!\n!\nd!");
+    }
+
+    #[test]
+    fn replace() {
+        let mut editor = Editor::new("abcdef");
+        assert_eq!(editor.keys("Rxyz").text(), "xyzdef");
+        assert_eq!(editor.keys("\x08\x08").text(), "xbcdef");
+        assert_eq!(editor.keys("wxyz12\x08\x08\x08").text(), "xwxyef");
+
+        // Enter splits the line instead of replacing, and backspace joins it again
+        let mut editor = Editor::new("abcdef");
+        assert_eq!(editor.keys("lR\n").text(), "a\nbcdef");
+        assert_eq!(editor.keys("\x08").text(), "abcdef");
+        assert_eq!(editor.keys("\x1b").text(), "abcdef");
+
+        // gR replaces screen cells, the tab stays until its columns are used up
+        let mut editor = Editor::new("\tx");
+        assert_eq!(editor.keys(":set ts=4\ngRab\x1b").text(), "ab\tx");
+        assert_eq!(editor.keys("u0gRabcde\x1b").text(), "abcde");
+        assert_eq!(editor.keys("u0Ra\x1b").text(), "ax");
+
+        let mut editor = Editor::new("abcdefgh");
+        assert_eq!(editor.keys("3Rxy\x1b").text(), "xyxyxygh");
+        assert_eq!(editor.keys("0l5rz").text(), "xzzzzzgh");
+        assert_eq!(editor.cursor(), (0, 5));
+        assert_eq!(editor.keys("0l10r-").text(), "xzzzzzgh");
+        assert_eq!(editor.keys("$r\n").text(), "xzzzzzg\n");
+    }
+
+    #[test]
+    fn insert_commands() {
+        let mut editor = Editor::new("  one two\nthree");
+        assert_eq!(editor.keys("Ax\x1b").text(), "  one twox\nthree");
+        assert_eq!(editor.keys("u$Ix\x1b").text(), "  xone two\nthree");
+        assert_eq!(editor.keys("u0wsX\x1b").text(), "  Xne two\nthree");
+        assert_eq!(editor.keys("u0wCX\x1b").text(), "  X\nthree");
+        assert_eq!(editor.keys("u0wD").text(), "  \nthree");
+        assert_eq!(editor.keys("uSX\x1b").text(), "  X\nthree");
+        assert_eq!(editor.keys("uccY\x1b").text(), "  Y\nthree");
+
+        // o and O keep the indent of the cursor line, unless nothing is typed after it
+        assert_eq!(editor.keys("uox\x1b").text(), "  one two\n  x\nthree");
+        assert_eq!(editor.keys("uOx\x1b").text(), "  x\n  one two\nthree");
+        assert_eq!(editor.keys("uo\x1b").text(), "  one two\n\nthree");
+        assert_eq!(editor.keys("u").text(), "  one two\nthree");
+
+        // gi inserts where insert mode was last left
+        let mut editor = Editor::new("abc\ndef");
+        assert_eq!(editor.keys("lix\x1bjgiy\x1b").text(), "axybc\ndef");
+        assert_eq!(editor.keys("u").text(), "axbc\ndef");
+        assert_eq!(editor.keys("u").text(), "abc\ndef");
+    }
+
+    #[test]
+    fn last_inserted() {
+        // Ctrl-A and Ctrl-R . type the last inserted keys again, Ctrl-W and all
+        let mut editor = Editor::new("");
+        assert_eq!(editor.keys("ifoo bar\x17baz\x1bo\x01\x1b").text(), "foo baz\nfoo baz");
+        assert_eq!(editor.keys("o\x12.\x1b").text(), "foo baz\nfoo baz\nfoo baz");
+
+        // A Ctrl-A is kept as the text it inserted, so the next one inserts that again
+        let mut editor = Editor::new("");
+        assert_eq!(editor.keys("ifoo\x1bo\x01\x1bo\x01\x1b").text(), "foo\nfoo\nfoo");
+
+        let mut editor = Editor::new("word");
+        assert_eq!(editor.keys("yiwA \x12\"\x1bo\x01\x1b").text(), "word word\n word");
+        assert_eq!(editor.keys("ox\x08y\x1bo\x12.\x1b").text(), "word word\n word\n y\n y");
+    }
+
+    #[test]
+    fn unhandled_keys() {
+        // Keys with no meaning in insert mode are dropped, Ctrl with Up and Down scroll
+        let mut editor = Editor::new("a\nb");
+        editor.keys("ix").press(vec![Key::F(1), Key::Alt('y'), Key::CtrlDown]).keys("z\x1b");
+        assert_eq!(editor.text(), "xa\nzb");
+        editor.press(vec![Key::CtrlUp, Key::F(5)]).keys("iw\x1b");
+        assert_eq!(editor.text(), "xa\nwzb");
+    }
+
+    #[test]
+    fn curswant() {
+        // The column is kept through shorter lines, counted on the screen over tabs and wide
+        // characters. The cursor is given as a character index in its line.
+        let mut editor = Editor::new("abcdefgh\nab\n\tx\n日本語です\nabcdefgh");
+        assert_eq!(editor.keys("5l").cursor(), (0, 5));
+        assert_eq!(editor.keys("j").cursor(), (1, 1));
+        assert_eq!(editor.keys("j").cursor(), (2, 0));
+        assert_eq!(editor.keys("j").cursor(), (3, 2));
+        assert_eq!(editor.keys("j").cursor(), (4, 5));
+        assert_eq!(editor.keys("3k").cursor(), (1, 1));
+
+        // After $ the cursor goes to the end of every line
+        assert_eq!(editor.keys("gg$").cursor(), (0, 7));
+        assert_eq!(editor.keys("j").cursor(), (1, 1));
+        assert_eq!(editor.keys("j").cursor(), (2, 1));
+        assert_eq!(editor.keys("j").cursor(), (3, 4));
+
+        // A change sets the column again where the cursor ends up
+        assert_eq!(editor.keys("gg$x").cursor(), (0, 6));
+        assert_eq!(editor.keys("jjj").cursor(), (3, 3));
+        assert_eq!(editor.keys("j").cursor(), (4, 6));
+        assert_eq!(editor.keys("k0lx").cursor(), (3, 1));
+        assert_eq!(editor.keys("k").cursor(), (2, 0));
+        assert_eq!(editor.keys("kk").cursor(), (0, 2));
+    }
+
+    #[test]
+    fn join_and_case() {
+        // J adds no space after trailing white space or before a ), and drops the indent
+        let mut editor = Editor::new("a \n)b\nc.\n   d\ne");
+        assert_eq!(editor.keys("J").text(), "a )b\nc.\n   d\ne");
+        assert_eq!(editor.keys("jJ").text(), "a )b\nc. d\ne");
+
+        // gJ keeps the white space and adds none
+        assert_eq!(editor.keys("gJ").text(), "a )b\nc. de");
+        assert_eq!(editor.keys("ugggJ").text(), "a )bc. d\ne");
+        assert_eq!(editor.keys("u3gJ").text(), "a )bc. de");
+
+        // Joins with the cursor on the last line of the range
+        let mut editor = Editor::new("abc\ndef");
+        assert_eq!(editor.keys("vjJ").text(), "abc def");
+        assert_eq!(editor.keys("uVjJ").text(), "abc def");
+        assert_eq!(editor.keys("ujvkgJ").text(), "abcdef");
+        assert_eq!(editor.keys("uj:1,2j\n").text(), "abc def");
+        assert_eq!(editor.keys("uj:%j\n").text(), "abc def");
+        let mut editor = Editor::new("abc\ndef\nghi");
+        assert_eq!(editor.keys("G:1,3j\n").text(), "abc def ghi");
+        assert_eq!(editor.cursor(), (0, 7));
+
+        // Case changes map whole strings, ß becomes SS
+        let mut editor = Editor::new("straße ab");
+        assert_eq!(editor.keys("gUiw").text(), "STRASSE ab");
+        assert_eq!(editor.keys("u03~").text(), "STRaße ab");
+        assert_eq!(editor.cursor(), (0, 3));
+        assert_eq!(editor.keys("u0g??").text(), "fgenßr no");
+        assert_eq!(editor.keys("g??").text(), "straße ab");
+
+        // With tildeop ~ takes a motion like g~, and ~~ switches the whole line
+        assert_eq!(editor.keys(":set tildeop\n0~w").text(), "STRASSE ab");
+        assert_eq!(editor.keys("w~~").text(), "strasse AB");
+    }
+
+    #[test]
+    fn search() {
+        // n counts the matches and says when it wraps around
+        let mut editor = Editor::new("one foo\ntwo\nfoo three foo");
+        assert_eq!(editor.keys("/foo\n").cursor(), (0, 4));
+        assert_eq!(editor.message(), "/foo [1/3]");
+        assert_eq!(editor.keys("nn").cursor(), (2, 10));
+        assert_eq!(editor.message(), "/foo [3/3]");
+        assert_eq!(editor.keys("n").cursor(), (0, 4));
+        assert_eq!(editor.message(), "search hit BOTTOM, continuing at TOP [1/3]");
+
+        // After ? n goes back and N forward
+        assert_eq!(editor.keys("gg?foo\n").cursor(), (2, 10));
+        assert_eq!(editor.message(), "search hit TOP, continuing at BOTTOM [3/3]");
+        assert_eq!(editor.keys("n").cursor(), (2, 0));
+        assert_eq!(editor.message(), "?foo [2/3]");
+        assert_eq!(editor.keys("N").cursor(), (2, 10));
+        assert_eq!(editor.keys("N").cursor(), (0, 4));
+        assert_eq!(editor.message(), "search hit BOTTOM, continuing at TOP [1/3]");
+
+        // // searches for the last pattern again
+        assert_eq!(editor.keys("gg/three\n").cursor(), (2, 4));
+        assert_eq!(editor.keys("gg//\n").cursor(), (2, 4));
+
+        // An offset moves from the end of the match
+        let mut editor = Editor::new("a b foo. c");
+        assert_eq!(editor.keys("/foo/e+1\n").cursor(), (0, 7));
+        assert_eq!(editor.keys("0/foo/e\n").cursor(), (0, 6));
+
+        // An operator takes a search up to the match, or with /e up to its end
+        assert_eq!(editor.keys("0d/foo\n").text(), "foo. c");
+        assert_eq!(editor.keys("u0d/foo/e\n").text(), ". c");
+
+        // Incsearch moves the cursor while the search is typed, and Esc puts it back
+        let mut editor = Editor::new("one foo\ntwo\nfoo three foo");
+        assert_eq!(editor.keys("w/thr").cursor(), (2, 4));
+        assert_eq!(editor.keys("\x1b").cursor(), (0, 4));
+        assert_eq!(editor.keys("/tw\x08\x08\x08").cursor(), (0, 4));
+
+        // ~ needs a substitute to have been made first
+        assert_eq!(editor.keys("/~\n").message(), "E33: No previous substitute regular expression");
+        assert_eq!(editor.cursor(), (0, 4));
+    }
+
+    #[test]
+    fn hlsearch() {
+        // Matches are only highlighted with hlsearch set
+        let mut editor = Editor::new("foo bar foo\nbar");
+        assert_eq!(editor.keys("/foo\n").highlighted(), "");
+        assert_eq!(editor.keys(":set hls\n").highlighted(), "foofoo");
+
+        // :nohlsearch hides them until the next search or n
+        assert_eq!(editor.keys(":noh\n").highlighted(), "");
+        assert_eq!(editor.keys("n").highlighted(), "foofoo");
+        assert_eq!(editor.keys(":nohlsearch\n").highlighted(), "");
+        assert_eq!(editor.keys("/bar\n").highlighted(), "barbar");
+
+        // Changing the text searches its rows again
+        assert_eq!(editor.keys("ggibar\x1b").highlighted(), "barbarbar");
+        assert_eq!(editor.keys("jdd").highlighted(), "barbar");
+
+        // n shows which match it went to of how many, up to 99
+        let mut editor = Editor::new("a b a\nb a");
+        assert_eq!(editor.keys("/a\n").message(), "/a [2/3]");
+        assert_eq!(editor.keys("n").message(), "/a [3/3]");
+        assert_eq!(editor.keys("N").message(), "?a [2/3]");
+        let mut editor = Editor::new(&"a".repeat(150));
+        assert_eq!(editor.keys("/a\n").message(), "/a [2/>99]");
+        assert_eq!(editor.keys("$n").message(), "search hit BOTTOM, continuing at TOP [1/>99]");
+    }
+
+    #[test]
+    fn star_and_gd() {
+        let text = "let piece_table = 1;\nuse piece;\nx = piece_table + piece_tables;\n\nfn f() {}\n{\n  piece_table\n}";
+
+        // * and # search for the whole keyword, with _ in it by default
+        let mut editor = Editor::new(text);
+        assert_eq!(editor.keys("w*").cursor(), (2, 4));
+        assert_eq!(editor.message(), "/\\<piece_table\\> [2/3]");
+        assert_eq!(editor.keys("#").cursor(), (0, 4));
+        assert_eq!(editor.message(), "?\\<piece_table\\> [1/3]");
+        assert_eq!(editor.keys("2*").cursor(), (6, 2));
+        assert_eq!(editor.keys("n").cursor(), (0, 4));
+
+        // g* and g# find it inside other words too
+        assert_eq!(editor.keys("3Gwg*").cursor(), (2, 18));
+        assert_eq!(editor.message(), "/piece_table [3/4]");
+        assert_eq!(editor.keys("3Gwg#").cursor(), (0, 4));
+        assert_eq!(editor.message(), "?piece_table [1/4]");
+
+        // Off a keyword they take the next one in the line
+        assert_eq!(editor.keys("gg0f=*").cursor(), (0, 18));
+        assert_eq!(editor.message(), "search hit BOTTOM, continuing at TOP [1/1]");
+        assert_eq!(editor.keys("\"/p").text().lines().next(), Some("let piece_table = 1\\<1\\>;"));
+        editor.keys("u");
+        assert_eq!(editor.keys("3G0f=*").cursor(), (6, 2));
+
+        // gd goes to the first use in the function [[ goes back to, or in the file outside of one
+        assert_eq!(editor.keys("7G3|gd").cursor(), (6, 2));
+        assert_eq!(editor.keys("3Gwgd").cursor(), (0, 4));
+        assert_eq!(editor.keys("7G3|gD").cursor(), (0, 4));
+
+        // The keyword becomes the last search
+        assert_eq!(editor.keys("n").cursor(), (2, 4));
+    }
+
+    #[test]
+    fn write_and_quit() {
+        // :wq always writes, :x only a modified buffer, and either quits once it is written
+        let mut cmd = CommandView::new();
+        cmd.set_size(Position { row: 1, col: 80 });
+        cmd.add_keystrokes(text_to_keys(":wq\n:x\n:exit\n"));
+        let cmds: Vec<String> = std::iter::from_fn(|| cmd.get_app_command())
+            .filter(|c| !matches!(c, ApplicationCommand::FocusText | ApplicationCommand::FocusCommand))
+            .map(|c| format!("{c:?}"))
+            .collect();
+        assert_eq!(cmds, ["WriteQuit(true)", "WriteQuit(false)", "WriteQuit(false)"]);
+    }
+
+    #[test]
+    fn visual_line_end() {
+        // After $ a characterwise selection takes in the newline, through j and from o, until
+        // another move
+        let mut editor = Editor::new("a\nb");
+        assert_eq!(editor.keys("v$d").text(), "b");
+        assert_eq!(editor.keys("uGv$d").text(), "a\n");
+        let mut editor = Editor::new("ab\ncd\nef");
+        assert_eq!(editor.keys("lv$d").text(), "acd\nef");
+        assert_eq!(editor.keys("ugg0lv$jd").text(), "aef");
+        assert_eq!(editor.keys("ugg0lv$od").text(), "acd\nef");
+        assert_eq!(editor.keys("ugg0lv$hd").text(), "\ncd\nef");
+    }
+
+    #[test]
+    fn upper_case_registers() {
+        // Upper case names append when writing and read the lower case register
+        let mut editor = Editor::new("abc def");
+        assert_eq!(editor.keys("\"Ayw\"Ayw$\"Ap").text(), "abc defabc abc ");
+        assert_eq!(editor.keys("u0\"AP").text(), "abc abc abc def");
+        assert_eq!(editor.keys("uA \x12A\x1b").text(), "abc def abc abc ");
+        let mut editor = Editor::new("abcd");
+        assert_eq!(editor.keys("qAxq@A").text(), "cd");
+    }
+
+    #[test]
+    fn repeat_visual() {
+        // . after a visual operator takes as much text again from the cursor
+        let mut editor = Editor::new("abcdef\nghijkl\nmnopqr\nstuvwx");
+        assert_eq!(editor.keys("vld").text(), "cdef\nghijkl\nmnopqr\nstuvwx");
+        assert_eq!(editor.keys("l.").text(), "cf\nghijkl\nmnopqr\nstuvwx");
+        assert_eq!(editor.keys("uj0v~").text(), "cdef\nGhijkl\nmnopqr\nstuvwx");
+        assert_eq!(editor.keys("l.").text(), "cdef\nGHijkl\nmnopqr\nstuvwx");
+
+        // Lines stay lines, and a characterwise selection of several lines ends at the same column
+        let mut editor = Editor::new("abcdef\nghijkl\nmnopqr\nstuvwx");
+        assert_eq!(editor.keys("Vj>").text(), "\tabcdef\n\tghijkl\nmnopqr\nstuvwx");
+        assert_eq!(editor.keys("jj.").text(), "\tabcdef\n\tghijkl\n\tmnopqr\n\tstuvwx");
+        let mut editor = Editor::new("abcdef\nghijkl\nmnopqr\nstuvwx");
+        assert_eq!(editor.keys("lvjd").text(), "aijkl\nmnopqr\nstuvwx");
+        assert_eq!(editor.keys("j0.").text(), "aijkl\nuvwx");
+
+        // A change types the same text again, and a block keeps its width
+        let mut editor = Editor::new("abcdef\nghijkl\nmnopqr\nstuvwx");
+        assert_eq!(editor.keys("vlcX\x1b").text(), "Xcdef\nghijkl\nmnopqr\nstuvwx");
+        assert_eq!(editor.keys("j0.").text(), "Xcdef\nXijkl\nmnopqr\nstuvwx");
+        assert_eq!(editor.keys("j0\x16jld").text(), "Xcdef\nXijkl\nopqr\nuvwx");
+        assert_eq!(editor.keys("k0l.").text(), "Xcdef\nXkl\nor\nuvwx");
+
+        // A yank leaves the last change alone
+        assert_eq!(editor.keys("u0vy0.").text(), "Xcdef\njkl\nqr\nuvwx");
+    }
+
+    #[test]
+    fn large_counts() {
+        // Counts go past 65535 and stop at the largest number instead
+        let mut editor = Editor::new("1");
+        assert_eq!(editor.keys("99999999999\x01").text(), "100000000000");
+        assert_eq!(editor.keys("99999999999999999999\x01").text(), "9223372036854775807");
+        let mut editor = Editor::new(&format!("{}\nabc", "a".repeat(70000)));
+        assert_eq!(editor.keys("69999x").text(), "a\nabc");
+        assert_eq!(editor.keys("99999999999x").text(), "\nabc");
+
+        // Searches go round the matches, and motions stop at the end of the text
+        let mut editor = Editor::new("a a a");
+        assert_eq!(editor.keys("/a\r70000n").cursor(), (0, 4));
+        assert_eq!(editor.keys("99999999999)").cursor(), (0, 4));
+
+        // Repeating text that would fill memory fails
+        let mut editor = Editor::new("ab");
+        editor.keys("yl99999999999p");
+        assert_eq!(editor.text(), "ab");
+        assert_eq!(editor.message(), "E1240: Resulting text too long");
+        editor.keys("99999999999ia\x1b");
+        assert_eq!(editor.text(), "aab");
+        assert_eq!(editor.message(), "E1240: Resulting text too long");
+    }
+
+    #[test]
+    fn clipboard_paste() {
+        // Without a paste command "+ holds the text copied last, a failing one is reported
+        let mut editor = Editor::new("abc");
+        assert_eq!(editor.keys(":set clippaste=\n\"+yl\"+p").text(), "aabc");
+        editor.keys(":set clippaste=false\n\"+p");
+        assert_eq!(editor.text(), "aabc");
+        assert_eq!(editor.message(), "Clipboard: false failed with exit status: 1");
+        assert_eq!(editor.keys("i\x12*\x1b").text(), "aabc");
+    }
+}
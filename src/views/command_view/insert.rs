@@ -0,0 +1,119 @@
+use termion::event::Key;
+
+use crate::motions::Motion;
+use crate::registers::Registers;
+
+use super::normal::{key_motion, key_scroll, Parsed};
+
+/// A complete insert or replace mode command.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InsertCommand {
+    Type(char),        // A typed character, or one inserted literally with Ctrl-V
+    Backspace,         // Backspace or Ctrl-H
+    DeleteWord,        // Ctrl-W, delete the word before the cursor
+    DeleteLine,        // Ctrl-U, delete the text before the cursor in the line
+    Shift(bool),       // Ctrl-T, or Ctrl-D when false, shift the line by 'shiftwidth'
+    PutRegister(char), // Ctrl-R {reg}, and Ctrl-A which inserts the last inserted text from ".
+    Normal,            // Ctrl-O, run one normal mode command and come back
+    DeleteForward,     // Del, delete the character under the cursor
+    Move(Motion),      // Arrow keys, Home and End
+    ScrollPage(bool),  // PageDown, or PageUp when false
+    ToggleReplace,     // Insert, switch between insert and replace mode
+    Exit,
+}
+
+impl InsertCommand {
+    /// Whether the command is part of the inserted text, which a count repeats.
+    pub fn is_text(&self) -> bool {
+        !matches!(
+            self,
+            InsertCommand::Normal
+                | InsertCommand::Move(_)
+                | InsertCommand::ScrollPage(_)
+                | InsertCommand::ToggleReplace
+                | InsertCommand::Exit
+        )
+    }
+}
+
+/// Parse an insert mode command from the start of the pending keys.
+pub fn parse_insert(keys: &[Key]) -> Parsed<InsertCommand> {
+    if let Some(motion) = keys.first().and_then(key_motion) {
+        return Parsed::Done(InsertCommand::Move(motion), 1);
+    }
+    if let Some(forward) = keys.first().and_then(key_scroll) {
+        return Parsed::Done(InsertCommand::ScrollPage(forward), 1);
+    }
+    let cmd = match keys.first() {
+        None => return Parsed::Pending,
+        Some(Key::Esc) => InsertCommand::Exit,
+        Some(Key::Delete) => InsertCommand::DeleteForward,
+        Some(Key::Insert) => InsertCommand::ToggleReplace,
+        Some(Key::Char(c)) => InsertCommand::Type(*c),
+        Some(Key::Backspace) | Some(Key::Ctrl('h')) => InsertCommand::Backspace,
+        Some(Key::Ctrl('w')) => InsertCommand::DeleteWord,
+        Some(Key::Ctrl('u')) => InsertCommand::DeleteLine,
+        Some(Key::Ctrl('t')) => InsertCommand::Shift(true),
+        Some(Key::Ctrl('d')) => InsertCommand::Shift(false),
+        Some(Key::Ctrl('a')) => InsertCommand::PutRegister('.'),
+        Some(Key::Ctrl('o')) => InsertCommand::Normal,
+        Some(Key::Ctrl('r')) => {
+            return match keys.get(1) {
+                None => Parsed::Pending,
+                Some(Key::Char(name)) if Registers::is_valid(*name) => Parsed::Done(InsertCommand::PutRegister(*name), 2),
+                Some(_) => Parsed::Invalid(2),
+            };
+        }
+        Some(Key::Ctrl('v')) => {
+            return match parse_literal(&keys[1..]) {
+                Parsed::Done(c, n) => Parsed::Done(InsertCommand::Type(c), n + 1),
+                Parsed::Pending => Parsed::Pending,
+                Parsed::NoMatch => Parsed::NoMatch,
+                Parsed::Invalid(n) => Parsed::Invalid(n + 1),
+            };
+        }
+        Some(_) => return Parsed::NoMatch,
+    };
+    Parsed::Done(cmd, 1)
+}
+
+/// The character after Ctrl-V. It is taken as it is, or given by its code: up to three decimal
+/// digits, o and three octal digits, or x, u and U with up to two, four and eight hex digits.
+fn parse_literal(keys: &[Key]) -> Parsed<char> {
+    let (prefix, radix, max) = match keys.first() {
+        None => return Parsed::Pending,
+        Some(Key::Char(c @ ('x' | 'X'))) => (Some(*c), 16, 2),
+        Some(Key::Char('u')) => (Some('u'), 16, 4),
+        Some(Key::Char('U')) => (Some('U'), 16, 8),
+        Some(Key::Char(c @ ('o' | 'O'))) => (Some(*c), 8, 3),
+        Some(Key::Char(c)) if c.is_ascii_digit() => (None, 10, 3),
+        Some(Key::Char(c)) => return Parsed::Done(*c, 1),
+        Some(Key::Esc) => return Parsed::Done('\x1b', 1),
+        Some(Key::Backspace) => return Parsed::Done('\x7f', 1),
+        Some(Key::Ctrl(c)) if c.is_ascii_lowercase() => return Parsed::Done((*c as u8 - b'a' + 1) as char, 1),
+        Some(_) => return Parsed::Invalid(1),
+    };
+
+    let skip = prefix.is_some() as usize;
+    let mut code = 0u32;
+    let mut digits = 0;
+    while digits < max {
+        match keys.get(skip + digits) {
+            // Fewer digits are fine, but only a key that isn't one shows there are no more
+            None => return Parsed::Pending,
+            Some(Key::Char(c)) if c.is_digit(radix) => {
+                code = code * radix + c.to_digit(radix).unwrap();
+                digits += 1;
+            }
+            Some(_) => break,
+        }
+    }
+
+    match (prefix, digits) {
+        (Some(c), 0) => Parsed::Done(c, 1),
+        _ => match char::from_u32(code) {
+            Some(c) if radix == 16 || code <= 255 => Parsed::Done(c, skip + digits),
+            _ => Parsed::Invalid(skip + digits),
+        },
+    }
+}
@@ -0,0 +1,548 @@
+use termion::event::Key;
+
+use crate::motions::objects::TextObject;
+use crate::motions::Motion;
+use crate::registers::Registers;
+use crate::views::text_view::{InsertAt, MotionForce, Operator, OperatorTarget};
+
+/// Result of trying to parse a command from the start of the pending keys.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Parsed<T> {
+    Done(T, usize), // The command and the number of keys it used
+    Pending,        // The keys so far are the start of a command, wait for more
+    NoMatch,
+    Invalid(usize), // The keys can't form a command, drop this many of them
+}
+
+/// A complete normal mode command.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NormalCommand {
+    Move(Motion),
+    Operate(Operator, OperatorTarget),
+    Insert(InsertAt),
+    DeleteChar,
+    Undo,
+    Redo,
+    UndoLine,
+    Earlier,
+    Later,
+    Visual(MotionForce), // v, V or Ctrl-V
+    Reselect,            // gv
+    Register(char),      // "x, the register for the next command
+    Put { before: bool, follow: bool }, // p, P, gp and gP
+    Repeat,                             // ., repeat the last change
+    Record(char),                       // q{reg}, start recording keys into a register
+    Play(char),                         // @{reg}, run the keys in a register
+    Replace(bool),                      // R, or gR when true which replaces screen columns
+    ReplaceChar(char),                  // r{char}
+    ScrollPage(bool),                   // PageDown, or PageUp when false
+    SwitchCase,                         // ~ without 'tildeop'
+    AddNumber(bool),                    // Ctrl-A, or Ctrl-X when true which subtracts
+}
+
+impl NormalCommand {
+    /// Whether the command changes the text, so that . repeats it.
+    pub fn is_change(&self) -> bool {
+        match self {
+            NormalCommand::Operate(op, _) => *op != Operator::Yank,
+            NormalCommand::Insert(_)
+            | NormalCommand::DeleteChar
+            | NormalCommand::Put { .. }
+            | NormalCommand::Replace(_)
+            | NormalCommand::ReplaceChar(_)
+            | NormalCommand::SwitchCase
+            | NormalCommand::AddNumber(_) => true,
+            _ => false,
+        }
+    }
+}
+
+/// A complete visual mode command.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VisualCommand {
+    Move(Motion),
+    Select(TextObject),
+    Operate(Operator, VisualExtent),
+    Insert(bool),      // I, or A when true
+    Mode(MotionForce), // Change the kind of selection, or leave visual mode if it is the same
+    SwapEnds(bool),    // o, or O which goes to the other corner of a block on the same line
+    Exit,
+    CommandLine,
+    Register(char),
+    ScrollPage(bool),
+}
+
+/// What a visual mode operator acts on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VisualExtent {
+    Selection,
+    Lines,            // All lines of the selection, as S or J
+    LinesUnlessBlock, // X and Y, which act on a block as it is
+    LinesOrLineEnds,  // D and C, which extend a block to the end of its lines
+}
+
+/// Parse a `[count]` prefix, returning the count and the number of keys it used.
+fn parse_count(keys: &[Key]) -> (Option<usize>, usize) {
+    let mut count: Option<usize> = None;
+    let mut n = 0;
+    while let Some(Key::Char(c)) = keys.get(n) {
+        match c.to_digit(10) {
+            Some(0) if count.is_none() => break, // A leading 0 is a motion
+            Some(d) => count = Some(count.unwrap_or(0).saturating_mul(10).saturating_add(d as usize)),
+            None => break,
+        }
+        n += 1;
+    }
+    (count, n)
+}
+
+/// Counts on both the operator and the motion multiply, so 2d3w deletes 6 words.
+fn multiply_counts(a: Option<usize>, b: Option<usize>) -> Option<usize> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a.saturating_mul(b)),
+        (a, b) => a.or(b),
+    }
+}
+
+/// Fold a count into motions that treat it as a line number instead of a repeat.
+fn resolve_count(motion: Motion, count: Option<usize>) -> (Motion, Option<usize>) {
+    match (motion, count) {
+        (Motion::GotoLine(_), Some(n)) | (Motion::LastLine, Some(n)) => (Motion::GotoLine(n), None),
+        _ => (motion, count),
+    }
+}
+
+/// Parse a normal mode command, where ~ is an operator with 'tildeop'.
+pub fn parse_normal(keys: &[Key], tildeop: bool) -> Parsed<(Option<usize>, NormalCommand)> {
+    let (count, c) = parse_count(keys);
+    let keys = &keys[c..];
+
+    match parse_operator(keys, tildeop) {
+        Parsed::Done(op, n) => {
+            let (motion_count, m) = parse_count(&keys[n..]);
+            let count = multiply_counts(count, motion_count);
+            return match parse_operator_target(op, &keys[n + m..]) {
+                Parsed::Done(OperatorTarget::Motion(motion, force), t) => {
+                    let (motion, count) = resolve_count(motion, count);
+                    Parsed::Done((count, NormalCommand::Operate(op, OperatorTarget::Motion(motion, force))), c + n + m + t)
+                }
+                Parsed::Done(target, t) => Parsed::Done((count, NormalCommand::Operate(op, target)), c + n + m + t),
+                Parsed::Pending => Parsed::Pending,
+                Parsed::NoMatch => Parsed::Invalid(c + n + m + 1),
+                Parsed::Invalid(t) => Parsed::Invalid(c + n + m + t),
+            };
+        }
+        Parsed::Pending => return Parsed::Pending,
+        Parsed::Invalid(n) => return Parsed::Invalid(c + n),
+        Parsed::NoMatch => (),
+    }
+
+    match parse_motion(keys) {
+        Parsed::Done(motion, n) => {
+            let (motion, count) = resolve_count(motion, count);
+            return Parsed::Done((count, NormalCommand::Move(motion)), c + n);
+        }
+        Parsed::Pending => return Parsed::Pending,
+        Parsed::Invalid(n) => return Parsed::Invalid(c + n),
+        Parsed::NoMatch => (),
+    }
+
+    let cmd = match keys.first() {
+        None => return Parsed::Pending,
+        Some(Key::Char('i')) => NormalCommand::Insert(InsertAt::Cursor),
+        Some(Key::Char('a')) => NormalCommand::Insert(InsertAt::After),
+        Some(Key::Char('I')) => NormalCommand::Insert(InsertAt::FirstNonBlank),
+        Some(Key::Char('A')) => NormalCommand::Insert(InsertAt::LineEnd),
+        Some(Key::Char('o')) => NormalCommand::Insert(InsertAt::LineBelow),
+        Some(Key::Char('O')) => NormalCommand::Insert(InsertAt::LineAbove),
+        // s, S, C and D are short for cl, cc, c$ and d$
+        Some(Key::Char('s')) => NormalCommand::Operate(Operator::Change, OperatorTarget::Motion(Motion::Right, None)),
+        Some(Key::Char('S')) => NormalCommand::Operate(Operator::Change, OperatorTarget::Lines),
+        Some(Key::Char('C')) => NormalCommand::Operate(Operator::Change, OperatorTarget::Motion(Motion::LineEnd, None)),
+        Some(Key::Char('D')) => NormalCommand::Operate(Operator::Delete, OperatorTarget::Motion(Motion::LineEnd, None)),
+        Some(Key::Char('x')) | Some(Key::Delete) => NormalCommand::DeleteChar,
+        Some(Key::Char('~')) => NormalCommand::SwitchCase,
+        Some(Key::Ctrl('a')) => NormalCommand::AddNumber(false),
+        Some(Key::Ctrl('x')) => NormalCommand::AddNumber(true),
+        // J and gJ act on count lines, and at least two
+        Some(Key::Char('J')) => NormalCommand::Operate(Operator::Join(true), OperatorTarget::Lines),
+        Some(Key::Insert) => NormalCommand::Insert(InsertAt::Cursor),
+        Some(key) if key_scroll(key).is_some() => NormalCommand::ScrollPage(key_scroll(key).unwrap()),
+        Some(Key::Char('u')) => NormalCommand::Undo,
+        Some(Key::Ctrl('r')) => NormalCommand::Redo,
+        Some(Key::Char('U')) => NormalCommand::UndoLine,
+        Some(Key::Char('v')) => NormalCommand::Visual(MotionForce::Charwise),
+        Some(Key::Char('V')) => NormalCommand::Visual(MotionForce::Linewise),
+        Some(Key::Ctrl('v')) => NormalCommand::Visual(MotionForce::Blockwise),
+        Some(Key::Char('p')) => NormalCommand::Put { before: false, follow: false },
+        Some(Key::Char('P')) => NormalCommand::Put { before: true, follow: false },
+        Some(Key::Char('.')) => NormalCommand::Repeat,
+        Some(Key::Char('R')) => NormalCommand::Replace(false),
+        Some(Key::Char('r')) => {
+            return match keys.get(1) {
+                None => Parsed::Pending,
+                Some(Key::Char(r)) => Parsed::Done((count, NormalCommand::ReplaceChar(*r)), c + 2),
+                Some(_) => Parsed::Invalid(c + 2),
+            };
+        }
+        Some(Key::Char('"')) => {
+            return match parse_register(keys) {
+                Parsed::Done(name, n) => Parsed::Done((count, NormalCommand::Register(name)), c + n),
+                Parsed::Pending => Parsed::Pending,
+                _ => Parsed::Invalid(c + 2),
+            };
+        }
+        Some(Key::Char('q')) => {
+            return match keys.get(1) {
+                None => Parsed::Pending,
+                Some(Key::Char(name)) if name.is_ascii_alphanumeric() || *name == '"' => {
+                    Parsed::Done((count, NormalCommand::Record(*name)), c + 2)
+                }
+                Some(_) => Parsed::Invalid(c + 2),
+            };
+        }
+        Some(Key::Char('@')) => {
+            return match keys.get(1) {
+                None => Parsed::Pending,
+                Some(Key::Char(name)) if Registers::is_valid(*name) || *name == '@' => {
+                    Parsed::Done((count, NormalCommand::Play(*name)), c + 2)
+                }
+                Some(_) => Parsed::Invalid(c + 2),
+            };
+        }
+        Some(Key::Char('g')) => {
+            return match keys.get(1) {
+                None => Parsed::Pending,
+                Some(Key::Char('v')) => Parsed::Done((count, NormalCommand::Reselect), c + 2),
+                Some(Key::Char('i')) => Parsed::Done((count, NormalCommand::Insert(InsertAt::LastInsert)), c + 2),
+                Some(Key::Char('p')) => Parsed::Done((count, NormalCommand::Put { before: false, follow: true }), c + 2),
+                Some(Key::Char('P')) => Parsed::Done((count, NormalCommand::Put { before: true, follow: true }), c + 2),
+                Some(Key::Char('R')) => Parsed::Done((count, NormalCommand::Replace(true)), c + 2),
+                Some(Key::Char('J')) => {
+                    Parsed::Done((count, NormalCommand::Operate(Operator::Join(false), OperatorTarget::Lines)), c + 2)
+                }
+                Some(Key::Char('-')) => Parsed::Done((count, NormalCommand::Earlier), c + 2),
+                Some(Key::Char('+')) => Parsed::Done((count, NormalCommand::Later), c + 2),
+                Some(_) => Parsed::Invalid(c + 2),
+            };
+        }
+        Some(_) => return Parsed::Invalid(c + 1),
+    };
+    Parsed::Done((count, cmd), c + 1)
+}
+
+pub fn parse_visual(keys: &[Key]) -> Parsed<(Option<usize>, VisualCommand)> {
+    let (count, c) = parse_count(keys);
+    let keys = &keys[c..];
+
+    match parse_motion(keys) {
+        Parsed::Done(motion, n) => {
+            let (motion, count) = resolve_count(motion, count);
+            return Parsed::Done((count, VisualCommand::Move(motion)), c + n);
+        }
+        Parsed::Pending => return Parsed::Pending,
+        Parsed::Invalid(n) => return Parsed::Invalid(c + n),
+        Parsed::NoMatch => (),
+    }
+
+    match parse_object(keys) {
+        Parsed::Done(object, n) => return Parsed::Done((count, VisualCommand::Select(object)), c + n),
+        Parsed::Pending => return Parsed::Pending,
+        Parsed::Invalid(n) => return Parsed::Invalid(c + n),
+        Parsed::NoMatch => (),
+    }
+
+    let cmd = match keys.first() {
+        None => return Parsed::Pending,
+        Some(key) if key_scroll(key).is_some() => VisualCommand::ScrollPage(key_scroll(key).unwrap()),
+        Some(Key::Char('d')) | Some(Key::Char('x')) | Some(Key::Delete) => VisualCommand::Operate(Operator::Delete, VisualExtent::Selection),
+        Some(Key::Char('D')) => VisualCommand::Operate(Operator::Delete, VisualExtent::LinesOrLineEnds),
+        Some(Key::Char('X')) => VisualCommand::Operate(Operator::Delete, VisualExtent::LinesUnlessBlock),
+        Some(Key::Char('c')) | Some(Key::Char('s')) => VisualCommand::Operate(Operator::Change, VisualExtent::Selection),
+        Some(Key::Char('C')) => VisualCommand::Operate(Operator::Change, VisualExtent::LinesOrLineEnds),
+        Some(Key::Char('S')) | Some(Key::Char('R')) => VisualCommand::Operate(Operator::Change, VisualExtent::Lines),
+        Some(Key::Char('y')) => VisualCommand::Operate(Operator::Yank, VisualExtent::Selection),
+        Some(Key::Char('Y')) => VisualCommand::Operate(Operator::Yank, VisualExtent::LinesUnlessBlock),
+        Some(Key::Char('>')) => VisualCommand::Operate(Operator::ShiftRight, VisualExtent::Lines),
+        Some(Key::Char('<')) => VisualCommand::Operate(Operator::ShiftLeft, VisualExtent::Lines),
+        Some(Key::Char('=')) => VisualCommand::Operate(Operator::Indent, VisualExtent::Lines),
+        Some(Key::Char('~')) => VisualCommand::Operate(Operator::ToggleCase, VisualExtent::Selection),
+        Some(Key::Char('u')) => VisualCommand::Operate(Operator::Lowercase, VisualExtent::Selection),
+        Some(Key::Char('U')) => VisualCommand::Operate(Operator::Uppercase, VisualExtent::Selection),
+        Some(Key::Char('J')) => VisualCommand::Operate(Operator::Join(true), VisualExtent::Lines),
+        Some(Key::Ctrl('a')) => VisualCommand::Operate(Operator::AddNumber { subtract: false, progressive: false }, VisualExtent::Selection),
+        Some(Key::Ctrl('x')) => VisualCommand::Operate(Operator::AddNumber { subtract: true, progressive: false }, VisualExtent::Selection),
+        Some(Key::Char('r')) => {
+            return match keys.get(1) {
+                None => Parsed::Pending,
+                Some(Key::Char(r)) => Parsed::Done((count, VisualCommand::Operate(Operator::Replace(*r), VisualExtent::Selection)), c + 2),
+                Some(_) => Parsed::Invalid(c + 2),
+            };
+        }
+        Some(Key::Char('I')) => VisualCommand::Insert(false),
+        Some(Key::Char('A')) => VisualCommand::Insert(true),
+        Some(Key::Char('o')) => VisualCommand::SwapEnds(false),
+        Some(Key::Char('O')) => VisualCommand::SwapEnds(true),
+        Some(Key::Char('v')) => VisualCommand::Mode(MotionForce::Charwise),
+        Some(Key::Char('V')) => VisualCommand::Mode(MotionForce::Linewise),
+        Some(Key::Ctrl('v')) => VisualCommand::Mode(MotionForce::Blockwise),
+        Some(Key::Esc) => VisualCommand::Exit,
+        Some(Key::Char(':')) => VisualCommand::CommandLine,
+        Some(Key::Char('"')) => {
+            return match parse_register(keys) {
+                Parsed::Done(name, n) => Parsed::Done((count, VisualCommand::Register(name)), c + n),
+                Parsed::Pending => Parsed::Pending,
+                _ => Parsed::Invalid(c + 2),
+            };
+        }
+        Some(Key::Char('g')) => {
+            let cmd = match keys.get(1) {
+                None => return Parsed::Pending,
+                Some(Key::Char('~')) => VisualCommand::Operate(Operator::ToggleCase, VisualExtent::Selection),
+                Some(Key::Char('u')) => VisualCommand::Operate(Operator::Lowercase, VisualExtent::Selection),
+                Some(Key::Char('U')) => VisualCommand::Operate(Operator::Uppercase, VisualExtent::Selection),
+                Some(Key::Char('J')) => VisualCommand::Operate(Operator::Join(false), VisualExtent::Lines),
+                Some(Key::Char('?')) => VisualCommand::Operate(Operator::Rot13, VisualExtent::Selection),
+                Some(Key::Ctrl('a')) => VisualCommand::Operate(Operator::AddNumber { subtract: false, progressive: true }, VisualExtent::Selection),
+                Some(Key::Ctrl('x')) => VisualCommand::Operate(Operator::AddNumber { subtract: true, progressive: true }, VisualExtent::Selection),
+                Some(_) => return Parsed::Invalid(c + 2),
+            };
+            return Parsed::Done((count, cmd), c + 2);
+        }
+        Some(_) => return Parsed::Invalid(c + 1),
+    };
+    Parsed::Done((count, cmd), c + 1)
+}
+
+/// A register name after ".
+fn parse_register(keys: &[Key]) -> Parsed<char> {
+    match keys.get(1) {
+        None => Parsed::Pending,
+        Some(Key::Char(name)) if Registers::is_valid(*name) => Parsed::Done(*name, 2),
+        Some(_) => Parsed::Invalid(2),
+    }
+}
+
+fn parse_operator(keys: &[Key], tildeop: bool) -> Parsed<Operator> {
+    let op = match keys.first() {
+        None => return Parsed::Pending,
+        Some(Key::Char('~')) if tildeop => Operator::ToggleCase,
+        Some(Key::Char('d')) => Operator::Delete,
+        Some(Key::Char('c')) => Operator::Change,
+        Some(Key::Char('y')) => Operator::Yank,
+        Some(Key::Char('>')) => Operator::ShiftRight,
+        Some(Key::Char('<')) => Operator::ShiftLeft,
+        Some(Key::Char('=')) => Operator::Indent,
+        Some(Key::Char('g')) => {
+            return match keys.get(1) {
+                None => Parsed::Pending,
+                Some(Key::Char('~')) => Parsed::Done(Operator::ToggleCase, 2),
+                Some(Key::Char('u')) => Parsed::Done(Operator::Lowercase, 2),
+                Some(Key::Char('U')) => Parsed::Done(Operator::Uppercase, 2),
+                Some(Key::Char('?')) => Parsed::Done(Operator::Rot13, 2),
+                Some(_) => Parsed::NoMatch,
+            };
+        }
+        Some(_) => return Parsed::NoMatch,
+    };
+    Parsed::Done(op, 1)
+}
+
+/// The keys that repeat an operator to make it act on whole lines, e.g. dd, g~~ or g~g~.
+fn is_doubled(op: Operator, keys: &[Key]) -> Parsed<()> {
+    let last = match op {
+        Operator::Delete => 'd',
+        Operator::Change => 'c',
+        Operator::Yank => 'y',
+        Operator::ShiftRight => '>',
+        Operator::ShiftLeft => '<',
+        Operator::Indent => '=',
+        Operator::ToggleCase => '~',
+        Operator::Lowercase => 'u',
+        Operator::Uppercase => 'U',
+        Operator::Rot13 => '?',
+        Operator::Join(_) | Operator::Replace(_) | Operator::AddNumber { .. } => return Parsed::NoMatch,
+    };
+
+    match keys.first() {
+        None => Parsed::Pending,
+        Some(Key::Char(c)) if *c == last => Parsed::Done((), 1),
+        Some(Key::Char('g')) if matches!(op, Operator::ToggleCase | Operator::Lowercase | Operator::Uppercase | Operator::Rot13) => {
+            match keys.get(1) {
+                None => Parsed::Pending,
+                Some(Key::Char(c)) if *c == last => Parsed::Done((), 2),
+                Some(_) => Parsed::NoMatch,
+            }
+        }
+        Some(_) => Parsed::NoMatch,
+    }
+}
+
+fn parse_operator_target(op: Operator, keys: &[Key]) -> Parsed<OperatorTarget> {
+    let (force, n) = match keys.first() {
+        None => return Parsed::Pending,
+        Some(Key::Esc) => return Parsed::NoMatch,
+        Some(Key::Char('v')) => (Some(MotionForce::Charwise), 1),
+        Some(Key::Char('V')) => (Some(MotionForce::Linewise), 1),
+        Some(Key::Ctrl('v')) => (Some(MotionForce::Blockwise), 1),
+        Some(_) => (None, 0),
+    };
+
+    match is_doubled(op, &keys[n..]) {
+        Parsed::Done(_, m) => return Parsed::Done(OperatorTarget::Lines, n + m),
+        Parsed::Pending => return Parsed::Pending,
+        Parsed::Invalid(m) => return Parsed::Invalid(n + m),
+        Parsed::NoMatch => (),
+    }
+
+    match parse_motion(&keys[n..]) {
+        Parsed::Done(motion, m) => return Parsed::Done(OperatorTarget::Motion(motion, force), n + m),
+        Parsed::Pending => return Parsed::Pending,
+        Parsed::Invalid(m) => return Parsed::Invalid(n + m),
+        Parsed::NoMatch => (),
+    }
+
+    match parse_object(&keys[n..]) {
+        Parsed::Done(object, m) => Parsed::Done(OperatorTarget::Object(object, force), n + m),
+        Parsed::Pending => Parsed::Pending,
+        Parsed::Invalid(m) => Parsed::Invalid(n + m),
+        Parsed::NoMatch => Parsed::Invalid(n + 1),
+    }
+}
+
+/// Text objects, `i` or `a` followed by the kind of object.
+pub fn parse_object(keys: &[Key]) -> Parsed<TextObject> {
+    let around = match keys.first() {
+        None => return Parsed::Pending,
+        Some(Key::Char('i')) => false,
+        Some(Key::Char('a')) => true,
+        Some(_) => return Parsed::NoMatch,
+    };
+
+    let object = match keys.get(1) {
+        None => return Parsed::Pending,
+        Some(Key::Char('w')) => TextObject::Word { big: false, around },
+        Some(Key::Char('W')) => TextObject::Word { big: true, around },
+        Some(Key::Char('s')) => TextObject::Sentence { around },
+        Some(Key::Char('p')) => TextObject::Paragraph { around },
+        Some(Key::Char('(')) | Some(Key::Char(')')) | Some(Key::Char('b')) => TextObject::Block { open: '(', close: ')', around },
+        Some(Key::Char('{')) | Some(Key::Char('}')) | Some(Key::Char('B')) => TextObject::Block { open: '{', close: '}', around },
+        Some(Key::Char('[')) | Some(Key::Char(']')) => TextObject::Block { open: '[', close: ']', around },
+        Some(Key::Char('<')) | Some(Key::Char('>')) => TextObject::Block { open: '<', close: '>', around },
+        Some(Key::Char(c)) if matches!(c, '"' | '\'' | '`') => TextObject::Quote { quote: *c, around },
+        Some(Key::Char('t')) => TextObject::Tag { around },
+        Some(_) => return Parsed::Invalid(2),
+    };
+    Parsed::Done(object, 2)
+}
+
+/// The motion of an arrow, Home or End key, which are the same in every mode. Ctrl or Shift
+/// with Left and Right jump by words.
+pub fn key_motion(key: &Key) -> Option<Motion> {
+    Some(match key {
+        Key::Left => Motion::Left,
+        Key::Right => Motion::Right,
+        Key::Up => Motion::Up,
+        Key::Down => Motion::Down,
+        Key::Home => Motion::LineStart,
+        Key::End => Motion::LineEnd,
+        Key::CtrlLeft | Key::ShiftLeft => Motion::WordBackward(false),
+        Key::CtrlRight | Key::ShiftRight => Motion::WordForward(false),
+        Key::CtrlHome => Motion::GotoLine(1),
+        Key::CtrlEnd => Motion::LastLine,
+        _ => return None,
+    })
+}
+
+/// PageDown and PageUp, or Ctrl or Shift with Down and Up, scroll by a screen. True for forward.
+pub fn key_scroll(key: &Key) -> Option<bool> {
+    match key {
+        Key::PageDown | Key::ShiftDown | Key::CtrlDown => Some(true),
+        Key::PageUp | Key::ShiftUp | Key::CtrlUp => Some(false),
+        _ => None,
+    }
+}
+
+pub fn parse_motion(keys: &[Key]) -> Parsed<Motion> {
+    if let Some(motion) = keys.first().and_then(key_motion) {
+        return Parsed::Done(motion, 1);
+    }
+    let motion = match keys.first() {
+        None => return Parsed::Pending,
+        Some(Key::Char('h')) | Some(Key::Backspace) => Motion::Left,
+        Some(Key::Char('l')) | Some(Key::Char(' ')) => Motion::Right,
+        Some(Key::Char('k')) => Motion::Up,
+        Some(Key::Char('j')) => Motion::Down,
+        Some(Key::Char('G')) => Motion::LastLine,
+        Some(Key::Char('w')) => Motion::WordForward(false),
+        Some(Key::Char('W')) => Motion::WordForward(true),
+        Some(Key::Char('b')) => Motion::WordBackward(false),
+        Some(Key::Char('B')) => Motion::WordBackward(true),
+        Some(Key::Char('e')) => Motion::WordEnd(false),
+        Some(Key::Char('E')) => Motion::WordEnd(true),
+        Some(Key::Char('0')) => Motion::LineStart,
+        Some(Key::Char('^')) => Motion::FirstNonBlank,
+        Some(Key::Char('$')) => Motion::LineEnd,
+        Some(Key::Char('|')) => Motion::Column,
+        Some(Key::Char('H')) => Motion::ScreenTop,
+        Some(Key::Char('M')) => Motion::ScreenMiddle,
+        Some(Key::Char('L')) => Motion::ScreenBottom,
+        Some(Key::Char('+')) | Some(Key::Char('\n')) => Motion::NextLine,
+        Some(Key::Char('-')) => Motion::PrevLine,
+        Some(Key::Char(')')) => Motion::Sentence(true),
+        Some(Key::Char('(')) => Motion::Sentence(false),
+        Some(Key::Char('}')) => Motion::Paragraph(true),
+        Some(Key::Char('{')) => Motion::Paragraph(false),
+        Some(Key::Char(c)) if matches!(c, '[' | ']') => {
+            let forward = *c == ']';
+            return match keys.get(1) {
+                None => Parsed::Pending,
+                // The brace is '{' when the key is repeated, [[ and ]], and '}' otherwise
+                Some(Key::Char(d)) if matches!(d, '[' | ']') => {
+                    let brace = if c == d { '{' } else { '}' };
+                    Parsed::Done(Motion::Section { forward, brace }, 2)
+                }
+                Some(_) => Parsed::Invalid(2),
+            };
+        }
+        Some(Key::Char('/')) => Motion::SearchPrompt(true),
+        Some(Key::Char('?')) => Motion::SearchPrompt(false),
+        Some(Key::Char('n')) => Motion::SearchNext(false),
+        Some(Key::Char('N')) => Motion::SearchNext(true),
+        Some(Key::Char('*')) => Motion::SearchWord { forward: true, whole: true },
+        Some(Key::Char('#')) => Motion::SearchWord { forward: false, whole: true },
+        Some(Key::Char(';')) => Motion::RepeatFind(false),
+        Some(Key::Char(',')) => Motion::RepeatFind(true),
+        Some(Key::Char(c)) if matches!(c, 'f' | 'F' | 't' | 'T') => {
+            return match keys.get(1) {
+                None => Parsed::Pending,
+                Some(Key::Char(target)) => Parsed::Done(
+                    Motion::FindChar {
+                        target: *target,
+                        forward: c.is_lowercase(),
+                        till: c.eq_ignore_ascii_case(&'t'),
+                        repeat: false,
+                    },
+                    2,
+                ),
+                Some(_) => Parsed::Invalid(2),
+            };
+        }
+        Some(Key::Char('g')) => {
+            return match keys.get(1) {
+                None => Parsed::Pending,
+                Some(Key::Char('g')) => Parsed::Done(Motion::GotoLine(1), 2),
+                Some(Key::Char('e')) => Parsed::Done(Motion::WordEndBackward(false), 2),
+                Some(Key::Char('E')) => Parsed::Done(Motion::WordEndBackward(true), 2),
+                Some(Key::Char('_')) => Parsed::Done(Motion::LastNonBlank, 2),
+                Some(Key::Char('*')) => Parsed::Done(Motion::SearchWord { forward: true, whole: false }, 2),
+                Some(Key::Char('#')) => Parsed::Done(Motion::SearchWord { forward: false, whole: false }, 2),
+                Some(Key::Char('d')) => Parsed::Done(Motion::Declaration(false), 2),
+                Some(Key::Char('D')) => Parsed::Done(Motion::Declaration(true), 2),
+                Some(_) => Parsed::NoMatch,
+            };
+        }
+        Some(_) => return Parsed::NoMatch,
+    };
+    Parsed::Done(motion, 1)
+}
@@ -1,204 +1,2102 @@
-use crate::interface::CursorStyle;
+use crate::clipboard;
+use crate::interface::{CursorStyle, Highlight};
+use crate::motions::objects::TextObject;
+use crate::motions::{first_non_blank, Motion, MotionContext, MotionKind};
+use crate::numbers;
+use crate::options::Options;
 use crate::piece_table::PieceTable;
 use crate::position::Position;
+use crate::registers::{Register, RegisterKind, Registers};
+use crate::regex::Haystack;
+use crate::search::{Pattern, Search, SearchOffset};
+use crate::undo::{Edit, UndoDistance, UndoTree};
 use crate::views::View;
 
-#[derive(Debug, Copy, Clone)]
+/// The most text a count may repeat, in bytes, so a mistyped count fails instead of filling memory.
+pub const MAX_REPEAT_LENGTH: usize = 10_000_000;
+
+#[derive(Debug, Clone)]
 pub enum TextCommand {
     CursorUp(u16), // For cursor up, down, left, right u16 defines how many spaces to move in that
     CursorDown(u16), // direction
     CursorLeft(u16),
     CursorRight(u16),
-    JumpTop(u16),
-    JumpBottom(u16),
+    JumpTop(u16), // gg, go to the given line number
+    JumpBottom(u16), // G, go to the given line number or the last line if 0
     SetCursorStyle(CursorStyle),
     Insert(char),
     Delete,
+    Overwrite(char, bool), // R, type over the character under the cursor, with gR over the columns of a tab
+    Restore,               // Backspace in replace mode, put back what was typed over
+    ReplaceChars(char, usize), // r, replace count characters, r<CR> with a single line break
+    Undo(usize),
+    Redo(usize),
+    UndoLine,
+    UndoBreak, // Close the current undo step, everything since the last break is undone together
+    UndoJump(usize), // Go to the text state after the numbered change
+    Earlier(UndoDistance),
+    Later(UndoDistance),
+    UndoList,
+    Move(Motion, usize),
+    Search(String, bool), // The text typed after /, or ? when false, sets the search that n and N repeat
+    SearchPreview(Option<(String, bool)>), // Show where the search being typed goes, None puts the cursor back
+    NoHighlight, // :nohlsearch, stop highlighting the matches until the next search
+    ScrollPage(bool, usize), // PageDown, or PageUp when false, scroll by count screens
+    Operate(Operator, OperatorTarget, usize),
+    Visual(Option<MotionForce>), // Start visual mode or change its kind, or end it with None
+    VisualSwap(bool),            // o, go to the other end of the selection, or with O the other corner of a block
+    VisualRestore,               // gv, select the previous selection again
+    VisualSelect(TextObject, usize),
+    VisualInsert(bool), // I, or A when true, insert before or after the selection
+    StartInsert(InsertAt), // Put the cursor where insert mode starts
+    InsertEnd,          // Leaving insert mode, moves back onto the text and finishes a block I, A or c
+    DeleteBefore(Motion), // Ctrl-W and Ctrl-U, delete back to where the motion goes in the cursor line
+    DeleteForward,        // Del in insert mode
+    SwitchCase(usize),      // ~, switch the case of count characters and move past them
+    AddNumber { subtract: bool, count: usize }, // Ctrl-A, or Ctrl-X which subtracts, on the number at or after the cursor
+    ShiftLine(bool),      // Ctrl-T, or Ctrl-D when false, shift the cursor line in insert mode
+    InsertRegister(char), // Ctrl-R, type the text of a register
+    Goto(LineAddress), // An Ex command that is only a line number, as :12 or :'<
+    UseRegister(char), // "x, the register for the next command
+    Put { before: bool, follow: bool, count: usize }, // p and P, gp and gP follow the text with the cursor
+    ReadOnlyRegister(char, String), // Record the text for one of ". ": "% or "/
+    ListRegisters(String),          // :registers, of the named registers or all of them
+    Record(char, String),           // q, store the keys typed since q{reg} in the register
+    Play(char, usize),                // @x, run the keys in a register count times, @@ the last one run
+    Normal(Option<LineRange>, String), // :normal, run keys once or on each line of a range
+    PlaybackEnd(bool), // The keys of a macro or :normal line are used up, true when nothing else plays
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Operator {
+    Delete,
+    Change,
+    Yank,
+    ShiftRight,
+    ShiftLeft,
+    Indent,
+    ToggleCase,
+    Lowercase,
+    Uppercase,
+    Join(bool), // J, or gJ when false, which doesn't add or remove spaces
+    Rot13,      // g?
+    AddNumber { subtract: bool, progressive: bool }, // Ctrl-A or Ctrl-X on a selection, with g counting up line by line
+    Replace(char), // r in visual mode, replace every selected character
+}
+
+/// Where insert mode starts, from the command that entered it.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum InsertAt {
+    Cursor,        // i
+    After,         // a
+    FirstNonBlank, // I
+    LineEnd,       // A
+    LineBelow,     // o, a new line with the indent of the cursor line
+    LineAbove,     // O
+    LastInsert,    // gi, where insert mode was last left
+}
+
+/// Overrides the kind of a motion, as with `dvj` or `d<C-V>2j`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MotionForce {
+    Charwise,
+    Linewise,
+    Blockwise,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum OperatorTarget {
+    Motion(Motion, Option<MotionForce>),
+    Object(TextObject, Option<MotionForce>),
+    Lines,        // Doubled operator, e.g. dd, acts on count lines
+    Visual(bool), // The visual selection, or all of its lines when true as with D or Y
+    LastVisual(bool), // As much text from the cursor as the last visual operator took, for .
+    Range(LineRange),
+}
+
+/// A line number in an Ex range, resolved against the text when the command runs.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum LineSpec {
+    Current,       // .
+    Last,          // $
+    Number(usize), // Lines are numbered from 1
+    Mark(char),    // '< and '>, the first and last line of the last visual selection
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct LineAddress {
+    pub spec: LineSpec,
+    pub offset: isize, // From trailing +N and -N
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct LineRange {
+    pub start: LineAddress,
+    pub end: LineAddress,
+}
+
+/// The text an operator acts on.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Region {
+    Chars { start: usize, end: usize }, // start..end
+    Lines { first: usize, last: usize },
+    Block { first: usize, last: usize, left: usize, right: usize }, // Display columns left..=right
+}
+
+/// How much text a visual operator took, so that . takes as much again from the cursor.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum SelectionSize {
+    Chars(usize),            // Characters within a line
+    CharLines(usize, usize), // Lines, and the column the selection ends before in the last one
+    Lines(usize),
+    Block(usize, usize), // Lines, and display columns after the first, usize::MAX to the line ends
 }
 
-pub struct TextView {
-    offset: Position,
-    text: PieceTable,
-    cursor: Position,
-    sz: Position,
-    view: Vec<char>,
-    updates: Vec<bool>,
-    cursor_style: CursorStyle,
-}
+/// The matches of the last search in a line on screen, kept until the row changes.
+#[derive(Debug, Clone)]
+struct SearchRow {
+    line: usize,
+    length: usize,                // Length of the line when it was searched
+    matches: Vec<(usize, usize)>, // Columns of each match, start..end
+}
+
+/// Text typed after a block I, A or c, which is copied to the other lines of the block when
+/// insert mode ends.
+#[derive(Debug, Clone)]
+struct BlockInsert {
+    first: usize,
+    lines: Vec<usize>,     // The other lines to copy the text to
+    column: Option<usize>, // Display column to insert at, short lines are padded, None for the line ends
+    start: usize,          // Where the text is typed on the first line
+    length: usize,         // Length of the first line before typing
+    home: usize,           // Where the cursor goes afterwards, the top left of the block
+}
+
+pub struct TextView {
+    offset: Position,
+    text: PieceTable,
+    cursor: Position,
+    sz: Position,
+    view: Vec<char>,
+    updates: Vec<bool>,
+    cursor_style: CursorStyle,
+    history: UndoTree,
+    line_undo: Option<(usize, String)>, // Last changed line and its contents before the changes
+    message: Option<String>,
+    options: Options,
+    visual: Option<(usize, MotionForce)>, // Other end of the selection and its kind, set in visual mode
+    visual_eol: bool, // After $ a block selection extends to the end of every line, and others take in the newline
+    curswant: usize,     // Display column kept through up and down moves, usize::MAX after $
+    curswant_idx: usize, // Where the cursor was when curswant was set, moving elsewhere resets it
+    last_visual: Option<(usize, usize, MotionForce, bool)>, // Both ends of the last selection, for gv and '< '>
+    visual_size: Option<SelectionSize>, // Size of the selection the last visual operator took, for .
+    block_insert: Option<BlockInsert>,
+    highlights: Vec<Highlight>,
+    registers: Registers,
+    register: Option<char>, // Set by "x for the command that follows
+    overwritten: Vec<Option<char>>, // Characters typed over in replace mode, None where one was added
+    auto_indent: Option<usize>, // Line opened by o or O, its indent goes if nothing is typed after it
+    last_insert: Option<usize>, // Where insert mode was last left, for gi
+    last_search: Option<Search>,
+    search_origin: Option<(usize, Position, usize, usize)>, // Cursor, scroll offset and curswant before a search preview
+    search_rows: Vec<Option<SearchRow>>, // Matches on each row of the screen, for 'hlsearch'
+    no_highlight: bool, // :nohlsearch, until n, N or a new search
+    playback: Option<Vec<String>>, // Keys for the command view to run, from @x or :normal
+    last_played: Option<char>,     // Register of the last @x, for @@
+    playing: bool, // Keys of a macro are being run
+    aborted: bool, // A command failed while playing, the rest of the macro is skipped
+}
+
+impl TextView {
+    pub fn new(text: &str) -> Self {
+        Self {
+            cursor: Position { row: 0, col: 5 },
+            offset: Position { row: 0, col: 0 },
+            text: PieceTable::new(text),
+            sz: Position { row: 0, col: 0 },
+            view: Vec::<char>::new(),
+            updates: Vec::<bool>::new(),
+            cursor_style: CursorStyle::Block,
+            history: UndoTree::new(),
+            line_undo: None,
+            message: None,
+            options: Options::new(),
+            visual: None,
+            visual_eol: false,
+            curswant: 0,
+            curswant_idx: 0,
+            last_visual: None,
+            visual_size: None,
+            block_insert: None,
+            highlights: Vec::<Highlight>::new(),
+            registers: Registers::new(),
+            register: None,
+            overwritten: Vec::new(),
+            auto_indent: None,
+            last_insert: None,
+            last_search: None,
+            search_origin: None,
+            search_rows: Vec::new(),
+            no_highlight: false,
+            playback: None,
+            last_played: None,
+            playing: false,
+            aborted: false,
+        }
+    }
+
+    pub fn get_text(&self) -> String {
+        self.text.slice(0, self.text.len())
+    }
+
+    /// Record that the text has been written to disk in its current state.
+    pub fn mark_saved(&mut self) {
+        self.history.mark_saved();
+    }
+
+    pub fn is_modified(&self) -> bool {
+        self.history.is_modified()
+    }
+
+    pub fn undo_tree(&self) -> &UndoTree {
+        &self.history
+    }
+
+    /// Message for the command line produced by the last commands, if any.
+    pub fn take_message(&mut self) -> Option<String> {
+        self.message.take()
+    }
+
+    /// Keys to run from @x or :normal, one string per run. The command view has to take them
+    /// before it goes on with the keys after the command.
+    pub fn take_playback(&mut self) -> Option<Vec<String>> {
+        self.playback.take()
+    }
+
+    /// A command couldn't be carried out. Like an error in vim this stops any macro being run.
+    fn fail(&mut self) {
+        if self.playing {
+            self.aborted = true;
+        }
+    }
+
+    /// Text yanked or deleted into "+ or "* since the last call, to copy to the system clipboard.
+    pub fn take_clipboard(&mut self) -> Option<String> {
+        self.registers.take_copied()
+    }
+
+    pub fn set_options(&mut self, options: &Options) {
+        self.options = options.clone();
+        // n and N follow 'ignorecase' and 'iskeyword' as they are now
+        if let Some(search) = self.last_search.as_mut() {
+            if let Ok(pattern) = Pattern::new(search.pattern.source(), options) {
+                search.pattern = pattern;
+            }
+        }
+        self.search_rows.clear();
+        self.refresh_text();
+    }
+
+    fn cursor_index(&self) -> usize {
+        let line_offset = self.text.get_line_offset((self.offset.row + self.cursor.row) as usize).unwrap();
+        line_offset + (self.offset.col + self.cursor.col - 5) as usize
+    }
+
+    fn set_cursor_index(&mut self, idx: usize) {
+        let line = self.text.get_line_number(idx);
+        let col = idx - self.text.get_line_offset(line).unwrap();
+        self.set_cursor_position(line, col);
+    }
+
+    /// Move the cursor to a line and column of the text, scrolling the view if required.
+    fn set_cursor_position(&mut self, line: usize, col: usize) {
+        let line = line.min(self.text.lines() - 1);
+        let line_length = self.text.get_line_length(line).unwrap();
+        let col = match self.cursor_style {
+            CursorStyle::Bar | CursorStyle::Underline => col.min(line_length),
+            _ => col.min(line_length.saturating_sub(1)),
+        };
+
+        let rows = (self.sz.row as usize).max(1);
+        let cols = (self.sz.col.saturating_sub(5) as usize).max(1);
+        if line < self.offset.row as usize {
+            self.offset.row = line as u16;
+        } else if line >= self.offset.row as usize + rows {
+            self.offset.row = (line + 1 - rows) as u16;
+        }
+        if col < self.offset.col as usize {
+            self.offset.col = col as u16;
+        } else if col >= self.offset.col as usize + cols {
+            self.offset.col = (col + 1 - cols) as u16;
+        }
+
+        self.cursor.row = (line - self.offset.row as usize) as u16;
+        self.cursor.col = 5 + (col - self.offset.col as usize) as u16;
+        self.refresh_text();
+    }
+
+    /// Apply an edit to the text, recording it so that it can be undone.
+    fn apply_edit(&mut self, edit: Edit) {
+        if matches!(&edit, Edit::Insert { text, .. } | Edit::Delete { text, .. } if text.is_empty()) {
+            return;
+        }
+
+        let line = self.text.get_line_number(edit.start());
+        if self.line_undo.as_ref().is_none_or(|(ln, _)| *ln != line) {
+            let contents = self.text.get_line(line).unwrap_or_default();
+            self.line_undo = Some((line, contents.into_iter().collect()));
+        }
+
+        self.history.record(edit.clone(), self.cursor_index());
+        edit.apply(&mut self.text);
+
+        let text = match &edit {
+            Edit::Insert { text, .. } | Edit::Delete { text, .. } => text,
+        };
+        if text.contains('\n') {
+            self.line_undo = None;
+        }
+    }
+
+    fn undo_line(&mut self) {
+        if let Some((line, saved)) = self.line_undo.take() {
+            if let (Some(start), Some(current)) = (self.text.get_line_offset(line), self.text.get_line(line)) {
+                let current: String = current.into_iter().collect();
+                self.history.commit();
+                self.apply_edit(Edit::Delete { idx: start, text: current.clone() });
+                self.apply_edit(Edit::Insert { idx: start, text: saved });
+                self.history.commit();
+
+                self.line_undo = Some((line, current));
+                self.set_cursor_index(start);
+            }
+        }
+    }
+
+    fn cursor_line(&self) -> usize {
+        (self.offset.row + self.cursor.row) as usize
+    }
+
+    /// The index of the end of a line, where its newline is.
+    fn line_end_index(&self, line: usize) -> usize {
+        self.text.get_line_offset(line).unwrap() + self.text.get_line_length(line).unwrap()
+    }
+
+    fn motion_context(&self, operator: bool) -> MotionContext<'_> {
+        MotionContext {
+            text: &self.text,
+            keywords: &self.options.iskeyword,
+            top: self.offset.row as usize,
+            height: self.sz.row as usize,
+            operator,
+        }
+    }
+
+    fn move_cursor(&mut self, motion: Motion, count: usize) {
+        if self.visual.is_some() {
+            // Like vim's curswant, $ sticks through moves up and down
+            self.visual_eol = match motion {
+                Motion::LineEnd => true,
+                Motion::Up | Motion::Down => self.visual_eol,
+                _ => false,
+            };
+        }
+        let target = match motion {
+            Motion::SearchNext(reverse) => self.search_motion(reverse, count).map(|(idx, _)| idx),
+            Motion::SearchWord { forward, whole } => self.search_word(forward, whole, count).map(|(idx, _)| idx),
+            Motion::Declaration(file) => self.goto_declaration(file).map(|(idx, _)| idx),
+            _ => motion.apply(&self.motion_context(false), self.cursor_index(), count),
+        };
+        match target {
+            Some(idx) if matches!(motion, Motion::Up | Motion::Down) => {
+                // Go back to the wanted column after passing through shorter lines
+                let line = self.text.get_line_number(idx);
+                let col = match self.curswant {
+                    usize::MAX => self.text.get_line_length(line).unwrap(),
+                    column => self.display_to_col(line, column),
+                };
+                self.set_cursor_position(line, col);
+                self.curswant_idx = self.cursor_index();
+            }
+            // The insert cursor can go after the last character
+            Some(idx) if motion == Motion::LineEnd && self.cursor_style != CursorStyle::Block => {
+                let line = self.text.get_line_number(idx);
+                self.set_cursor_index(idx + (self.text.get_line_length(line).unwrap() > 0) as usize);
+            }
+            Some(idx) => self.set_cursor_index(idx),
+            None => self.fail(),
+        }
+        if motion == Motion::LineEnd {
+            self.curswant = usize::MAX;
+            self.curswant_idx = self.cursor_index();
+        }
+    }
+
+    /// Start keeping to the display column of the cursor if a command moved it anywhere other
+    /// than up and down moves took it.
+    fn update_curswant(&mut self) {
+        if self.text.get_line_offset((self.offset.row + self.cursor.row) as usize).is_none() {
+            return;
+        }
+        let idx = self.cursor_index();
+        if idx != self.curswant_idx {
+            self.curswant = self.display_span(idx).0;
+            self.curswant_idx = idx;
+        }
+    }
+
+    /// PageDown and PageUp, scroll by count screens less two lines of context. The cursor goes to
+    /// the first line shown going forward, and the last going back.
+    fn scroll_page(&mut self, forward: bool, count: usize) {
+        let rows = (self.sz.row as usize).max(1);
+        let last_line = self.text.lines() - 1;
+        let top = self.offset.row as usize;
+        let distance = rows.saturating_sub(2).max(1).saturating_mul(count.max(1));
+        let (new_top, line) = if forward {
+            let new_top = (top + distance).min(last_line);
+            (new_top, new_top)
+        } else {
+            let new_top = top.saturating_sub(distance);
+            (new_top, (new_top + rows - 1).min(last_line))
+        };
+        if new_top == top {
+            self.fail();
+            return;
+        }
+
+        self.offset.row = new_top as u16;
+        self.set_cursor_index(first_non_blank(&self.text, line));
+    }
+
+    /// First and last display column of the character at idx, the end of a line is one column.
+    fn display_span(&self, idx: usize) -> (usize, usize) {
+        let line = self.text.get_line_number(idx);
+        let col = idx - self.text.get_line_offset(line).unwrap();
+        let chars = self.text.get_line(line).unwrap();
+        let start = display_width(&chars[..col.min(chars.len())], self.options.tabstop);
+        let width = chars.get(col).map_or(1, |c| char_width(*c, start, self.options.tabstop));
+        (start, start + width - 1)
+    }
+
+    /// Column of the character of a line covering a display column, or the line length if the
+    /// line is shorter.
+    fn display_to_col(&self, line: usize, column: usize) -> usize {
+        let chars = self.text.get_line(line).unwrap();
+        let mut vcol = 0;
+        for (col, c) in chars.iter().enumerate() {
+            vcol += char_width(*c, vcol, self.options.tabstop);
+            if vcol > column {
+                return col;
+            }
+        }
+        chars.len()
+    }
+
+    /// The characters of a line in the display columns left..=right as (start, end, before,
+    /// after). A tab or wide character on either edge is only partly inside, `before` and `after`
+    /// count its columns outside the block. None if the line ends before left.
+    fn block_span(&self, line: usize, left: usize, right: usize) -> Option<(usize, usize, usize, usize)> {
+        let chars = self.text.get_line(line).unwrap();
+        let mut span: Option<(usize, usize, usize, usize)> = None;
+        let mut vcol = 0;
+        for (col, c) in chars.iter().enumerate() {
+            let width = char_width(*c, vcol, self.options.tabstop);
+            if vcol > right {
+                break;
+            }
+            if vcol + width > left {
+                let after = (vcol + width).saturating_sub(right.saturating_add(1));
+                span = match span {
+                    None => Some((col, col + 1, left.saturating_sub(vcol), after)),
+                    Some((start, _, before, _)) => Some((start, col + 1, before, after)),
+                };
+            }
+            vcol += width;
+        }
+        span
+    }
+
+    /// Index of the display column of a line, splitting a tab that covers it into spaces.
+    fn split_at_display(&mut self, line: usize, column: usize) -> usize {
+        let start = self.text.get_line_offset(line).unwrap();
+        let col = self.display_to_col(line, column);
+        let (vcol, _) = self.display_span(start + col);
+        if vcol < column && start + col < self.text.len() && self.text.index(start + col) == '\t' {
+            let width = char_width('\t', vcol, self.options.tabstop);
+            self.apply_edit(Edit::Delete { idx: start + col, text: "\t".to_string() });
+            self.apply_edit(Edit::Insert { idx: start + col, text: " ".repeat(width) });
+            return start + col + column - vcol;
+        }
+        start + col
+    }
+
+    /// Start typing into a block, at a display column of every line or at the line ends. The
+    /// first line gets the text as it is typed, the others when insert mode ends.
+    fn start_block_insert(&mut self, first: usize, lines: Vec<usize>, column: Option<usize>, home: Option<usize>) {
+        let start = self.block_insert_index(first, column);
+        let home = home.unwrap_or(start);
+        self.block_insert = Some(BlockInsert {
+            first,
+            lines,
+            column,
+            start,
+            length: self.text.get_line_length(first).unwrap(),
+            home,
+        });
+        self.set_cursor_index(start);
+    }
+
+    /// Copy the text typed into the first line of a block to the rest of its lines. Nothing is
+    /// copied if the text went to another line.
+    fn finish_block_insert(&mut self) {
+        let Some(insert) = self.block_insert.take() else {
+            return;
+        };
+        let length = self.text.get_line_length(insert.first).unwrap();
+        if length <= insert.length {
+            return;
+        }
+        let typed = self.text.slice(insert.start, insert.start + length - insert.length);
+        if typed.contains('\n') || self.cursor_line() != insert.first {
+            return;
+        }
+
+        for line in insert.lines {
+            if line < self.text.lines() {
+                let idx = self.block_insert_index(line, insert.column);
+                self.apply_edit(Edit::Insert { idx, text: typed.clone() });
+            }
+        }
+        self.set_cursor_index(insert.home.min(self.text.len()));
+    }
+
+    /// Where text goes on a line of a block insert, after padding the line with spaces to the
+    /// column if it is short.
+    fn block_insert_index(&mut self, line: usize, column: Option<usize>) -> usize {
+        let line_end = self.text.get_line_offset(line).unwrap() + self.text.get_line_length(line).unwrap();
+        let Some(column) = column else {
+            return line_end;
+        };
+        let width = display_width(&self.text.get_line(line).unwrap(), self.options.tabstop);
+        if width < column {
+            self.apply_edit(Edit::Insert { idx: line_end, text: " ".repeat(column - width) });
+            return line_end + column - width;
+        }
+        self.split_at_display(line, column)
+    }
+
+    /// Lines after the first of a block that reach its left column, which I and c copy text to.
+    fn block_lines(&self, first: usize, last: usize, left: usize) -> Vec<usize> {
+        (first + 1..=last).filter(|line| self.block_span(*line, left, usize::MAX).is_some()).collect()
+    }
+
+    /// The text covered by moving from the cursor with a motion, None if the motion fails.
+    fn motion_region(&mut self, motion: Motion, force: Option<MotionForce>, count: usize) -> Option<Region> {
+        let (target, kind) = match motion {
+            Motion::SearchNext(reverse) => self.search_motion(reverse, count)?,
+            Motion::SearchWord { forward, whole } => self.search_word(forward, whole, count)?,
+            Motion::Declaration(file) => self.goto_declaration(file)?,
+            _ => (motion.apply(&self.motion_context(true), self.cursor_index(), count)?, motion.kind()),
+        };
+        // * and # put the cursor at the start of the word first
+        let cursor = self.cursor_index();
+        self.region(cursor.min(target), cursor.max(target), kind, force)
+    }
+
+    /// Set the search that n and N repeat from what was typed after / or ?. An empty pattern
+    /// searches for the last one again, with the new offset.
+    fn set_search(&mut self, typed: &str, forward: bool) {
+        let (pattern, offset) = match Search::parse(typed, forward, &self.options) {
+            Ok(parsed) => parsed,
+            Err(e) => {
+                self.message = Some(e);
+                self.fail();
+                return;
+            }
+        };
+        let Some(pattern) = pattern.or_else(|| self.last_search.as_ref().map(|s| s.pattern.clone())) else {
+            self.message = Some("E35: No previous regular expression".to_string());
+            self.fail();
+            return;
+        };
+        self.use_search(Search { pattern, forward, offset });
+    }
+
+    /// Make a search the one n and N repeat and 'hlsearch' shows.
+    fn use_search(&mut self, search: Search) {
+        self.registers.set_read_only('/', search.pattern.source());
+        self.last_search = Some(search);
+        self.no_highlight = false;
+        self.search_rows.clear();
+        self.refresh_text();
+    }
+
+    /// The keyword under the cursor or after it in the line as the start and end of it, or when
+    /// `keyword_only` is false and there is none, the non-blank text there.
+    fn word_at_cursor(&self, keyword_only: bool) -> Option<(usize, usize)> {
+        let line = self.cursor_line();
+        let start = self.text.get_line_offset(line).unwrap();
+        let chars = self.text.get_line(line).unwrap();
+        let col = self.cursor_index() - start;
+        let keywords = &self.options.iskeyword;
+        let find = |wanted: &dyn Fn(char) -> bool| {
+            let mut first = (col..chars.len()).find(|i| wanted(chars[*i]))?;
+            if first == col {
+                first -= chars[..col].iter().rev().take_while(|c| wanted(**c)).count();
+            }
+            let end = (first..chars.len()).find(|i| !wanted(chars[*i])).unwrap_or(chars.len());
+            Some((start + first, start + end))
+        };
+        find(&|c| c != '\n' && keywords.contains(c)).or_else(|| match keyword_only {
+            true => None,
+            false => find(&|c| !c.is_whitespace()),
+        })
+    }
+
+    /// A pattern for the text from start to end as it is, matching a whole keyword when `whole`
+    /// is set. Like vim it ignores 'smartcase', only 'ignorecase' applies.
+    fn literal_pattern(&self, start: usize, end: usize, whole: bool) -> Pattern {
+        let mut source: String = self.text.slice(start, end).chars().flat_map(|c| {
+            let escape = matches!(c, '\\' | '.' | '*' | '[' | '~' | '^' | '$');
+            escape.then_some('\\').into_iter().chain(std::iter::once(c))
+        }).collect();
+        if whole {
+            source = format!("\\<{source}\\>");
+        }
+        // Every special character is escaped, so the pattern is valid
+        let options = Options { smartcase: false, ..self.options.clone() };
+        Pattern::new(&source, &options).unwrap()
+    }
+
+    /// `*` and `#`, or `g*` and `g#` when not `whole`: search for the keyword under the cursor or
+    /// after it like n would. The cursor goes to the start of the word first, so # doesn't stop
+    /// on the word itself.
+    fn search_word(&mut self, forward: bool, whole: bool, count: usize) -> Option<(usize, MotionKind)> {
+        let Some((start, end)) = self.word_at_cursor(false) else {
+            self.message = Some("E348: No string under cursor".to_string());
+            self.fail();
+            return None;
+        };
+        // A \< or \> only goes next to keyword characters
+        let keyword = self.options.iskeyword.contains(self.text.index(start));
+        let pattern = self.literal_pattern(start, end, whole && keyword);
+        self.use_search(Search { pattern, forward, offset: SearchOffset::Start(0) });
+        self.set_cursor_index(start);
+        self.search_motion(false, count)
+    }
+
+    /// gd, or gD when `file`: go to the first use of the keyword under the cursor in the current
+    /// function, or in the whole file. As in vim, the function starts at the blank line above
+    /// the '{' in the first column that [[ goes back to. The keyword becomes the last search.
+    fn goto_declaration(&mut self, file: bool) -> Option<(usize, MotionKind)> {
+        let Some((start, end)) = self.word_at_cursor(true) else {
+            self.message = Some("E349: No identifier under cursor".to_string());
+            self.fail();
+            return None;
+        };
+        let pattern = self.literal_pattern(start, end, true);
+
+        let mut line = 0;
+        if !file {
+            let section = Motion::Section { forward: false, brace: '{' };
+            line = section.apply(&self.motion_context(false), self.cursor_index(), 1).map_or(0, |idx| self.text.get_line_number(idx));
+            while line > 0 && self.text.get_line(line).is_some_and(|chars| chars.iter().any(|c| !c.is_whitespace())) {
+                line -= 1;
+            }
+        }
+        let from = self.text.get_line_offset(line).unwrap();
+        let haystack = Haystack::new(&self.text);
+        let found = pattern.matches(&haystack, from, self.text.len()).next().map(|(s, _)| s);
+        self.use_search(Search { pattern, forward: true, offset: SearchOffset::Start(0) });
+        found.map(|idx| (idx, MotionKind::Exclusive))
+    }
+
+    /// n, or N when `reverse`, as a motion: where the count-th match of the last search puts the
+    /// cursor and how an operator takes the text up to it. Going past either end of the text
+    /// wraps around, with a message saying so. The message ends with which match it is of how
+    /// many, as "[3/17]".
+    fn search_motion(&mut self, reverse: bool, count: usize) -> Option<(usize, MotionKind)> {
+        match self.search_target(reverse, count) {
+            Ok((idx, kind, wrapped, found)) => {
+                let search = self.last_search.as_ref().unwrap();
+                let forward = search.forward != reverse;
+                let message = match (wrapped, forward) {
+                    (true, true) => "search hit BOTTOM, continuing at TOP".to_string(),
+                    (true, false) => "search hit TOP, continuing at BOTTOM".to_string(),
+                    (false, _) => format!("{}{}", if forward { '/' } else { '?' }, search.pattern.source()),
+                };
+                self.message = Some(format!("{message} {}", self.match_count(&search.pattern, found)));
+                if self.no_highlight {
+                    self.no_highlight = false;
+                    self.refresh_text();
+                }
+                Some((idx, kind))
+            }
+            Err(e) => {
+                self.message = Some(e);
+                self.fail();
+                None
+            }
+        }
+    }
+
+    /// Where n or N goes, how an operator takes the text up to it, whether the search wrapped
+    /// around and the start of the match.
+    fn search_target(&self, reverse: bool, count: usize) -> Result<(usize, MotionKind, bool, usize), String> {
+        let search = self.last_search.as_ref().ok_or("E35: No previous regular expression")?;
+        let forward = search.forward != reverse;
+        let last_line = self.text.lines() as isize - 1;
+        let end_anchor = matches!(search.offset, SearchOffset::End(_));
+
+        // With a line offset the search goes on from the line of the match the cursor came from,
+        // otherwise from the cursor itself
+        let cursor = self.cursor_index();
+        let (mut line, mut after) = match search.offset {
+            SearchOffset::Lines(n) => ((self.cursor_line() as isize - n).clamp(0, last_line) as usize, None),
+            _ => (self.cursor_line(), Some(cursor)),
+        };
+        let mut wrapped = false;
+        let mut found = (cursor, cursor);
+        let mut first = None;
+        let mut remaining = count.max(1);
+        let mut taken = 0;
+        while remaining > 0 {
+            let (start, end, wrap) = self
+                .next_match(&search.pattern, end_anchor, forward, line, after)
+                .ok_or_else(|| format!("E486: Pattern not found: {}", search.pattern.source()))?;
+            wrapped |= wrap;
+            line = self.text.get_line_number(start);
+            after = after.map(|_| if end_anchor { end.saturating_sub(1).max(start) } else { start });
+            found = (start, end);
+            remaining -= 1;
+            taken += 1;
+            // Coming back to the first match the search goes round the same matches again, so a
+            // large count only needs what is left over from whole rounds
+            match first {
+                None => first = Some((found, line, after)),
+                Some(state) if state == (found, line, after) => remaining %= taken - 1,
+                Some(_) => {}
+            }
+        }
+
+        let (start, end) = found;
+        let last = self.text.len().saturating_sub(1) as isize;
+        Ok(match search.offset {
+            SearchOffset::Lines(n) => {
+                let line = (line as isize + n).clamp(0, last_line) as usize;
+                (self.text.get_line_offset(line).unwrap(), MotionKind::Linewise, wrapped, start)
+            }
+            SearchOffset::Start(n) => ((start as isize + n).clamp(0, last) as usize, MotionKind::Exclusive, wrapped, start),
+            SearchOffset::End(n) => {
+                let idx = end.saturating_sub(1).max(start) as isize + n;
+                (idx.clamp(0, last) as usize, MotionKind::Inclusive, wrapped, start)
+            }
+        })
+    }
+
+    /// The first match from a line on going forward or back, as its start, its end and whether
+    /// the search went past an end of the text. In the line it starts from a match must be after
+    /// the index `after`, or before it going back, which is its start or with `end_anchor` its last
+    /// character. Without `after` the search starts in the next line.
+    fn next_match(&self, pattern: &Pattern, end_anchor: bool, forward: bool, line: usize, after: Option<usize>) -> Option<(usize, usize, bool)> {
+        let lines = self.text.lines();
+        let haystack = Haystack::new(&self.text);
+        for step in 0..=lines {
+            let (l, wrapped) = if forward {
+                ((line + step) % lines, line + step >= lines)
+            } else {
+                ((line + lines - step) % lines, step > line)
+            };
+            let line_start = self.text.get_line_offset(l).unwrap();
+            let line_end = line_start + self.text.get_line_length(l).unwrap();
+            let mut matches = pattern.find_all(&haystack, line_start, line_end);
+            if !forward {
+                matches.reverse();
+            }
+            let found = matches.into_iter().find(|&(s, e)| {
+                let anchor = if end_anchor { e.saturating_sub(1).max(s) } else { s };
+                match after {
+                    _ if step > 0 => true,
+                    Some(idx) => (anchor > idx && forward) || (anchor < idx && !forward),
+                    None => false,
+                }
+            });
+            if let Some((s, e)) = found {
+                return Some((s, e, wrapped));
+            }
+        }
+        None
+    }
+
+    /// Which match of a pattern starts at `found` and how many there are, as "[3/17]". Like vim it
+    /// stops counting after 99, so n stays quick in a large file.
+    fn match_count(&self, pattern: &Pattern, found: usize) -> String {
+        const MAX: usize = 99;
+        let haystack = Haystack::new(&self.text);
+        let mut matches = pattern.matches(&haystack, 0, self.text.len()).take(MAX + 1);
+        let index = matches.by_ref().position(|(start, _)| start >= found).map(|i| i + 1);
+        let total = index.map_or(MAX + 1, |i| i + matches.count());
+        let index = index.unwrap_or(MAX + 1);
+        let show = |n: usize| if n > MAX { format!(">{MAX}") } else { n.to_string() };
+        format!("[{}/{}]", show(index), show(total))
+    }
+
+    /// Incsearch, move the cursor to where the search typed so far goes. It goes back to where it
+    /// was before the preview when the search is done or abandoned.
+    fn preview_search(&mut self, typed: Option<(String, bool)>) {
+        let (idx, offset, curswant, curswant_idx) =
+            *self.search_origin.get_or_insert((self.cursor_index(), self.offset, self.curswant, self.curswant_idx));
+        self.offset = offset;
+        self.set_cursor_index(idx);
+        self.curswant = curswant;
+        self.curswant_idx = curswant_idx;
+
+        let Some((typed, forward)) = typed else {
+            self.search_origin = None;
+            return;
+        };
+        if let Ok((Some(pattern), _)) = Search::parse(&typed, forward, &self.options) {
+            if let Some((start, _, _)) = self.next_match(&pattern, false, forward, self.cursor_line(), Some(idx)) {
+                self.set_cursor_index(start);
+                self.curswant_idx = self.cursor_index();
+            }
+        }
+    }
+
+    /// The text selected by a text object, None if there is no object at the cursor. Unlike a
+    /// motion the selection can be empty, as with ci( on "()", so the cursor still moves into it.
+    fn object_region(&self, object: TextObject, force: Option<MotionForce>, count: usize) -> Option<Region> {
+        let (start, end, kind) = object.select(&self.motion_context(true), self.cursor_index(), count)?;
+        if start == end && kind == MotionKind::Exclusive && force.is_none() {
+            return Some(Region::Chars { start, end });
+        }
+        self.region(start, end, kind, force)
+    }
+
+    /// The region between start and end for a kind of motion, which force can override.
+    fn region(&self, start: usize, end: usize, kind: MotionKind, force: Option<MotionForce>) -> Option<Region> {
+        let first = self.text.get_line_number(start);
+        let last = self.text.get_line_number(end);
+
+        let kind = match force {
+            None => kind,
+            Some(MotionForce::Linewise) => MotionKind::Linewise,
+            Some(MotionForce::Charwise) => match kind {
+                MotionKind::Exclusive => MotionKind::Inclusive,
+                _ => MotionKind::Exclusive,
+            },
+            Some(MotionForce::Blockwise) => {
+                let (start_left, start_right) = self.display_span(start);
+                let (end_left, end_right) = self.display_span(end);
+                return Some(Region::Block {
+                    first,
+                    last,
+                    left: start_left.min(end_left),
+                    right: start_right.max(end_right),
+                });
+            }
+        };
+
+        match kind {
+            MotionKind::Linewise => Some(Region::Lines { first, last }),
+            MotionKind::Inclusive => {
+                // The end of a line is never included, so d$ on an empty line does nothing
+                if end == self.text.len() || self.text.index(end) == '\n' {
+                    return (start < end).then_some(Region::Chars { start, end });
+                }
+                Some(Region::Chars { start, end: end + 1 })
+            }
+            MotionKind::Exclusive => {
+                if start == end {
+                    return None;
+                }
+
+                // An exclusive motion ending in column 0 stops at the end of the previous line,
+                // and covers whole lines if it also started before the first non-blank
+                if last > first && end == self.text.get_line_offset(last).unwrap() {
+                    if start <= first_non_blank(&self.text, first) {
+                        return Some(Region::Lines { first, last: last - 1 });
+                    }
+                    return Some(Region::Chars { start, end: end - 1 });
+                }
+                Some(Region::Chars { start, end })
+            }
+        }
+    }
+
+    /// The selected text in visual mode. A characterwise selection includes both ends.
+    fn selection(&self) -> Option<Region> {
+        let (anchor, kind) = self.visual?;
+        let cursor = match kind {
+            MotionForce::Charwise if self.visual_eol => self.line_end_index(self.cursor_line()),
+            _ => self.cursor_index(),
+        };
+        let (start, end) = (anchor.min(cursor), anchor.max(cursor));
+        match kind {
+            MotionForce::Charwise => Some(Region::Chars { start, end: (end + 1).min(self.text.len()) }),
+            MotionForce::Linewise => Some(Region::Lines {
+                first: self.text.get_line_number(start),
+                last: self.text.get_line_number(end),
+            }),
+            MotionForce::Blockwise => {
+                let (anchor_left, anchor_right) = self.display_span(anchor);
+                let (cursor_left, cursor_right) = self.display_span(cursor);
+                Some(Region::Block {
+                    first: self.text.get_line_number(start),
+                    last: self.text.get_line_number(end),
+                    left: anchor_left.min(cursor_left),
+                    right: if self.visual_eol { usize::MAX } else { anchor_right.max(cursor_right) },
+                })
+            }
+        }
+    }
+
+    /// Start insert mode before or after the selection. On a block the text goes on every line.
+    fn visual_insert(&mut self, append: bool) {
+        let Some(region) = self.selection() else {
+            return;
+        };
+        let eol = self.visual_eol;
+        self.process_command(TextCommand::Visual(None));
+
+        match region {
+            Region::Block { first, last, left, right } => {
+                // After A the cursor goes back to the top left of the block, after I it stays put
+                let home = append.then(|| self.text.get_line_offset(first).unwrap() + self.display_to_col(first, left));
+                let (lines, column) = match append {
+                    false => (self.block_lines(first, last, left), Some(left)),
+                    true if eol => ((first + 1..=last).collect(), None),
+                    true => ((first + 1..=last).collect(), Some(right + 1)),
+                };
+                self.start_block_insert(first, lines, column, home);
+            }
+            Region::Chars { start, end } => self.set_cursor_index(if append { end } else { start }),
+            Region::Lines { first, last } => {
+                if append {
+                    let end = self.text.get_line_offset(last).unwrap() + self.text.get_line_length(last).unwrap();
+                    self.set_cursor_index(end);
+                } else {
+                    self.set_cursor_index(self.text.get_line_offset(first).unwrap());
+                }
+            }
+        }
+    }
+
+    /// Select a text object, or extend the selection with it if more than one character is
+    /// selected.
+    fn select_object(&mut self, object: TextObject, count: usize) {
+        let Some((anchor, kind)) = self.visual else {
+            return;
+        };
+        let cursor = self.cursor_index();
+        let Some((start, end, object_kind)) = object.select(&self.motion_context(false), cursor, count) else {
+            return;
+        };
+
+        let end = match object_kind {
+            MotionKind::Exclusive => end.saturating_sub(1).max(start),
+            _ => end,
+        };
+        let (anchor, end) = if anchor == cursor { (start, end) } else { (anchor.min(start), end.max(cursor)) };
+        self.visual = Some((anchor, kind));
+        self.set_cursor_index(end.min(self.text.len().saturating_sub(1)));
+    }
+
+    /// First and last line of an Ex range, counted from 0.
+    fn resolve_range(&self, range: LineRange) -> Result<(usize, usize), String> {
+        let first = self.resolve_address(range.start)?;
+        let last = self.resolve_address(range.end)?;
+        let lines = self.text.lines() as isize;
+        if first < 0 || last < 0 || first >= lines || last >= lines {
+            return Err("E16: Invalid range".to_string());
+        }
+        Ok((first.min(last) as usize, first.max(last) as usize))
+    }
+
+    fn resolve_address(&self, address: LineAddress) -> Result<isize, String> {
+        let line = match address.spec {
+            LineSpec::Current => self.cursor_line(),
+            LineSpec::Last => self.text.lines() - 1,
+            LineSpec::Number(n) => n.max(1) - 1,
+            LineSpec::Mark(mark) => {
+                let (anchor, cursor, _, _) = self.last_visual.ok_or("E20: Mark not set")?;
+                let idx = if mark == '<' { anchor.min(cursor) } else { anchor.max(cursor) };
+                self.text.get_line_number(idx.min(self.text.len()))
+            }
+        };
+        Ok(line as isize + address.offset)
+    }
+
+    /// Join the lines of a region, or a line and the next one. With spaces the indent of each
+    /// joined line is replaced by a single space, or none before a ')' or after white space.
+    fn join_lines(&mut self, region: Region, spaces: bool) {
+        let (first, last) = self.region_lines(region);
+        let last = last.max(first + 1);
+        if last >= self.text.lines() {
+            self.fail();
+            return;
+        }
+
+        for _ in first..last {
+            let line = self.text.get_line(first).unwrap();
+            let next = self.text.get_line(first + 1).unwrap();
+            let join = self.text.get_line_offset(first).unwrap() + line.len();
+
+            let (removed, space) = if spaces {
+                let indent = next.iter().take_while(|c| **c == ' ' || **c == '\t').count();
+                let space = indent < next.len()
+                    && next[indent] != ')'
+                    && !line.is_empty()
+                    && !matches!(line.last(), Some(' ') | Some('\t'));
+                (1 + indent, space)
+            } else {
+                (1, false)
+            };
+
+            // The cursor goes to the first line before the edits, as the line it is on may go away
+            self.set_cursor_index(join);
+            self.apply_edit(Edit::Delete { idx: join, text: self.text.slice(join, join + removed) });
+            if space {
+                self.apply_edit(Edit::Insert { idx: join, text: " ".to_string() });
+            }
+            self.set_cursor_index(join);
+        }
+    }
+
+    fn cursor_on_blank(&self) -> bool {
+        let idx = self.cursor_index();
+        idx >= self.text.len() || self.text.index(idx).is_whitespace()
+    }
+
+    /// The size of a selection, to take as much again with . after a visual operator.
+    fn selection_size(&self, region: Region) -> SelectionSize {
+        match region {
+            Region::Chars { start, end } => {
+                let (first, last) = self.region_lines(region);
+                match last - first {
+                    0 => SelectionSize::Chars(end - start),
+                    n => SelectionSize::CharLines(n + 1, end - self.text.get_line_offset(last).unwrap()),
+                }
+            }
+            Region::Lines { first, last } => SelectionSize::Lines(last - first + 1),
+            Region::Block { first, last, left, right } => {
+                SelectionSize::Block(last - first + 1, if right == usize::MAX { right } else { right - left })
+            }
+        }
+    }
+
+    /// A region of a size from the cursor, as far as the text goes. Like vim, a selection of
+    /// several lines ends at the same column again.
+    fn region_of_size(&self, size: SelectionSize) -> Region {
+        let first = self.cursor_line();
+        let cursor = self.cursor_index();
+        let last_line = |lines: usize| (first + lines.max(1) - 1).min(self.text.lines() - 1);
+        match size {
+            SelectionSize::Chars(n) => {
+                let end = (cursor + n).min(self.line_end_index(first) + 1);
+                Region::Chars { start: cursor, end: end.min(self.text.len()) }
+            }
+            SelectionSize::CharLines(lines, col) => {
+                let last = last_line(lines);
+                let end = (self.text.get_line_offset(last).unwrap() + col).min(self.line_end_index(last) + 1);
+                Region::Chars { start: cursor, end: end.min(self.text.len()) }
+            }
+            SelectionSize::Lines(lines) => Region::Lines { first, last: last_line(lines) },
+            SelectionSize::Block(lines, width) => {
+                let (left, _) = self.display_span(cursor);
+                Region::Block { first, last: last_line(lines), left, right: left.saturating_add(width) }
+            }
+        }
+    }
+
+    fn region_lines(&self, region: Region) -> (usize, usize) {
+        match region {
+            Region::Chars { start, end } => (
+                self.text.get_line_number(start),
+                self.text.get_line_number(end.saturating_sub(1).max(start)),
+            ),
+            Region::Lines { first, last } | Region::Block { first, last, .. } => (first, last),
+        }
+    }
+
+    /// Spans of text covered by a region, blocks have one span per line.
+    fn region_spans(&self, region: Region) -> Vec<(usize, usize)> {
+        match region {
+            Region::Chars { start, end } => vec![(start, end)],
+            Region::Lines { first, last } => {
+                let start = self.text.get_line_offset(first).unwrap();
+                let end = self.text.get_line_offset(last).unwrap() + self.text.get_line_length(last).unwrap();
+                vec![(start, end)]
+            }
+            Region::Block { first, last, left, right } => (first..=last)
+                .filter_map(|line| {
+                    let line_start = self.text.get_line_offset(line).unwrap();
+                    let (start, end, _, _) = self.block_span(line, left, right)?;
+                    Some((line_start + start, line_start + end))
+                })
+                .collect(),
+        }
+    }
+
+    fn operate(&mut self, op: Operator, target: OperatorTarget, count: usize, register: Option<char>) {
+        let region = match target {
+            OperatorTarget::Lines => {
+                let first = self.cursor_line();
+                let last = first.saturating_add(count.max(1) - 1).min(self.text.lines() - 1);
+                Some(Region::Lines { first, last })
+            }
+            OperatorTarget::Motion(Motion::WordForward(big), force)
+                if op == Operator::Change && !self.cursor_on_blank() =>
+            {
+                // cw on a word is like ce, but leaves a single character word alone
+                self.motion_region(Motion::ChangeWord(big), force, count)
+            }
+            OperatorTarget::Motion(motion, force) => self.motion_region(motion, force, count),
+            OperatorTarget::Object(object, force) => self.object_region(object, force, count),
+            OperatorTarget::Visual(lines) | OperatorTarget::LastVisual(lines) => {
+                let region = if let OperatorTarget::Visual(_) = target {
+                    let region = self.selection();
+                    // A yank isn't repeated, so . keeps the size of the last change
+                    if op != Operator::Yank {
+                        self.visual_size = region.map(|region| self.selection_size(region)).or(self.visual_size);
+                    }
+                    self.process_command(TextCommand::Visual(None));
+                    region
+                } else {
+                    self.visual_size.map(|size| self.region_of_size(size))
+                };
+                region.map(|region| match region {
+                    Region::Chars { .. } | Region::Block { .. } if lines => {
+                        let (first, last) = self.region_lines(region);
+                        Region::Lines { first, last }
+                    }
+                    _ => region,
+                })
+            }
+            OperatorTarget::Range(range) => match self.resolve_range(range) {
+                Ok((first, last)) => Some(Region::Lines { first, last }),
+                Err(e) => {
+                    self.message = Some(e);
+                    None
+                }
+            },
+        };
+
+        // Counts on a selection or range repeat a shift instead of selecting lines
+        let times = match target {
+            OperatorTarget::Visual(_) | OperatorTarget::LastVisual(_) | OperatorTarget::Range(_) => count.max(1),
+            _ => 1,
+        };
+
+        if let Some(region) = region {
+            if matches!(op, Operator::Delete | Operator::Change | Operator::Yank) {
+                // Deletes with these motions always go to "1, even within a line
+                let use_reg_one = matches!(
+                    target,
+                    OperatorTarget::Motion(Motion::Sentence(_) | Motion::Paragraph(_), _)
+                );
+                let text = self.region_register(region);
+                let stored = match op {
+                    Operator::Yank => self.registers.yank(register, text),
+                    _ => self.registers.delete(register, text, use_reg_one),
+                };
+                if let Err(e) = stored {
+                    self.message = Some(e);
+                    self.fail();
+                    return;
+                }
+            }
+
+            match op {
+                Operator::Delete => self.delete_region(region),
+                Operator::Change => self.change_region(region),
+                Operator::Yank => self.yank_region(region),
+                Operator::ShiftRight => self.shift_lines(region, true, times),
+                Operator::ShiftLeft => self.shift_lines(region, false, times),
+                Operator::Join(spaces) => self.join_lines(region, spaces),
+                Operator::Replace(c) => self.replace_region(region, c),
+                Operator::Indent => self.indent_lines(region),
+                Operator::ToggleCase | Operator::Lowercase | Operator::Uppercase | Operator::Rot13 => self.change_case(region, op),
+                Operator::AddNumber { subtract, progressive } => {
+                    let step = i64::try_from(times).unwrap_or(i64::MAX);
+                    let step = if subtract { -step } else { step };
+                    self.add_to_numbers(region, step, progressive);
+                }
+            }
+        } else {
+            self.fail();
+        }
+    }
+
+    /// Hand the keys of a register to the command view. @: runs the last command line again.
+    /// Nothing is run after a failure, which stops recursive macros.
+    fn play(&mut self, name: char, count: usize) {
+        self.playing = true;
+        let name = match name {
+            '@' => self.last_played,
+            name => Some(name),
+        };
+        let keys = match name {
+            None => {
+                self.message = Some("E748: No previously used register".to_string());
+                None
+            }
+            Some(':') => self.registers.get(':').map(|reg| format!(":{}\n", reg.text())),
+            Some(name) => self.registers.get(name).map(|reg| reg.text()),
+        };
+        self.last_played = name.or(self.last_played);
+
+        match keys {
+            Some(keys) if keys.len().saturating_mul(count) > MAX_REPEAT_LENGTH => {
+                self.message = Some("E1240: Resulting text too long".to_string());
+                self.fail();
+                self.playback = Some(vec![String::new()]);
+            }
+            Some(keys) if !self.aborted => self.playback = Some(vec![keys.repeat(count.max(1))]),
+            _ => {
+                self.fail();
+                self.playback = Some(vec![String::new()]);
+            }
+        }
+    }
+
+    /// Keys for :normal. On a range they run once per line, from the start of the line, and each
+    /// run is ended with Esc in case its keys leave a command unfinished.
+    fn normal(&mut self, range: Option<LineRange>, keys: &str) {
+        self.playing = true;
+        let runs = match range.map(|range| self.resolve_range(range)) {
+            None => vec![format!("{keys}\x1b")],
+            Some(Ok((first, last))) => (first..=last).map(|line| format!("{}G0{keys}\x1b", line + 1)).collect(),
+            Some(Err(e)) => {
+                self.message = Some(e);
+                self.fail();
+                vec![String::new()]
+            }
+        };
+        self.playback = Some(runs);
+    }
+
+    /// The text of a region as it is kept in a register. Tabs and wide characters partly in a
+    /// block are kept as spaces.
+    fn region_register(&self, region: Region) -> Register {
+        match region {
+            Region::Chars { start, end } => Register::new(&self.text.slice(start, end), RegisterKind::Chars),
+            Region::Lines { first, last } => {
+                let start = self.text.get_line_offset(first).unwrap();
+                let end = self.text.get_line_offset(last).unwrap() + self.text.get_line_length(last).unwrap();
+                Register::new(&self.text.slice(start, end), RegisterKind::Lines)
+            }
+            Region::Block { first, last, left, right } => {
+                let lines: Vec<String> = (first..=last)
+                    .map(|line| match self.block_span(line, left, right) {
+                        Some((start, end, before, after)) => {
+                            let offset = self.text.get_line_offset(line).unwrap();
+                            " ".repeat(before) + &self.text.slice(offset + start, offset + end) + &" ".repeat(after)
+                        }
+                        None => String::new(),
+                    })
+                    .collect();
+                let width = match right {
+                    usize::MAX => lines.iter().map(|l| display_width(&l.chars().collect::<Vec<char>>(), self.options.tabstop)).max().unwrap_or(0),
+                    right => right + 1 - left,
+                };
+                Register { lines, kind: RegisterKind::Block(width) }
+            }
+        }
+    }
+
+    /// The contents of a register, with "+ and "* read from the system clipboard if there is a
+    /// paste command. Without one the clipboard is the text last copied from here, but when the
+    /// command fails its error is returned rather than older text.
+    fn read_register(&self, name: char) -> Result<Option<Register>, String> {
+        match name {
+            '+' | '*' if !self.options.clippaste.is_empty() => {
+                clipboard::paste(&self.options.clippaste).map(|text| Some(Register::from_text(&text)))
+            }
+            _ => Ok(self.registers.get(name)),
+        }
+    }
+
+    /// Put the text of a register after the cursor, or before it. Linewise text goes on new lines
+    /// below or above the cursor line, and a block goes in at the cursor column of each line.
+    fn put(&mut self, register: Option<char>, before: bool, follow: bool, count: usize) {
+        let name = register.unwrap_or('"');
+        let reg = match self.read_register(name) {
+            Ok(Some(reg)) => reg,
+            Ok(None) => {
+                self.message = Some(format!("E353: Nothing in register {name}"));
+                self.fail();
+                return;
+            }
+            Err(e) => {
+                self.message = Some(e);
+                self.fail();
+                return;
+            }
+        };
+        let count = count.max(1);
+        if reg.text().len().saturating_mul(count) > MAX_REPEAT_LENGTH {
+            self.message = Some("E1240: Resulting text too long".to_string());
+            self.fail();
+            return;
+        }
+        let line = self.cursor_line();
+        let line_start = self.text.get_line_offset(line).unwrap();
+        let line_end = line_start + self.text.get_line_length(line).unwrap();
+
+        match reg.kind {
+            RegisterKind::Chars => {
+                let text = reg.lines.join("\n").repeat(count);
+                let cursor = self.cursor_index();
+                let idx = if before { cursor } else { (cursor + 1).min(line_end) };
+                let length = text.chars().count();
+                self.apply_edit(Edit::Insert { idx, text });
+
+                // The cursor ends on the last character, or the first if the text has several lines
+                if follow {
+                    self.set_cursor_index(idx + length);
+                } else if reg.lines.len() > 1 {
+                    self.set_cursor_index(idx);
+                } else {
+                    self.set_cursor_index(idx + length - 1);
+                }
+            }
+            RegisterKind::Lines => {
+                let lines = reg.lines.len() * count;
+                let mut text = (reg.lines.join("\n") + "\n").repeat(count);
+                let (idx, first) = if before {
+                    (line_start, line)
+                } else if line + 1 < self.text.lines() {
+                    (self.text.get_line_offset(line + 1).unwrap(), line + 1)
+                } else {
+                    // Below the last line the newline goes before the text
+                    text.pop();
+                    text.insert(0, '\n');
+                    (self.text.len(), line + 1)
+                };
+                self.apply_edit(Edit::Insert { idx, text });
+
+                if follow {
+                    self.set_cursor_position(first + lines, 0);
+                } else {
+                    self.set_cursor_index(first_non_blank(&self.text, first));
+                }
+            }
+            RegisterKind::Block(width) => {
+                let cursor = self.cursor_index();
+                let column = match (before, cursor == line_end) {
+                    (false, false) => self.display_span(cursor).1 + 1,
+                    _ => self.display_span(cursor).0,
+                };
+
+                let mut end = 0;
+                for (i, row) in reg.lines.iter().enumerate() {
+                    if line + i >= self.text.lines() {
+                        self.apply_edit(Edit::Insert { idx: self.text.len(), text: "\n".to_string() });
+                    }
+                    // Short rows are padded to keep the text after the block aligned
+                    let row_width = display_width(&row.chars().collect::<Vec<char>>(), self.options.tabstop);
+                    let mut text = (row.clone() + &" ".repeat(width.saturating_sub(row_width))).repeat(count);
+                    let idx = self.block_insert_index(line + i, Some(column));
+                    let line_end = self.text.get_line_offset(line + i).unwrap() + self.text.get_line_length(line + i).unwrap();
+                    if idx == line_end {
+                        text.truncate(text.trim_end_matches(' ').len());
+                    }
+                    end = idx + text.chars().count();
+                    self.apply_edit(Edit::Insert { idx, text });
+                }
+
+                if follow {
+                    self.set_cursor_index(end);
+                } else {
+                    self.set_cursor_position(line, self.display_to_col(line, column));
+                }
+            }
+        }
+    }
 
-impl TextView {
-    pub fn new(text: &str) -> Self {
-        Self {
-            cursor: Position { row: 0, col: 5 },
-            offset: Position { row: 0, col: 0 },
-            text: PieceTable::new(text),
-            sz: Position { row: 0, col: 0 },
-            view: Vec::<char>::new(),
-            updates: Vec::<bool>::new(),
-            cursor_style: CursorStyle::Block,
+    fn delete_region(&mut self, region: Region) {
+        match region {
+            Region::Chars { start, end } => {
+                self.apply_edit(Edit::Delete { idx: start, text: self.text.slice(start, end) });
+                self.set_cursor_index(start);
+            }
+            Region::Lines { first, last } => {
+                let mut start = self.text.get_line_offset(first).unwrap();
+                let end = self.text.get_line_offset(last + 1).unwrap_or(self.text.len());
+                if last + 1 == self.text.lines() && first > 0 {
+                    // There is no newline after the last line, take the one before the first instead
+                    start -= 1;
+                }
+                self.apply_edit(Edit::Delete { idx: start, text: self.text.slice(start, end) });
+
+                let line = first.min(self.text.lines() - 1);
+                self.set_cursor_index(first_non_blank(&self.text, line));
+            }
+            Region::Block { first, last, left, right } => {
+                // Tabs and wide characters on the edges leave spaces for their columns outside
+                for line in (first..=last).rev() {
+                    if let Some((start, end, before, after)) = self.block_span(line, left, right) {
+                        let idx = self.text.get_line_offset(line).unwrap() + start;
+                        let end = self.text.get_line_offset(line).unwrap() + end;
+                        self.apply_edit(Edit::Delete { idx, text: self.text.slice(idx, end) });
+                        self.apply_edit(Edit::Insert { idx, text: " ".repeat(before + after) });
+                    }
+                }
+                self.set_cursor_position(first, self.display_to_col(first, left));
+            }
         }
     }
 
-    pub fn refresh_text(&mut self) {
-        for i in 0..self.view.len() {
-            self.view[i] = ' ';
+    /// Switch to the insert cursor and put it where the text is to be typed. o and O open a line
+    /// with the indent of the cursor line.
+    fn start_insert(&mut self, at: InsertAt) {
+        self.cursor_style = CursorStyle::Bar;
+        let line = self.cursor_line();
+        let line_start = self.text.get_line_offset(line).unwrap();
+        let line_end = line_start + self.text.get_line_length(line).unwrap();
+
+        match at {
+            InsertAt::Cursor => self.set_cursor_index(self.cursor_index()),
+            InsertAt::After => self.set_cursor_index((self.cursor_index() + 1).min(line_end)),
+            InsertAt::FirstNonBlank => self.set_cursor_index(first_non_blank(&self.text, line)),
+            InsertAt::LineEnd => self.set_cursor_index(line_end),
+            InsertAt::LineBelow | InsertAt::LineAbove => {
+                let indent: String = self.text.get_line(line).unwrap().into_iter().take_while(|c| *c == ' ' || *c == '\t').collect();
+                let width = indent.chars().count();
+                let (idx, text, new_line) = match at {
+                    InsertAt::LineBelow => (line_end, format!("\n{indent}"), line + 1),
+                    _ => (line_start, format!("{indent}\n"), line),
+                };
+                self.apply_edit(Edit::Insert { idx, text });
+                self.set_cursor_position(new_line, width);
+                self.auto_indent = (width > 0).then_some(new_line);
+            }
+            InsertAt::LastInsert => {
+                let idx = self.last_insert.unwrap_or(self.cursor_index()).min(self.text.len());
+                self.set_cursor_index(idx);
+            }
+        }
+    }
+
+    /// Leave insert or replace mode. The cursor moves back onto the last character typed, and
+    /// an indent from o or O that nothing was typed after is removed.
+    fn end_insert(&mut self) {
+        if let Some(line) = self.auto_indent.take().filter(|line| *line == self.cursor_line()) {
+            let start = self.text.get_line_offset(line).unwrap();
+            let chars = self.text.get_line(line).unwrap();
+            if chars.iter().all(|c| *c == ' ' || *c == '\t') && self.cursor_index() == start + chars.len() {
+                self.apply_edit(Edit::Delete { idx: start, text: chars.into_iter().collect() });
+                self.set_cursor_index(start);
+            }
+        }
+
+        let idx = self.cursor_index();
+        self.last_insert = Some(idx);
+        self.cursor_style = CursorStyle::Block;
+        let idx = Motion::Left.apply(&self.motion_context(false), idx, 1).unwrap_or(idx);
+        self.set_cursor_index(idx);
+        self.overwritten.clear();
+        self.finish_block_insert();
+    }
+
+    /// Ctrl-W and Ctrl-U in insert mode, delete from where the motion goes back to, but not past
+    /// the start of the line. At the start of a line the line break before it goes instead.
+    fn delete_before(&mut self, motion: Motion) {
+        let idx = self.cursor_index();
+        let line_start = self.text.get_line_offset(self.cursor_line()).unwrap();
+        if idx == line_start {
+            if idx > 0 {
+                self.apply_edit(Edit::Delete { idx: idx - 1, text: "\n".to_string() });
+                self.set_cursor_index(idx - 1);
+            }
+            return;
+        }
+
+        // Ctrl-U in the indent takes the indent
+        let start = match motion.apply(&self.motion_context(false), idx, 1) {
+            Some(start) if start < idx => start.max(line_start),
+            _ => line_start,
+        };
+        self.apply_edit(Edit::Delete { idx: start, text: self.text.slice(start, idx) });
+        self.set_cursor_index(start);
+    }
+
+    /// Del in insert mode, delete the character under the cursor. At the end of a line the next
+    /// line is joined to it.
+    fn delete_forward(&mut self) {
+        let idx = self.cursor_index();
+        if idx < self.text.len() {
+            self.apply_edit(Edit::Delete { idx, text: self.text.index(idx).to_string() });
+            self.set_cursor_index(idx);
+        }
+    }
+
+    /// Ctrl-A and Ctrl-X, add to the number under or after the cursor and put the cursor on its
+    /// last digit.
+    fn add_to_number(&mut self, delta: i64) {
+        let line = self.cursor_line();
+        let line_start = self.text.get_line_offset(line).unwrap();
+        let chars = self.text.get_line(line).unwrap();
+        let col = self.cursor_index() - line_start;
+        let Some((start, end, new)) = numbers::add_to_number(&chars, col, delta, &self.options.nrformats) else {
+            self.fail();
+            return;
+        };
+
+        let idx = line_start + start;
+        let length = new.chars().count();
+        self.apply_edit(Edit::Delete { idx, text: self.text.slice(idx, line_start + end) });
+        self.apply_edit(Edit::Insert { idx, text: new });
+        self.set_cursor_index(idx + length - 1);
+    }
+
+    /// Ctrl-A and Ctrl-X in visual mode, add to the first number of the selection in each line.
+    /// With `progressive`, as g Ctrl-A, each line with a number gets one more step than the last.
+    fn add_to_numbers(&mut self, region: Region, step: i64, progressive: bool) {
+        let (first, last) = self.region_lines(region);
+        let mut changed = 0;
+        for line in first..=last {
+            let line_start = self.text.get_line_offset(line).unwrap();
+            let chars = self.text.get_line(line).unwrap();
+            let (from, to) = match region {
+                Region::Chars { start, end } => (
+                    start.saturating_sub(line_start).min(chars.len()),
+                    end.saturating_sub(line_start).min(chars.len()),
+                ),
+                Region::Lines { .. } => (0, chars.len()),
+                Region::Block { left, right, .. } => match self.block_span(line, left, right) {
+                    Some((start, end, _, _)) => (start, end),
+                    None => continue,
+                },
+            };
+
+            let delta = if progressive { step.saturating_mul(changed + 1) } else { step };
+            if let Some((start, end, new)) = numbers::add_to_number(&chars[from..to], 0, delta, &self.options.nrformats) {
+                let idx = line_start + from + start;
+                self.apply_edit(Edit::Delete { idx, text: self.text.slice(idx, line_start + from + end) });
+                self.apply_edit(Edit::Insert { idx, text: new });
+                changed += 1;
+            }
+        }
+
+        if changed == 0 {
+            self.fail();
+        }
+        match region {
+            Region::Chars { start, .. } => self.set_cursor_index(start),
+            Region::Block { first, left, .. } => self.set_cursor_position(first, self.display_to_col(first, left)),
+            Region::Lines { first, .. } => self.set_cursor_position(first, 0),
+        }
+    }
+
+    /// ~ without 'tildeop', switch the case of count characters and move past them. The cursor
+    /// stays on the last character of the line.
+    fn switch_case(&mut self, count: usize) {
+        let idx = self.cursor_index();
+        let line = self.cursor_line();
+        let line_end = self.text.get_line_offset(line).unwrap() + self.text.get_line_length(line).unwrap();
+        if idx >= line_end {
+            self.fail();
+            return;
+        }
+
+        let end = idx.saturating_add(count.max(1)).min(line_end);
+        let length = self.text.len();
+        self.change_case(Region::Chars { start: idx, end }, Operator::ToggleCase);
+        // The text grows with each character that becomes several
+        self.set_cursor_index(end + self.text.len() - length);
+    }
+
+    /// Ctrl-T and Ctrl-D in insert mode, change the indent of the cursor line to the next or
+    /// previous multiple of 'shiftwidth'. The cursor stays on the same character.
+    fn shift_line(&mut self, right: bool) {
+        let line = self.cursor_line();
+        let line_start = self.text.get_line_offset(line).unwrap();
+        let col = self.cursor_index() - line_start;
+        let chars = self.text.get_line(line).unwrap();
+        let indent = chars.iter().take_while(|c| **c == ' ' || **c == '\t').count();
+        let width = self.indent_width(&chars[..indent]);
+        let shift = self.options.shiftwidth.max(1);
+        let width = if right {
+            (width / shift + 1) * shift
+        } else {
+            width.saturating_sub(1) / shift * shift
+        };
+
+        self.set_indent(line, width);
+        let new_indent = self.text.get_line(line).unwrap().iter().take_while(|c| **c == ' ' || **c == '\t').count();
+        self.set_cursor_index(line_start + (col.max(indent) - indent) + new_indent);
+    }
+
+    /// Ctrl-R in insert mode, type the text of a register at the cursor. Linewise text ends with
+    /// a line break and the lines of a block are inserted as lines.
+    fn insert_register(&mut self, name: char) {
+        let reg = match self.read_register(name) {
+            Ok(Some(reg)) => reg,
+            Ok(None) => {
+                self.fail();
+                return;
+            }
+            Err(e) => {
+                self.message = Some(e);
+                self.fail();
+                return;
+            }
+        };
+        let text = reg.text();
+        let idx = self.cursor_index();
+        let length = text.chars().count();
+        self.apply_edit(Edit::Insert { idx, text });
+        self.set_cursor_index(idx + length);
+    }
+
+    /// Type over the character under the cursor, or add to the end of the line. A line break is
+    /// always added. With `screen`, as in gR, a tab is kept until the text reaches its last column.
+    fn overwrite(&mut self, c: char, screen: bool) {
+        let idx = self.cursor_index();
+        let line = self.cursor_line();
+        let line_end = self.text.get_line_offset(line).unwrap() + self.text.get_line_length(line).unwrap();
+        let old = (idx < line_end && c != '\n').then(|| self.text.index(idx)).filter(|old| {
+            let (start, end) = self.display_span(idx);
+            !(screen && *old == '\t' && end > start)
+        });
+
+        if let Some(old) = old {
+            self.apply_edit(Edit::Delete { idx, text: old.to_string() });
+        }
+        self.apply_edit(Edit::Insert { idx, text: c.to_string() });
+        self.overwritten.push(old);
+        if c == '\n' {
+            self.set_cursor_position(line + 1, 0);
+        } else {
+            self.set_cursor_index(idx + 1);
+        }
+    }
+
+    /// Undo the last character typed in replace mode. Before where replacing started the cursor
+    /// only moves left.
+    fn restore(&mut self) {
+        let idx = self.cursor_index();
+        match self.overwritten.pop() {
+            Some(old) if idx > 0 => {
+                self.apply_edit(Edit::Delete { idx: idx - 1, text: self.text.index(idx - 1).to_string() });
+                if let Some(old) = old {
+                    self.apply_edit(Edit::Insert { idx: idx - 1, text: old.to_string() });
+                }
+                self.set_cursor_index(idx - 1);
+            }
+            _ => {
+                if let Some(idx) = Motion::Left.apply(&self.motion_context(false), idx, 1) {
+                    self.set_cursor_index(idx);
+                }
+            }
+        }
+    }
+
+    /// Replace count characters from the cursor with c, ending on the last of them. A line break
+    /// replaces them all at once. Nothing changes if the line is too short.
+    fn replace_chars(&mut self, c: char, count: usize) {
+        let idx = self.cursor_index();
+        let count = count.max(1);
+        let line = self.cursor_line();
+        let line_end = self.text.get_line_offset(line).unwrap() + self.text.get_line_length(line).unwrap();
+        if idx.saturating_add(count) > line_end {
+            self.fail();
+            return;
+        }
+
+        self.apply_edit(Edit::Delete { idx, text: self.text.slice(idx, idx + count) });
+        if c == '\n' {
+            self.apply_edit(Edit::Insert { idx, text: c.to_string() });
+            self.set_cursor_position(line + 1, 0);
+        } else {
+            self.apply_edit(Edit::Insert { idx, text: c.to_string().repeat(count) });
+            self.set_cursor_index(idx + count - 1);
+        }
+    }
+
+    fn replace_region(&mut self, region: Region, c: char) {
+        match region {
+            Region::Block { first, last, left, right } => {
+                for line in (first..=last).rev() {
+                    if let Some((start, end, before, after)) = self.block_span(line, left, right) {
+                        let idx = self.text.get_line_offset(line).unwrap() + start;
+                        let end = self.text.get_line_offset(line).unwrap() + end;
+                        let old = self.text.slice(idx, end);
+                        let (vcol, _) = self.display_span(idx);
+                        let width = old.chars().fold(vcol, |column, c| column + char_width(c, column, self.options.tabstop)) - vcol;
+                        let new = " ".repeat(before) + &c.to_string().repeat(width - before - after) + &" ".repeat(after);
+                        self.apply_edit(Edit::Delete { idx, text: old });
+                        self.apply_edit(Edit::Insert { idx, text: new });
+                    }
+                }
+                self.set_cursor_position(first, self.display_to_col(first, left));
+            }
+            _ => {
+                for (start, end) in self.region_spans(region).into_iter().rev() {
+                    let old = self.text.slice(start, end);
+                    let new: String = old.chars().map(|o| if o == '\n' { o } else { c }).collect();
+                    self.apply_edit(Edit::Delete { idx: start, text: old });
+                    self.apply_edit(Edit::Insert { idx: start, text: new });
+                }
+                match region {
+                    Region::Lines { first, .. } => self.set_cursor_position(first, 0),
+                    Region::Chars { start, .. } => self.set_cursor_index(start),
+                    Region::Block { .. } => (),
+                }
+            }
+        }
+    }
+
+    fn change_region(&mut self, region: Region) {
+        self.cursor_style = CursorStyle::Bar;
+        match region {
+            Region::Block { first, last, left, .. } => {
+                let lines = self.block_lines(first, last, left);
+                self.delete_region(region);
+                self.start_block_insert(first, lines, Some(left), None);
+            }
+            Region::Lines { first, last } => {
+                // Keep the indent of the first line and one empty line to insert into
+                let start = first_non_blank(&self.text, first);
+                let end = self.text.get_line_offset(last).unwrap() + self.text.get_line_length(last).unwrap();
+                if start < end {
+                    self.apply_edit(Edit::Delete { idx: start, text: self.text.slice(start, end) });
+                }
+                self.set_cursor_index(start);
+            }
+            _ => self.delete_region(region),
+        }
+    }
+
+    fn yank_region(&mut self, region: Region) {
+        match region {
+            Region::Chars { start, .. } => self.set_cursor_index(start),
+            Region::Lines { first, last } => {
+                if first < self.cursor_line() {
+                    let col = (self.offset.col + self.cursor.col - 5) as usize;
+                    self.set_cursor_position(first, col);
+                }
+                if last - first + 1 > 2 {
+                    self.message = Some(format!("{} lines yanked", last - first + 1));
+                }
+            }
+            Region::Block { first, last, left, .. } => {
+                self.set_cursor_position(first, self.display_to_col(first, left));
+                if last - first + 1 > 2 {
+                    self.message = Some(format!("block of {} lines yanked", last - first + 1));
+                }
+            }
+        }
+    }
+
+    /// Display width of leading whitespace.
+    fn indent_width(&self, indent: &[char]) -> usize {
+        indent.iter().fold(0, |width, c| match c {
+            '\t' => (width / self.options.tabstop + 1) * self.options.tabstop,
+            _ => width + 1,
+        })
+    }
+
+    /// Replace the leading whitespace of a line with an indent of the given display width.
+    fn set_indent(&mut self, line: usize, width: usize) {
+        let start = self.text.get_line_offset(line).unwrap();
+        let old: String = self.text.get_line(line).unwrap().into_iter().take_while(|c| *c == ' ' || *c == '\t').collect();
+        let new = if self.options.expandtab {
+            " ".repeat(width)
+        } else {
+            "\t".repeat(width / self.options.tabstop) + &" ".repeat(width % self.options.tabstop)
+        };
+
+        if old != new {
+            if !old.is_empty() {
+                self.apply_edit(Edit::Delete { idx: start, text: old });
+            }
+            if !new.is_empty() {
+                self.apply_edit(Edit::Insert { idx: start, text: new });
+            }
+        }
+    }
+
+    fn shift_lines(&mut self, region: Region, right: bool, times: usize) {
+        let (first, last) = self.region_lines(region);
+        let shift = self.options.shiftwidth.saturating_mul(times);
+        if right && shift > MAX_REPEAT_LENGTH {
+            self.message = Some("E1240: Resulting text too long".to_string());
+            self.fail();
+            return;
+        }
+        for line in first..=last {
+            let chars = self.text.get_line(line).unwrap();
+            if chars.is_empty() {
+                continue;
+            }
+
+            let indent = chars.iter().take_while(|c| **c == ' ' || **c == '\t').count();
+            let width = self.indent_width(&chars[..indent]);
+            if right {
+                self.set_indent(line, width + shift);
+            } else {
+                self.set_indent(line, width.saturating_sub(shift));
+            }
         }
 
+        self.set_cursor_index(first_non_blank(&self.text, first));
+        if last - first + 1 > 2 {
+            let op = if right { '>' } else { '<' };
+            self.message = Some(format!("{} lines {op}ed {times} time{}", last - first + 1, if times == 1 { "" } else { "s" }));
+        }
+    }
+
+    /// Reindent lines by bracket depth, starting from the indent of the line above.
+    fn indent_lines(&mut self, region: Region) {
+        let (first, last) = self.region_lines(region);
+        let sw = self.options.shiftwidth as isize;
+        let balance = |chars: &[char]| -> isize {
+            let opens = chars.iter().filter(|c| "([{".contains(**c)).count() as isize;
+            let closes = chars.iter().filter(|c| ")]}".contains(**c)).count() as isize;
+            opens - closes
+        };
+        let leading_closers = |chars: &[char]| -> isize {
+            chars.iter().skip_while(|c| c.is_whitespace()).take_while(|c| ")]}".contains(**c)).count() as isize
+        };
+
+        let mut depth: isize = 0;
+        if let Some(prev) = (0..first).rev().find(|l| self.text.get_line(*l).unwrap().iter().any(|c| !c.is_whitespace())) {
+            let chars = self.text.get_line(prev).unwrap();
+            let indent = chars.iter().take_while(|c| c.is_whitespace()).count();
+            depth = self.indent_width(&chars[..indent]) as isize + sw * (balance(&chars) + leading_closers(&chars));
+        }
+
+        for line in first..=last {
+            let chars = self.text.get_line(line).unwrap();
+            if chars.iter().all(|c| c.is_whitespace()) {
+                self.set_indent(line, 0);
+                continue;
+            }
+
+            let closers = leading_closers(&chars);
+            let width = (depth - sw * closers).max(0);
+            self.set_indent(line, width as usize);
+            depth = (width + sw * (balance(&chars) + closers)).max(0);
+        }
+
+        self.set_cursor_index(first_non_blank(&self.text, first));
+        if last - first + 1 > 2 {
+            self.message = Some(format!("{} lines indented", last - first + 1));
+        }
+    }
+
+    /// Change the case of a region, or rot13 it. A character can change into several, as ß
+    /// becomes SS.
+    fn change_case(&mut self, region: Region, op: Operator) {
+        for (start, end) in self.region_spans(region).into_iter().rev() {
+            let old = self.text.slice(start, end);
+            let new: String = old
+                .chars()
+                .flat_map(|c| {
+                    let upper = match op {
+                        Operator::Rot13 => return vec![rot13(c)],
+                        Operator::Uppercase => true,
+                        Operator::Lowercase => false,
+                        _ => c.is_lowercase(),
+                    };
+                    if upper {
+                        c.to_uppercase().collect::<Vec<char>>()
+                    } else {
+                        c.to_lowercase().collect::<Vec<char>>()
+                    }
+                })
+                .collect();
+
+            if new != old {
+                self.apply_edit(Edit::Delete { idx: start, text: old });
+                self.apply_edit(Edit::Insert { idx: start, text: new });
+            }
+        }
+
+        match region {
+            Region::Chars { start, .. } => self.set_cursor_index(start),
+            Region::Block { first, left, .. } => self.set_cursor_position(first, self.display_to_col(first, left)),
+            Region::Lines { .. } => self.refresh_text(),
+        }
+    }
+
+    pub fn refresh_text(&mut self) {
+        let width = self.sz.col as usize;
+        let mut row = vec![' '; width];
         for r in 0..self.sz.row {
+            row.fill(' ');
             let ln = self.offset.row + r + 1;
             if ln < 1000 {
-                self.view[(r * self.sz.col) as usize] = ' ';
+                row[0] = ' ';
             } else {
-                self.view[(r * self.sz.col) as usize] =
-                    char::from_digit(((ln / 1000) % 10) as u32, 10).unwrap();
+                row[0] = char::from_digit(((ln / 1000) % 10) as u32, 10).unwrap();
             }
 
             if ln < 100 {
-                self.view[(r * self.sz.col + 1) as usize] = ' ';
+                row[1] = ' ';
             } else {
-                self.view[(r * self.sz.col + 1) as usize] =
-                    char::from_digit(((ln / 100) % 10) as u32, 10).unwrap();
+                row[1] = char::from_digit(((ln / 100) % 10) as u32, 10).unwrap();
             }
 
             if ln < 10 {
-                self.view[(r * self.sz.col + 2) as usize] = ' ';
+                row[2] = ' ';
             } else {
-                self.view[(r * self.sz.col + 2) as usize] =
-                    char::from_digit(((ln / 10) % 10) as u32, 10).unwrap();
+                row[2] = char::from_digit(((ln / 10) % 10) as u32, 10).unwrap();
             }
 
-            self.view[(r * self.sz.col + 3) as usize] =
-                char::from_digit((ln % 10) as u32, 10).unwrap();
-            self.view[(r * self.sz.col + 4) as usize] = ' ';
+            row[3] = char::from_digit((ln % 10) as u32, 10).unwrap();
+            row[4] = ' ';
 
             if let Some(line) = self.text.get_line((self.offset.row + r) as usize) {
                 for c in 5..self.sz.col {
                     if ((self.offset.col + c - 5) as usize) < line.len() {
-                        self.view[(r * self.sz.col + c) as usize] = line[(self.offset.col + c - 5) as usize];
+                        row[c as usize] = line[(self.offset.col + c - 5) as usize];
                     } else {
                         break;
                     }
                 }
             }
+
+            // Only the cells that change are marked for drawing, and only their rows searched again
+            let start = r as usize * width;
+            for (c, &ch) in row.iter().enumerate() {
+                if self.view[start + c] != ch {
+                    self.view[start + c] = ch;
+                    self.updates[start + c] = true;
+                }
+            }
         }
 
-        for i in 0..self.updates.len() {
-            self.updates[i] = true;
+        let previous = std::mem::take(&mut self.highlights);
+        self.refresh_highlights();
+        for (i, update) in self.updates.iter_mut().enumerate() {
+            let before = previous.get(i).copied().unwrap_or(Highlight::Normal);
+            *update |= before != self.highlights.get(i).copied().unwrap_or(Highlight::Normal);
         }
     }
 
-    pub fn process_command(&mut self, cmd: TextCommand) {
-        match cmd {
-            TextCommand::CursorUp(y) => {
-                if y <= self.cursor.row {
-                    self.cursor.row -= y;
-                } else {
-                    if (y - self.cursor.row) <= self.offset.row {
-                        self.offset.row -= y - self.cursor.row;
-                    } else {
-                        self.offset.row = 0;
-                    }
-                    self.cursor.row = 0;
-                    self.refresh_text();
-                }
-                
-                if self.cursor.col >= 5 + self.text.get_line_length(self.cursor.row as usize).unwrap() as u16 {
-                    if self.text.get_line_length((self.cursor.row + self.offset.row) as usize).unwrap() > 0 {
-                        self.cursor.col = 4 + self.text.get_line_length((self.cursor.row + self.offset.row) as usize).unwrap() as u16;
-                    } else {
-                        self.cursor.col = 5;
-                    }
-                }
+    /// Find the matches of the last search on the rows of the screen, for 'hlsearch'. A row is
+    /// searched again when any of its cells or those of the row below it are marked in `updates`,
+    /// as a match can go on into the next line, or when it shows another line or its line changed
+    /// length. Rows left alone keep their matches, so typing doesn't search the whole screen.
+    fn refresh_search_rows(&mut self) {
+        let Some(search) = self.last_search.as_ref().filter(|_| self.options.hlsearch && !self.no_highlight) else {
+            self.search_rows.clear();
+            return;
+        };
+
+        let width = self.sz.col as usize;
+        let dirty: Vec<bool> = self.updates.chunks(width.max(1)).map(|row| row.contains(&true)).collect();
+        let haystack = Haystack::new(&self.text);
+        self.search_rows.resize(self.sz.row as usize, None);
+        for r in 0..self.search_rows.len() {
+            let line = self.offset.row as usize + r;
+            let (Some(start), Some(len)) = (self.text.get_line_offset(line), self.text.get_line_length(line)) else {
+                self.search_rows[r] = None;
+                continue;
+            };
+            let same = matches!(&self.search_rows[r], Some(row) if row.line == line && row.length == len);
+            if same && !dirty[r] && !dirty.get(r + 1).copied().unwrap_or(false) {
+                continue;
             }
-            TextCommand::CursorDown(y) => {
-                if ((self.cursor.row + self.offset.row + 1) as usize) < self.text.lines() {
-                    if self.cursor.row + y < self.sz.row {
-                        self.cursor.row += y;
-                    } else {
-                        self.offset.row += 1;
-                        if ((self.offset.row + self.cursor.row + 1) as usize) >= self.text.lines() {
-                            self.offset.row = (self.text.lines() - (self.sz.row as usize) - 1) as u16;
-                        }
-                        self.cursor.row = self.sz.row - 1;
-                        self.refresh_text();
-                    }
-                    
-                    if self.cursor.col >= 5 + self.text.get_line_length((self.cursor.row + self.offset.row) as usize).unwrap() as u16 {
-                        if self.text.get_line_length((self.cursor.row + self.offset.row) as usize).unwrap() > 0 {
-                            self.cursor.col = 4 + self.text.get_line_length((self.cursor.row + self.offset.row) as usize).unwrap() as u16;
-                        } else {
-                            self.cursor.col = 5;
-                        }
+            let matches = search.pattern.matches(&haystack, start, start + len).map(|(s, e)| (s - start, e - start));
+            self.search_rows[r] = Some(SearchRow { line, length: len, matches: matches.collect() });
+        }
+    }
+
+    /// Mark the cells of the visual selection and the matches of the last search. The end of a
+    /// selected line is shown as one more cell, and an empty selected line as one cell, as is a
+    /// match of no characters.
+    fn refresh_highlights(&mut self) {
+        self.refresh_search_rows();
+        let selection = self.selection();
+        if selection.is_none() && self.search_rows.iter().flatten().all(|row| row.matches.is_empty()) {
+            return;
+        }
+
+        self.highlights = vec![Highlight::Normal; self.view.len()];
+        for (r, row) in self.search_rows.iter().enumerate().filter_map(|(r, row)| Some((r, row.as_ref()?))) {
+            for &(s, e) in &row.matches {
+                for col in s..e.max(s + 1).min(row.length + 1) {
+                    if let Some(c) = (col + 5).checked_sub(self.offset.col as usize).filter(|c| (5..self.sz.col as usize).contains(c)) {
+                        self.highlights[r * self.sz.col as usize + c] = Highlight::Search;
                     }
                 }
             }
-            TextCommand::CursorLeft(x) => {
-                if x + 5 <= self.cursor.col {
-                    self.cursor.col -= x;
-                } else {
-                    self.cursor.col = 5;
-                }
-            }
-            TextCommand::CursorRight(x) => {
-                self.cursor.col += x;
-                if self.cursor.col >= self.sz.col {
-                    self.offset.col += self.cursor.col - self.sz.col + 1;
-                    self.cursor.col = self.sz.col - 1;
-                }
+        }
 
-                let line_length = self.text.get_line_length((self.cursor.row + self.offset.row) as usize).unwrap() as u16;
-                if self.cursor_style == CursorStyle::Block && (self.offset.col + self.cursor.col - 5) >= line_length {
-                    if self.cursor.col + 1 == self.sz.col {
-                        self.offset.col = line_length - self.cursor.col - 1;
-                    } else {
-                        self.cursor.col = 5 + line_length - 1;
-                    }
-                } else if self.cursor_style == CursorStyle::Bar && (self.offset.col + self.cursor.col - 5) > line_length {
-                    if self.cursor.col + 1 == self.sz.col {
-                        self.offset.col = line_length - self.cursor.col;
-                    } else {
-                        self.cursor.col = 5 + line_length;
-                    }
+        let Some(selection) = selection else {
+            return;
+        };
+        for r in 0..self.sz.row as usize {
+            let line = self.offset.row as usize + r;
+            let (Some(start), Some(len)) = (self.text.get_line_offset(line), self.text.get_line_length(line)) else {
+                break;
+            };
+            let block = match selection {
+                Region::Block { first, last, left, right } if first <= line && line <= last => self.block_span(line, left, right),
+                _ => None,
+            };
+            for c in 5..self.sz.col as usize {
+                let col = self.offset.col as usize + c - 5;
+                let selected = match selection {
+                    Region::Chars { start: s, end: e } => col <= len && s <= start + col && start + col < e,
+                    Region::Lines { first, last } => first <= line && line <= last && col < len.max(1),
+                    Region::Block { .. } => block.is_some_and(|(s, e, _, _)| s <= col && col < e),
+                };
+                if selected {
+                    self.highlights[r * self.sz.col as usize + c] = Highlight::Visual;
                 }
             }
-            TextCommand::JumpTop(_reps) => {
-                self.cursor.row = 0;
-                self.offset.row = 0;
-                
-                if self.cursor.col >= 5 + self.text.get_line_length((self.cursor.row + self.offset.row) as usize).unwrap() as u16 {
-                    if self.text.get_line_length((self.cursor.row + self.offset.row) as usize).unwrap() > 0 {
-                        self.cursor.col = 4 + self.text.get_line_length((self.cursor.row + self.offset.row) as usize).unwrap() as u16;
-                    } else {
-                        self.cursor.col = 5;
-                    }
-                }
-                self.refresh_text();
-            },
-            TextCommand::JumpBottom(_reps) => {
-                if self.text.lines() > (self.sz.row as usize) {
-                    self.offset.row = (self.text.lines() - (self.sz.row as usize) - 1) as u16;
-                    self.cursor.row = self.sz.row - 1;
-                } else {
-                    self.cursor.row = self.text.lines() as u16;
-                }
-                
-                if self.cursor.col >= 5 + self.text.get_line_length((self.cursor.row + self.offset.row) as usize).unwrap() as u16 {
-                    if self.text.get_line_length((self.cursor.row + self.offset.row) as usize).unwrap() > 0 {
-                        self.cursor.col = 4 + self.text.get_line_length((self.cursor.row + self.offset.row) as usize).unwrap() as u16;
-                    } else {
-                        self.cursor.col = 5;
-                    }
-                }
+        }
+    }
+
+    pub fn process_command(&mut self, cmd: TextCommand) {
+        // After a failure the rest of a macro is skipped, only following the changes of mode
+        if self.aborted
+            && !matches!(
+                cmd,
+                TextCommand::Play(..)
+                    | TextCommand::Normal(..)
+                    | TextCommand::PlaybackEnd(_)
+                    | TextCommand::SetCursorStyle(_)
+                    | TextCommand::Visual(_)
+                    | TextCommand::UndoBreak
+                    | TextCommand::InsertEnd
+                    | TextCommand::SearchPreview(_)
+            )
+        {
+            return;
+        }
+        // A register only applies to the command right after it
+        let register = self.register.take();
+        match cmd {
+            TextCommand::CursorUp(y) => self.move_cursor(Motion::Up, y as usize),
+            TextCommand::CursorDown(y) => self.move_cursor(Motion::Down, y as usize),
+            TextCommand::CursorLeft(x) => self.move_cursor(Motion::Left, x as usize),
+            TextCommand::CursorRight(x) => self.move_cursor(Motion::Right, x as usize),
+            TextCommand::Move(motion, n) => self.move_cursor(motion, n),
+            TextCommand::Search(typed, forward) => self.set_search(&typed, forward),
+            TextCommand::SearchPreview(typed) => self.preview_search(typed),
+            TextCommand::NoHighlight => {
+                self.no_highlight = true;
                 self.refresh_text();
-                
-            },
+            }
+            TextCommand::ScrollPage(forward, n) => self.scroll_page(forward, n),
+            TextCommand::Operate(op, target, n) => self.operate(op, target, n, register),
+            TextCommand::JumpTop(line) => self.move_cursor(Motion::GotoLine(line.max(1) as usize), 1),
+            TextCommand::JumpBottom(0) => self.move_cursor(Motion::LastLine, 1),
+            TextCommand::JumpBottom(line) => self.move_cursor(Motion::GotoLine(line as usize), 1),
             TextCommand::SetCursorStyle(sty) => {
                 self.cursor_style = sty;
             }
             TextCommand::Insert(c) => {
                 if let Some(line_offset) = self.text.get_line_offset((self.offset.row + self.cursor.row) as usize) {
                     let idx = line_offset + (self.offset.col + self.cursor.col - 5) as usize;
-                    self.text.insert(idx, c);
+                    self.apply_edit(Edit::Insert { idx, text: c.to_string() });
 
                     if c == '\n' {
                         self.cursor.row += 1;
@@ -216,12 +2114,18 @@ impl TextView {
                     
                     match self.cursor_style {
                         CursorStyle::Underline => panic!("Recieved TextCommand::Delete while cursor style is underscored"),
-                        CursorStyle::Block => self.text.delete(idx),
+                        CursorStyle::Block => {
+                            let line_length = self.text.get_line_length((self.offset.row + self.cursor.row) as usize).unwrap();
+                            if idx < line_offset + line_length {
+                                self.apply_edit(Edit::Delete { idx, text: self.text.index(idx).to_string() });
+                                self.set_cursor_index(idx);
+                            }
+                        },
                         CursorStyle::Bar => {
                             if idx > 0 {
                                 if self.cursor.col == 5 && self.offset.col == 0 {
                                     let prev_line_length = self.text.get_line_length((self.cursor.row + self.offset.row - 1) as usize).unwrap() as u16;
-                                    self.text.delete(idx - 1);
+                                    self.apply_edit(Edit::Delete { idx: idx - 1, text: self.text.index(idx - 1).to_string() });
 
                                     if self.cursor.row == 0 {
                                         self.offset.row -= 1;
@@ -237,7 +2141,7 @@ impl TextView {
                                         self.offset.col = prev_line_length - self.sz.col + 4;
                                     }
                                 } else {
-                                    self.text.delete(idx - 1);
+                                    self.apply_edit(Edit::Delete { idx: idx - 1, text: self.text.index(idx - 1).to_string() });
                                     self.cursor.col -= 1;
                                     if self.cursor.col < 5 {
                                         self.cursor.col = 5;
@@ -251,7 +2155,143 @@ impl TextView {
                 }
 
             }
+            TextCommand::Undo(n) => {
+                for _ in 0..n {
+                    match self.history.undo(&mut self.text) {
+                        Some(idx) => self.set_cursor_index(idx),
+                        None => break,
+                    }
+                }
+                self.line_undo = None;
+            }
+            TextCommand::Redo(n) => {
+                for _ in 0..n {
+                    match self.history.redo(&mut self.text) {
+                        Some(idx) => self.set_cursor_index(idx),
+                        None => break,
+                    }
+                }
+                self.line_undo = None;
+            }
+            TextCommand::UndoLine => self.undo_line(),
+            TextCommand::UndoBreak => self.history.commit(),
+            TextCommand::UndoJump(seq) => {
+                if let Some(idx) = self.history.jump_to(&mut self.text, seq) {
+                    self.set_cursor_index(idx);
+                }
+                self.line_undo = None;
+            }
+            TextCommand::Earlier(distance) => {
+                if let Some(idx) = self.history.earlier(&mut self.text, distance) {
+                    self.set_cursor_index(idx);
+                }
+                self.line_undo = None;
+            }
+            TextCommand::Later(distance) => {
+                if let Some(idx) = self.history.later(&mut self.text, distance) {
+                    self.set_cursor_index(idx);
+                }
+                self.line_undo = None;
+            }
+            TextCommand::Visual(Some(kind)) => {
+                let anchor = match self.visual {
+                    Some((anchor, _)) => anchor,
+                    None => {
+                        self.visual_eol = false;
+                        self.cursor_index()
+                    }
+                };
+                self.visual = Some((anchor, kind));
+                self.refresh_text();
+            }
+            TextCommand::Visual(None) => {
+                if let Some((anchor, kind)) = self.visual.take() {
+                    self.last_visual = Some((anchor, self.cursor_index(), kind, self.visual_eol));
+                }
+                self.refresh_text();
+            }
+            TextCommand::VisualSwap(corner) => match self.visual {
+                Some((anchor, MotionForce::Blockwise)) if corner => {
+                    // Swap the columns of the two corners, keeping their lines
+                    let anchor_line = self.text.get_line_number(anchor);
+                    let cursor_line = self.cursor_line();
+                    let (anchor_column, _) = self.display_span(anchor);
+                    let (cursor_column, _) = self.display_span(self.cursor_index());
+                    let anchor = self.text.get_line_offset(anchor_line).unwrap() + self.display_to_col(anchor_line, cursor_column);
+                    let cursor = self.text.get_line_offset(cursor_line).unwrap() + self.display_to_col(cursor_line, anchor_column);
+                    self.visual = Some((anchor, MotionForce::Blockwise));
+                    self.set_cursor_index(cursor);
+                }
+                Some((anchor, kind)) => {
+                    // The end of the line selected after $ stays selected as the anchor
+                    let cursor = match kind {
+                        MotionForce::Charwise if self.visual_eol => self.line_end_index(self.cursor_line()),
+                        _ => self.cursor_index(),
+                    };
+                    self.visual = Some((cursor, kind));
+                    self.visual_eol = false;
+                    self.set_cursor_index(anchor);
+                }
+                None => (),
+            },
+            TextCommand::VisualRestore => {
+                if let Some((anchor, cursor, kind, eol)) = self.last_visual {
+                    let len = self.text.len();
+                    self.visual = Some((anchor.min(len), kind));
+                    self.visual_eol = eol;
+                    self.set_cursor_index(cursor.min(len));
+                }
+            }
+            TextCommand::VisualSelect(object, n) => self.select_object(object, n),
+            TextCommand::VisualInsert(append) => self.visual_insert(append),
+            TextCommand::StartInsert(at) => self.start_insert(at),
+            TextCommand::InsertEnd => self.end_insert(),
+            TextCommand::DeleteBefore(motion) => self.delete_before(motion),
+            TextCommand::DeleteForward => self.delete_forward(),
+            TextCommand::SwitchCase(n) => self.switch_case(n),
+            TextCommand::AddNumber { subtract, count } => {
+                let count = i64::try_from(count.max(1)).unwrap_or(i64::MAX);
+                self.add_to_number(if subtract { -count } else { count });
+            }
+            TextCommand::ShiftLine(right) => self.shift_line(right),
+            TextCommand::InsertRegister(name) => self.insert_register(name),
+            TextCommand::Overwrite(c, screen) => self.overwrite(c, screen),
+            TextCommand::Restore => self.restore(),
+            TextCommand::ReplaceChars(c, count) => self.replace_chars(c, count),
+            TextCommand::UseRegister(name) => self.register = Some(name),
+            TextCommand::Put { before, follow, count } => self.put(register, before, follow, count),
+            TextCommand::ReadOnlyRegister(name, text) => self.registers.set_read_only(name, &text),
+            TextCommand::ListRegisters(names) => self.message = Some(self.registers.list(&names).join("\n")),
+            TextCommand::Goto(address) => match self.resolve_address(address) {
+                Ok(line) => {
+                    let line = line.clamp(0, self.text.lines() as isize - 1) as usize;
+                    self.set_cursor_index(first_non_blank(&self.text, line));
+                }
+                Err(e) => {
+                    self.message = Some(e);
+                    self.fail();
+                }
+            },
+            TextCommand::Record(name, keys) => {
+                if let Err(e) = self.registers.record(name, &keys) {
+                    self.message = Some(e);
+                }
+            }
+            TextCommand::Play(name, count) => self.play(name, count),
+            TextCommand::Normal(range, keys) => self.normal(range, &keys),
+            TextCommand::PlaybackEnd(last) => {
+                self.aborted = false;
+                self.playing = !last;
+            }
+            TextCommand::UndoList => {
+                if self.history.is_empty() {
+                    self.message = Some("Nothing to undo".to_string());
+                } else {
+                    self.message = Some(self.history.undolist().join("\n"));
+                }
+            }
         }
+        self.update_curswant();
     }
 }
 
@@ -266,7 +2306,13 @@ impl View for TextView {
         self.sz = p;
 
         self.view = vec![' '; (self.sz.row * self.sz.col) as usize];
-        self.updates = vec![false; (self.sz.row * self.sz.col) as usize];
+        self.updates = vec![true; (self.sz.row * self.sz.col) as usize];
+        self.search_rows.clear();
+        if self.cursor.row >= self.sz.row || self.cursor.col >= self.sz.col {
+            let line = (self.offset.row + self.cursor.row) as usize;
+            let col = (self.offset.col + self.cursor.col - 5) as usize;
+            self.set_cursor_position(line, col);
+        }
         self.refresh_text();
     }
 
@@ -295,4 +2341,39 @@ impl View for TextView {
     fn get_cursor_style(&self) -> CursorStyle {
         self.cursor_style
     }
+
+    fn get_highlights(&self) -> Vec<Highlight> {
+        self.highlights.clone()
+    }
+}
+
+/// Display width of a character starting at a display column.
+fn char_width(c: char, column: usize, tabstop: usize) -> usize {
+    match c {
+        '\t' => tabstop - column % tabstop,
+        c if is_wide(c) => 2,
+        _ => 1,
+    }
+}
+
+/// Display width of the start of a line.
+fn display_width(chars: &[char], tabstop: usize) -> usize {
+    chars.iter().fold(0, |column, c| column + char_width(*c, column, tabstop))
+}
+
+/// East Asian wide and fullwidth characters, and emoji, which take two columns.
+fn is_wide(c: char) -> bool {
+    matches!(c as u32,
+        0x1100..=0x115F | 0x2E80..=0x303E | 0x3041..=0x33FF | 0x3400..=0x4DBF | 0x4E00..=0x9FFF
+        | 0xA000..=0xA4CF | 0xAC00..=0xD7A3 | 0xF900..=0xFAFF | 0xFE30..=0xFE4F | 0xFF00..=0xFF60
+        | 0xFFE0..=0xFFE6 | 0x1F300..=0x1F64F | 0x1F900..=0x1F9FF | 0x20000..=0x3FFFD)
+}
+
+/// Rotate an ASCII letter 13 places, other characters are left alone.
+fn rot13(c: char) -> char {
+    match c {
+        'a'..='z' => ((c as u8 - b'a' + 13) % 26 + b'a') as char,
+        'A'..='Z' => ((c as u8 - b'A' + 13) % 26 + b'A') as char,
+        _ => c,
+    }
 }
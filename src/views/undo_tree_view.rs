@@ -0,0 +1,147 @@
+use crate::interface::CursorStyle;
+use crate::position::Position;
+use crate::undo::{format_age, UndoTree};
+use crate::views::View;
+
+/// Side panel listing every state in the undo tree, oldest first. Chains of changes are drawn in
+/// a single column and branches are indented below the change they split from.
+pub struct UndoTreeView {
+    rows: Vec<(String, usize)>, // Rendered row and the change it refers to
+    selected: usize,
+    scroll: usize,
+    sz: Position,
+    view: Vec<char>,
+    updates: Vec<bool>,
+}
+
+impl UndoTreeView {
+    pub fn new() -> Self {
+        Self {
+            rows: Vec::<(String, usize)>::new(),
+            selected: 0,
+            scroll: 0,
+            sz: Position { row: 0, col: 0 },
+            view: Vec::<char>::new(),
+            updates: Vec::<bool>::new(),
+        }
+    }
+
+    /// Rebuild the rows from the tree and select the current state.
+    pub fn set_tree(&mut self, tree: &UndoTree) {
+        self.rows.clear();
+
+        // Depth first, with the indentation for the node's own row and for the rows below it
+        let mut stack = vec![(0, String::new(), String::new())];
+        while let Some((node, indent, cont)) = stack.pop() {
+            let marker = if node == tree.current() { '*' } else { ' ' };
+            let mut row = format!("{marker}{indent}{node} {}", format_age(tree.time(node)));
+            if let Some(save) = tree.save(node) {
+                row.push_str(&format!(" [w{save}]"));
+            }
+            self.rows.push((row, node));
+
+            let children = tree.children(node);
+            if children.len() == 1 {
+                stack.push((children[0], cont.clone(), cont));
+            } else {
+                for (i, child) in children.iter().enumerate().rev() {
+                    if i + 1 == children.len() {
+                        stack.push((*child, format!("{cont}└ "), format!("{cont}  ")));
+                    } else {
+                        stack.push((*child, format!("{cont}├ "), format!("{cont}│ ")));
+                    }
+                }
+            }
+        }
+
+        self.selected = self.rows.iter().position(|(_, n)| *n == tree.current()).unwrap_or(0);
+        self.refresh_view();
+    }
+
+    /// Move the selection by delta rows, returning the newly selected change.
+    pub fn move_selection(&mut self, delta: isize) -> usize {
+        if !self.rows.is_empty() {
+            self.selected = self.selected.saturating_add_signed(delta).min(self.rows.len() - 1);
+        }
+        self.refresh_view();
+        self.selected_change()
+    }
+
+    pub fn selected_change(&self) -> usize {
+        self.rows.get(self.selected).map_or(0, |(_, n)| *n)
+    }
+
+    fn refresh_view(&mut self) {
+        let rows = self.sz.row as usize;
+        let cols = self.sz.col as usize;
+        if self.selected < self.scroll {
+            self.scroll = self.selected;
+        } else if rows > 0 && self.selected >= self.scroll + rows {
+            self.scroll = self.selected + 1 - rows;
+        }
+
+        for i in 0..self.view.len() {
+            self.view[i] = ' ';
+        }
+
+        for r in 0..rows {
+            self.view[r * cols] = '│';
+            if let Some((row, _)) = self.rows.get(self.scroll + r) {
+                for (c, ch) in row.chars().enumerate() {
+                    if c + 1 < cols {
+                        self.view[r * cols + c + 1] = ch;
+                    }
+                }
+            }
+        }
+
+        for i in 0..self.updates.len() {
+            self.updates[i] = true;
+        }
+    }
+}
+
+impl Default for UndoTreeView {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl View for UndoTreeView {
+    fn set_size(&mut self, p: Position) {
+        self.sz = p;
+
+        self.view = vec![' '; (self.sz.row * self.sz.col) as usize];
+        self.updates = vec![false; (self.sz.row * self.sz.col) as usize];
+        self.refresh_view();
+    }
+
+    fn get_size(&self) -> Position {
+        self.sz
+    }
+
+    fn get_view(&self) -> Vec<char> {
+        self.view.clone()
+    }
+
+    fn get_updates(&mut self) -> Vec<bool> {
+        let updates = self.updates.clone();
+
+        for i in 0..self.updates.len() {
+            self.updates[i] = false;
+        }
+
+        updates
+    }
+
+    fn get_cursor_pos(&self) -> Position {
+        Position {
+            row: (self.selected - self.scroll) as u16,
+            col: 1,
+        }
+    }
+
+    fn get_cursor_style(&self) -> CursorStyle {
+        CursorStyle::Block
+    }
+}